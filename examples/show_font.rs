@@ -6,7 +6,7 @@ fn main() {
         0 | 1 => "Sphinx of black quartz, judge my vow.",
         2 => &args[1],
         _ => {
-            eprintln!("Usage: show_text \"some ASCII text\" < SomeFont.NFNT");
+            eprintln!("Usage: show_text \"some text\" < SomeFont.NFNT");
             std::process::exit(1)
         }
     };
@@ -26,43 +26,24 @@ fn main() {
         ret
     })
     .unwrap();
-    let bold = plain.make_bold();
-    let italic = plain.make_italic();
-    let bolditalic = bold.make_italic();
-    let underline = plain.make_underline();
-    let underlinebold = bold.make_underline();
-    let underlineitalic = italic.make_underline();
-    let underlinebolditalic = bolditalic.make_underline();
-    let fonts_in_order = &[
-        &plain,
-        &bold,
-        &italic,
-        &bolditalic,
-        &underline,
-        &underlinebold,
-        &underlineitalic,
-        &underlinebolditalic,
-    ];
+    // plain, bold, italic, bold italic, and then all those underlined
+    let fonts_in_order: Vec<Font> = (0..8)
+        .map(|style| plain.derive_style(StyleFlags(style)))
+        .collect();
     let mut bits: Bitmap = Bitmap::new(width, height);
     for (n, font) in fonts_in_order.iter().enumerate() {
         let y = 8
             + (font.get_ascent() + font.get_descent() + font.get_leading())
                 * n as i32
             + font.get_ascent();
-        let fonts = [*font];
+        let fonts = [font];
         bits.draw_text(
             ModeXor(()),
             None,
             16,
             y,
             |n| fonts.get(n).copied(),
-            text.chars().map(|c| {
-                if c > '\u{0080}' {
-                    panic!("Non-ASCII character in argument text!")
-                } else {
-                    TextElement::DrawGlyph(c as u32 as u16)
-                }
-            }),
+            encode_text(MacRoman, text),
         );
     }
     #[cfg(feature = "netpbm")]
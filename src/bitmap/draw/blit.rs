@@ -1,4 +1,4 @@
-use std::cmp::Ordering;
+use std::{cmp::Ordering, iter::repeat};
 
 use super::*;
 
@@ -6,18 +6,67 @@ impl Bitmap {
     /// Blit the given rectangle of pixels from the source into ourselves, with
     /// the top-left corner of the rectangle being placed at the given x and y
     /// coordinates.
-    pub fn blit_bits<Mode: TransferMode>(
+    pub fn blit_bits<'c, Mode: TransferMode>(
         &mut self,
         mode: Mode,
-        clip_rect: Option<Rectangle>,
+        clip: impl Into<Clip<'c>>,
         src: &Bitmap,
         src_rect: Option<Rectangle>,
+        dst_x: i32,
+        dst_y: i32,
+    ) {
+        let clip = self.get_clip(clip.into());
+        for clip_rect in clip.get_rectangles(clip.bounds) {
+            self.blit_bits_in_rect(
+                &mode, clip_rect, src, None, src_rect, dst_x, dst_y,
+            );
+        }
+    }
+    /// Like `blit_bits`, but only the pixels that are set in `mask` are
+    /// affected, like QuickDraw's `CopyMask`. The mask lines up with the
+    /// source: pixel (x, y) of the source is drawn only if pixel (x, y) of
+    /// the mask is set. Pixels past the right or bottom edge of the mask are
+    /// treated as clear.
+    ///
+    /// This takes just as long as `blit_bits`, no matter how the source, the
+    /// mask, and the destination are aligned.
+    #[allow(clippy::too_many_arguments)]
+    pub fn blit_masked<'c, Mode: TransferMode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        src: &Bitmap,
+        mask: &Bitmap,
+        src_rect: Option<Rectangle>,
+        dst_x: i32,
+        dst_y: i32,
+    ) {
+        let clip = self.get_clip(clip.into());
+        for clip_rect in clip.get_rectangles(clip.bounds) {
+            self.blit_bits_in_rect(
+                &mode,
+                clip_rect,
+                src,
+                Some(mask),
+                src_rect,
+                dst_x,
+                dst_y,
+            );
+        }
+    }
+    /// Implements `blit_bits` and `blit_masked` for one rectangle of the
+    /// clip area, which must already be within our bounds.
+    #[allow(clippy::too_many_arguments)]
+    fn blit_bits_in_rect<Mode: TransferMode>(
+        &mut self,
+        mode: Mode,
+        clip_rect: Rectangle,
+        src: &Bitmap,
+        mask: Option<&Bitmap>,
+        src_rect: Option<Rectangle>,
         mut dst_x: i32,
         mut dst_y: i32,
     ) {
-        let clip_rect = clip_rect
-            .map(|x| x.intersection(self.get_bounds()))
-            .unwrap_or(self.get_bounds());
         let mut src_rect = src_rect.unwrap_or(src.get_bounds());
         if dst_x < clip_rect.left {
             src_rect.left -= dst_x - clip_rect.left;
@@ -42,6 +91,9 @@ impl Bitmap {
             return;
         }
         let mut src_rect = src_rect.intersection(src.get_bounds());
+        if let Some(mask) = mask {
+            src_rect = src_rect.intersection(mask.get_bounds());
+        }
         if let Some(overshoot) = src_rect
             .get_width()
             .checked_sub(clip_rect.right as u32 - dst_x as u32)
@@ -50,7 +102,7 @@ impl Bitmap {
         }
         if let Some(overshoot) = src_rect
             .get_height()
-            .checked_sub(clip_rect.right as u32 - dst_y as u32)
+            .checked_sub(clip_rect.bottom as u32 - dst_y as u32)
         {
             src_rect.bottom -= overshoot as i32;
         }
@@ -58,33 +110,27 @@ impl Bitmap {
             return;
         }
         let src_left = src_rect.left as u32;
-        let src_top = src_rect.top as u32;
-        let src_right = src_rect.right as u32;
         let dst_left = dst_x as u32;
         let dst_top = dst_y as u32;
         let dst_right = dst_left + src_rect.get_width();
         let dst_bottom =
             (dst_top + src_rect.get_height()).min(clip_rect.bottom as u32);
-        let (in_start_word, in_stop_word, _, _) =
-            calculate_span_mask(src_left, src_right);
-        let in_word_count = (in_stop_word + 1) - in_start_word;
-        let i = in_start_word + src_top * src.words_per_row;
         let src_bit_align = src_left % BITMAP_WORD_BITS as u32;
         let dst_bit_align = dst_left % BITMAP_WORD_BITS as u32;
+        // The source and the mask line up, so the same shifting works for
+        // both of them.
         match src_bit_align.cmp(&dst_bit_align) {
             Ordering::Less => {
                 // We must shift to the RIGHT
                 let slip = dst_bit_align - src_bit_align;
-                inner_blit(
+                self.blit_rows(
                     mode,
-                    (i..(i + src_rect.get_height() * src.words_per_row))
-                        .step_by(src.words_per_row as usize)
-                        .map(|offset| {
-                            let words = &src.words[offset as usize
-                                ..(offset + in_word_count) as usize];
-                            Biterator::new(words, slip, 0)
-                        }),
-                    self,
+                    src,
+                    mask,
+                    |bitmap| {
+                        get_row_words(bitmap, src_rect)
+                            .map(move |words| Biterator::new(words, slip, 0))
+                    },
                     dst_left,
                     dst_top,
                     dst_right,
@@ -93,17 +139,14 @@ impl Bitmap {
             }
             Ordering::Equal => {
                 // No shifting required
-                inner_blit(
+                self.blit_rows(
                     mode,
-                    (i..(i + src_rect.get_height() * src.words_per_row))
-                        .step_by(src.words_per_row as usize)
-                        .map(|offset| {
-                            src.words[offset as usize
-                                ..(offset + in_word_count) as usize]
-                                .iter()
-                                .copied()
-                        }),
-                    self,
+                    src,
+                    mask,
+                    |bitmap| {
+                        get_row_words(bitmap, src_rect)
+                            .map(|words| words.iter().copied())
+                    },
                     dst_left,
                     dst_top,
                     dst_right,
@@ -113,16 +156,14 @@ impl Bitmap {
             Ordering::Greater => {
                 // We must shift to the LEFT
                 let slip = src_bit_align - dst_bit_align;
-                inner_blit(
+                self.blit_rows(
                     mode,
-                    (i..(i + src_rect.get_height() * src.words_per_row))
-                        .step_by(src.words_per_row as usize)
-                        .map(|offset| {
-                            let words = &src.words[offset as usize
-                                ..(offset + in_word_count) as usize];
-                            Biterator::new(words, 0, slip)
-                        }),
-                    self,
+                    src,
+                    mask,
+                    |bitmap| {
+                        get_row_words(bitmap, src_rect)
+                            .map(move |words| Biterator::new(words, 0, slip))
+                    },
                     dst_left,
                     dst_top,
                     dst_right,
@@ -131,11 +172,72 @@ impl Bitmap {
             }
         }
     }
+    /// Blits the rows made by `get_rows` from the source, through the rows
+    /// made by `get_rows` from the mask (if any).
+    #[allow(clippy::too_many_arguments)]
+    fn blit_rows<'a, Mode, Rows, Row>(
+        &mut self,
+        mode: Mode,
+        src: &'a Bitmap,
+        mask: Option<&'a Bitmap>,
+        get_rows: impl Fn(&'a Bitmap) -> Rows,
+        dst_left: u32,
+        dst_top: u32,
+        dst_right: u32,
+        dst_bottom: u32,
+    ) where
+        Mode: TransferMode,
+        Rows: Iterator<Item = Row>,
+        Row: Iterator<Item = BitmapWord>,
+    {
+        match mask {
+            None => inner_blit(
+                mode,
+                get_rows(src),
+                repeat(repeat(!0)),
+                self,
+                dst_left,
+                dst_top,
+                dst_right,
+                dst_bottom,
+            ),
+            Some(mask) => inner_blit(
+                mode,
+                get_rows(src),
+                get_rows(mask),
+                self,
+                dst_left,
+                dst_top,
+                dst_right,
+                dst_bottom,
+            ),
+        }
+    }
+}
+
+/// Returns the words that make up each row of `src_rect` in the given
+/// bitmap, from the word containing its left edge to the word containing
+/// its right edge.
+fn get_row_words(
+    bitmap: &Bitmap,
+    src_rect: Rectangle,
+) -> impl Iterator<Item = &[BitmapWord]> {
+    let (start_word, stop_word, _, _) =
+        calculate_span_mask(src_rect.left as u32, src_rect.right as u32);
+    let words_per_row = bitmap.words_per_row as usize;
+    (src_rect.top as usize..src_rect.bottom as usize).map(move |y| {
+        let row = y * words_per_row;
+        &bitmap.words[row + start_word as usize..=row + stop_word as usize]
+    })
 }
 
+/// Combines the source rows into the destination. Only the bits that are
+/// set in the corresponding mask words are changed.
+#[allow(clippy::too_many_arguments)]
 fn inner_blit<Mode: TransferMode>(
     mode: Mode,
     mut src_rows: impl Iterator<Item = impl Iterator<Item = BitmapWord>>,
+    mut mask_rows: impl Iterator<Item = impl Iterator<Item = BitmapWord>>,
     dst: &mut Bitmap,
     dst_left: u32,
     dst_top: u32,
@@ -149,13 +251,15 @@ fn inner_blit<Mode: TransferMode>(
         let combined_mask = left_mask & right_mask;
         for y in dst_top..dst_bottom {
             let mut src_row = src_rows.next().unwrap();
-            dst.words[i] = dst.words[i] & !combined_mask
+            let mut mask_row = mask_rows.next().unwrap();
+            let mask = combined_mask & mask_row.next().unwrap();
+            dst.words[i] = dst.words[i] & !mask
                 | (mode.combine(
                     src_row.next().unwrap(),
                     dst.words[i],
                     out_start_word,
                     y,
-                ) & combined_mask);
+                ) & mask);
             i += dst.words_per_row as usize;
         }
     } else {
@@ -163,26 +267,35 @@ fn inner_blit<Mode: TransferMode>(
             (dst.words_per_row - (out_stop_word - out_start_word)) as usize;
         for y in dst_top..dst_bottom {
             let mut src_row = src_rows.next().unwrap();
-            dst.words[i] = dst.words[i] & !left_mask
+            let mut mask_row = mask_rows.next().unwrap();
+            let mask = left_mask & mask_row.next().unwrap();
+            dst.words[i] = dst.words[i] & !mask
                 | (mode.combine(
                     src_row.next().unwrap(),
                     dst.words[i],
                     out_start_word,
                     y,
-                ) & left_mask);
+                ) & mask);
             i += 1;
             for x in out_start_word + 1..out_stop_word {
-                dst.words[i] =
-                    mode.combine(src_row.next().unwrap(), dst.words[i], x, y);
+                let mask = mask_row.next().unwrap();
+                dst.words[i] = dst.words[i] & !mask
+                    | (mode.combine(
+                        src_row.next().unwrap(),
+                        dst.words[i],
+                        x,
+                        y,
+                    ) & mask);
                 i += 1;
             }
-            dst.words[i] = dst.words[i] & !right_mask
+            let mask = right_mask & mask_row.next().unwrap();
+            dst.words[i] = dst.words[i] & !mask
                 | (mode.combine(
                     src_row.next().unwrap(),
                     dst.words[i],
                     out_stop_word,
                     y,
-                ) & right_mask);
+                ) & mask);
             i += out_stride;
         }
     }
@@ -290,4 +403,68 @@ mod test {
             &[0x02345678, 0x9ABCDEF0],
         );
     }
+    #[test]
+    fn wide_clip() {
+        // a clip rectangle that's wider than it is tall, whose right edge is
+        // only just past the destination Y
+        let src = Bitmap::from_bytes(8, 8, &[0xFF; 8]);
+        let mut bitmap = Bitmap::new(16, 16);
+        let clip = Rectangle {
+            left: 0,
+            top: 10,
+            right: 12,
+            bottom: 14,
+        };
+        bitmap.blit_bits(ModeOr(()), Some(clip), &src, None, 0, 11);
+        let mut expected = [0; 32];
+        for row in 11..14 {
+            expected[row * 2] = 0xFF;
+        }
+        assert_eq!(bitmap.to_bytes(), expected);
+    }
+    #[test]
+    fn masked_blit() {
+        let pseudorandom = |seed: u32, len: usize| {
+            (0..len as u32)
+                .map(|x| {
+                    (x.wrapping_mul(2654435761) ^ seed).rotate_left(x) as u8
+                })
+                .collect::<Vec<u8>>()
+        };
+        let src = Bitmap::from_bytes(72, 3, &pseudorandom(1, 27));
+        // narrower than the source
+        let mask = Bitmap::from_bytes(60, 3, &pseudorandom(2, 24));
+        let background = Bitmap::from_bytes(96, 4, &pseudorandom(3, 48));
+        let src_rect = Rectangle {
+            left: 5,
+            top: 1,
+            right: 70,
+            bottom: 3,
+        };
+        for dst_x in -8..40 {
+            let mut bitmap = background.clone();
+            bitmap.blit_masked(
+                ModeXor(()),
+                None,
+                &src,
+                &mask,
+                Some(src_rect),
+                dst_x,
+                1,
+            );
+            for y in 0..4 {
+                for x in 0..96 {
+                    let (src_x, src_y) = (x - dst_x + 5, y);
+                    let masked = src_x >= src_rect.left
+                        && src_x < src_rect.right
+                        && src_y >= src_rect.top
+                        && src_y < src_rect.bottom
+                        && mask.get_pixel(src_x, src_y);
+                    let expected = background.get_pixel(x, y)
+                        ^ (masked && src.get_pixel(src_x, src_y));
+                    assert_eq!(bitmap.get_pixel(x, y), expected);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,224 @@
+use super::*;
+
+/// Which neighbors of a pixel are considered connected to it, for
+/// `flood_fill` and friends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Connectivity {
+    /// Only the pixels directly above, below, left, and right. A fill can't
+    /// leak through a diagonal gap. This is what QuickDraw did.
+    #[default]
+    Four,
+    /// The diagonal neighbors too. A fill will leak through a diagonal gap,
+    /// but can't get past a diagonal line.
+    Eight,
+}
+
+impl Bitmap {
+    /// Fill the area of same-colored pixels that includes the given pixel,
+    /// like a paint bucket. Pixels outside `clip` are not filled, and the
+    /// fill doesn't spread through them.
+    pub fn flood_fill<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        x: i32,
+        y: i32,
+        connectivity: Connectivity,
+    ) where
+        Mode: TransferMode,
+    {
+        let clip = self.get_clip(clip.into());
+        let Some(value) = clip.contains(x, y).then(|| self.get_pixel(x, y))
+        else {
+            return;
+        };
+        let mask = self.fill_mask(clip, value, [(y, x, x + 1)], connectivity);
+        self.fill_region(mode, None, &Region::from_bitmap(&mask));
+    }
+    /// Returns a mask the same size as this bitmap, with every pixel set
+    /// that `flood_fill` would fill if given the same pixel. Pixels outside
+    /// the bitmap are treated as a border that the fill can't cross. This is
+    /// like QuickDraw's `SeedFill`, except that the seed pixel can be of
+    /// either color.
+    pub fn seed_fill(
+        &self,
+        x: i32,
+        y: i32,
+        connectivity: Connectivity,
+    ) -> Bitmap {
+        let clip = self.get_clip(Clip::Everywhere);
+        if !clip.contains(x, y) {
+            return Bitmap::new(self.width, self.height);
+        }
+        let value = self.get_pixel(x, y);
+        self.fill_mask(clip, value, [(y, x, x + 1)], connectivity)
+    }
+    /// Returns a mask the same size as this bitmap, with every pixel set
+    /// except the clear pixels that can be reached from the edges of the
+    /// bitmap. In other words, the mask covers every shape in the bitmap,
+    /// including any holes inside it. This is QuickDraw's `CalcMask`.
+    pub fn calc_mask(&self, connectivity: Connectivity) -> Bitmap {
+        let clip = self.get_clip(Clip::Everywhere);
+        let (width, height) = (self.width as i32, self.height as i32);
+        let seeds = [(0, 0, width), (height - 1, 0, width)].into_iter().chain(
+            (0..height).flat_map(|y| [(y, 0, 1), (y, width - 1, width)]),
+        );
+        let mut ret = self.fill_mask(clip, false, seeds, connectivity);
+        ret.fill_rect(ModeXor(()), None, ret.get_bounds());
+        ret
+    }
+    /// Implements `flood_fill`, `seed_fill`, and `calc_mask`. Returns a
+    /// mask of every pixel inside `clip` whose value is `value`, and that
+    /// is connected to one of those pixels in `seeds`. Each seed is a row
+    /// and a range of pixels on it, left inclusive and right exclusive.
+    ///
+    /// This is a scanline fill. Each span of pixels is found by scanning a
+    /// word at a time, and then the spans above and below it are searched
+    /// for more pixels to fill.
+    fn fill_mask(
+        &self,
+        clip: ClipArea,
+        value: bool,
+        seeds: impl IntoIterator<Item = (i32, i32, i32)>,
+        connectivity: Connectivity,
+    ) -> Bitmap {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let mut mask = Bitmap::new(self.width, self.height);
+        // The pixels that can still be filled: the ones of the right color,
+        // inside the clip area, that haven't been filled yet.
+        let mut available = Bitmap::new(self.width, self.height);
+        for rectangle in clip.get_rectangles(clip.bounds) {
+            if value {
+                available.blit_bits(ModeCopy(()), rectangle, self, None, 0, 0);
+            } else {
+                available.blit_bits(
+                    ModeInverseCopy(()),
+                    rectangle,
+                    self,
+                    None,
+                    0,
+                    0,
+                );
+            }
+        }
+        let reach = match connectivity {
+            Connectivity::Four => 0,
+            Connectivity::Eight => 1,
+        };
+        let mut stack: Vec<(i32, i32, i32)> = seeds.into_iter().collect();
+        while let Some((y, left, right)) = stack.pop() {
+            if y < 0 || y >= height {
+                continue;
+            }
+            let (mut x, right) = (left.max(0), right.min(width));
+            while let Some(start) = available.find_set_pixel(y, x, right) {
+                let start = available.find_span_start(y, start);
+                let end = available.find_span_end(y, start);
+                available.fill_span_unclipped(ModeXor(()), start, end, y);
+                mask.fill_span_unclipped(ModeOr(()), start, end, y);
+                stack.push((y - 1, start - reach, end + reach));
+                stack.push((y + 1, start - reach, end + reach));
+                x = end;
+            }
+        }
+        mask
+    }
+    /// Given a set pixel on row `y`, returns the leftmost pixel of the span
+    /// of set pixels that it's part of.
+    fn find_span_start(&self, y: i32, x: i32) -> i32 {
+        let row = (y as u32 * self.words_per_row) as usize;
+        let mut i = x as usize / BITMAP_WORD_BITS;
+        let bit = x as usize % BITMAP_WORD_BITS;
+        // The clear pixels at or to the left of `x` in this word.
+        let mut word =
+            !self.words[row + i] & (!0 << (BITMAP_WORD_BITS - 1 - bit));
+        loop {
+            if word != 0 {
+                let last_clear = (i * BITMAP_WORD_BITS) as i32
+                    + (BITMAP_WORD_BITS - 1) as i32
+                    - word.trailing_zeros() as i32;
+                return last_clear + 1;
+            }
+            if i == 0 {
+                return 0;
+            }
+            i -= 1;
+            word = !self.words[row + i];
+        }
+    }
+}
+
+impl ClipArea<'_> {
+    /// Returns true if the given pixel can be drawn on.
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.bounds.left
+            && x < self.bounds.right
+            && y >= self.bounds.top
+            && y < self.bounds.bottom
+            && self.region.is_none_or(|region| region.contains(x, y))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn flood_fill() {
+        // a ring, with a diagonal gap in the top right corner, around a
+        // single pixel
+        let ring = Bitmap::from_bytes(
+            40,
+            5,
+            &[
+                0xF0, 0x00, 0x00, 0x00, 0x00, //
+                0x88, 0x00, 0x00, 0x00, 0x00, //
+                0xA8, 0x00, 0x00, 0x00, 0x00, //
+                0x88, 0x00, 0x00, 0x00, 0x00, //
+                0xF8, 0x00, 0x00, 0x00, 0x00, //
+            ],
+        );
+        let mut bitmap = ring.clone();
+        bitmap.flood_fill(ModeOr(()), None, 1, 1, Connectivity::Four);
+        assert_eq!(
+            bitmap.to_bytes()[..20],
+            [
+                0xF0, 0x00, 0x00, 0x00, 0x00, //
+                0xF8, 0x00, 0x00, 0x00, 0x00, //
+                0xF8, 0x00, 0x00, 0x00, 0x00, //
+                0xF8, 0x00, 0x00, 0x00, 0x00, //
+            ]
+        );
+        // leaks out through the gap, and fills everything but the ring and
+        // the pixel in the middle
+        let mask = ring.seed_fill(1, 1, Connectivity::Eight);
+        assert_eq!(mask.to_bytes()[0..5], [0x0F, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(mask.to_bytes()[10..15], [0x57, 0xFF, 0xFF, 0xFF, 0xFF]);
+        // the ring itself, but not the pixel in the middle
+        let mut bitmap = ring.clone();
+        bitmap.flood_fill(ModeXor(()), None, 0, 0, Connectivity::Four);
+        let mut expected = [0; 25];
+        expected[10] = 0x20;
+        assert_eq!(bitmap.to_bytes(), expected);
+        // clipped
+        let mut bitmap = ring.clone();
+        let clip = Rectangle {
+            left: 0,
+            top: 0,
+            right: 3,
+            bottom: 5,
+        };
+        bitmap.flood_fill(ModeOr(()), clip, 1, 1, Connectivity::Four);
+        assert_eq!(bitmap.to_bytes()[5], 0xE8);
+        assert_eq!(bitmap.to_bytes()[10], 0xE8);
+        bitmap.flood_fill(ModeOr(()), clip, 3, 1, Connectivity::Four);
+        assert_eq!(bitmap.to_bytes()[5], 0xE8);
+    }
+    #[test]
+    fn calc_mask() {
+        let ring = Bitmap::from_bytes(8, 5, &[0x00, 0x3C, 0x24, 0x3C, 0x00]);
+        assert_eq!(
+            ring.calc_mask(Connectivity::Four).to_bytes(),
+            &[0x00, 0x3C, 0x3C, 0x3C, 0x00],
+        );
+    }
+}
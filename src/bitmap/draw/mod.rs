@@ -6,6 +6,13 @@ mod transfer;
 pub use transfer::*;
 
 mod blit;
+mod flood;
+pub use flood::*;
+mod polygon;
+pub use polygon::*;
+mod region;
+mod scale;
+pub use scale::*;
 mod shapes;
 
 fn calculate_span_mask(left: u32, right: u32) -> (u32, u32, u32, u32) {
@@ -21,3 +28,187 @@ fn calculate_span_mask(left: u32, right: u32) -> (u32, u32, u32, u32) {
     debug_assert!(right_mask != 0);
     (start_word, stop_word, left_mask, right_mask)
 }
+
+/// Where a drawing operation is allowed to draw. Every drawing function
+/// accepts anything that can be turned into one of these:
+///
+/// - `None` draws anywhere in the bitmap.
+/// - `Some(rectangle)` or `rectangle` only draws inside the rectangle.
+/// - `&region` only draws inside the region.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Clip<'a> {
+    #[default]
+    Everywhere,
+    Rectangle(Rectangle),
+    Region(&'a Region),
+}
+
+impl From<Option<Rectangle>> for Clip<'_> {
+    fn from(clip_rect: Option<Rectangle>) -> Self {
+        match clip_rect {
+            None => Clip::Everywhere,
+            Some(rectangle) => Clip::Rectangle(rectangle),
+        }
+    }
+}
+
+impl From<Rectangle> for Clip<'_> {
+    fn from(rectangle: Rectangle) -> Self {
+        Clip::Rectangle(rectangle)
+    }
+}
+
+impl<'a> From<&'a Region> for Clip<'a> {
+    fn from(region: &'a Region) -> Self {
+        Clip::Region(region)
+    }
+}
+
+/// A `Clip` that has been limited to the bounds of a particular bitmap.
+#[derive(Clone, Copy)]
+struct ClipArea<'a> {
+    /// Nothing outside this rectangle is drawn. Always within the bounds of
+    /// the bitmap.
+    bounds: Rectangle,
+    /// If present, nothing outside this region is drawn either.
+    region: Option<&'a Region>,
+}
+
+impl ClipArea<'_> {
+    /// Returns non-overlapping rectangles that cover the part of `rectangle`
+    /// that can be drawn on.
+    fn get_rectangles(
+        &self,
+        rectangle: Rectangle,
+    ) -> impl Iterator<Item = Rectangle> + '_ {
+        let rectangle = rectangle.intersection(self.bounds);
+        let (whole, region) = match self.region {
+            None => (Some(rectangle), None),
+            Some(region) => (None, Some(region.get_rectangles())),
+        };
+        whole
+            .into_iter()
+            .chain(region.into_iter().flatten())
+            .map(move |x| x.intersection(rectangle))
+            .filter(|x| !x.is_empty())
+    }
+}
+
+impl Bitmap {
+    /// Returns the given clip, limited to the bounds of this bitmap.
+    fn get_clip<'a>(&self, clip: Clip<'a>) -> ClipArea<'a> {
+        let (clip_rect, region) = match clip {
+            Clip::Everywhere => (self.get_bounds(), None),
+            Clip::Rectangle(rectangle) => (rectangle, None),
+            Clip::Region(region) => (region.get_bounds(), Some(region)),
+        };
+        ClipArea {
+            bounds: clip_rect.intersection(self.get_bounds()),
+            region,
+        }
+    }
+    /// Fills the pixels from `left` (inclusive) to `right` (exclusive) on row
+    /// `y`, a word at a time, leaving out any that are outside `clip`.
+    fn fill_span<Mode: TransferMode>(
+        &mut self,
+        mode: Mode,
+        clip: ClipArea,
+        left: i32,
+        right: i32,
+        y: i32,
+    ) {
+        let left = left.max(clip.bounds.left);
+        let right = right.min(clip.bounds.right);
+        if left >= right || y < clip.bounds.top || y >= clip.bounds.bottom {
+            return;
+        }
+        match clip.region {
+            None => self.fill_span_unclipped(mode, left, right, y),
+            Some(region) => {
+                for (span_left, span_right) in region.get_spans(y) {
+                    let left = left.max(*span_left);
+                    let right = right.min(*span_right);
+                    if left < right {
+                        self.fill_span_unclipped(&mode, left, right, y);
+                    }
+                }
+            }
+        }
+    }
+    /// Implements `fill_span`, once the span is known to be entirely within
+    /// the clip area.
+    fn fill_span_unclipped<Mode: TransferMode>(
+        &mut self,
+        mode: Mode,
+        left: i32,
+        right: i32,
+        y: i32,
+    ) {
+        let y = y as u32;
+        let (start_word, stop_word, left_mask, right_mask) =
+            calculate_span_mask(left as u32, right as u32);
+        let mut i = (start_word + y * self.words_per_row) as usize;
+        if start_word == stop_word {
+            let combined_mask = left_mask & right_mask;
+            self.words[i] = self.words[i] & !combined_mask
+                | (mode.combine(combined_mask, self.words[i], start_word, y)
+                    & combined_mask);
+        } else {
+            self.words[i] = self.words[i] & !left_mask
+                | (mode.combine(left_mask, self.words[i], start_word, y)
+                    & left_mask);
+            i += 1;
+            for x in start_word + 1..stop_word {
+                self.words[i] = mode.combine(!0, self.words[i], x, y);
+                i += 1;
+            }
+            self.words[i] = self.words[i] & !right_mask
+                | (mode.combine(right_mask, self.words[i], stop_word, y)
+                    & right_mask);
+        }
+    }
+    /// Returns the leftmost set pixel on row `y`, from `left` (inclusive) to
+    /// `right` (exclusive), if there is one.
+    fn find_set_pixel(&self, y: i32, left: i32, right: i32) -> Option<i32> {
+        if left >= right {
+            return None;
+        }
+        let row = (y as u32 * self.words_per_row) as usize;
+        let mut i = left as usize / BITMAP_WORD_BITS;
+        let mut word =
+            self.words[row + i] & (!0 >> (left as usize % BITMAP_WORD_BITS));
+        loop {
+            if word != 0 {
+                let x = (i * BITMAP_WORD_BITS) as i32
+                    + word.leading_zeros() as i32;
+                return (x < right).then_some(x);
+            }
+            i += 1;
+            if (i * BITMAP_WORD_BITS) as i32 >= right {
+                return None;
+            }
+            word = self.words[row + i];
+        }
+    }
+    /// Given a set pixel on row `y`, returns the pixel just past the right
+    /// end of the span of set pixels that it's part of.
+    fn find_span_end(&self, y: i32, x: i32) -> i32 {
+        let row = (y as u32 * self.words_per_row) as usize;
+        let mut i = x as usize / BITMAP_WORD_BITS;
+        // The clear pixels at or to the right of `x` in this word.
+        let mut word =
+            !self.words[row + i] & (!0 >> (x as usize % BITMAP_WORD_BITS));
+        loop {
+            if word != 0 {
+                let x = (i * BITMAP_WORD_BITS) as i32
+                    + word.leading_zeros() as i32;
+                return x.min(self.width as i32);
+            }
+            i += 1;
+            if i == self.words_per_row as usize {
+                return self.width as i32;
+            }
+            word = !self.words[row + i];
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+
+use super::{shapes::get_line_spans, *};
+
+/// How to decide which pixels are inside a polygon whose edges cross over
+/// each other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FillRule {
+    /// A pixel is inside if a ray from it to the edge of the bitmap crosses
+    /// an odd number of edges. Places where the polygon overlaps itself are
+    /// left empty. This is what QuickDraw did.
+    #[default]
+    EvenOdd,
+    /// A pixel is inside if the edges wind around it at all. Places where
+    /// the polygon overlaps itself are filled, unless the overlapping parts
+    /// go around in opposite directions.
+    NonZero,
+}
+
+/// A closed shape with straight edges, like QuickDraw's `PolyHandle`.
+///
+/// Vertices lie on the boundaries between pixels, just like the corners of a
+/// `Rectangle`, so the polygon (0, 0), (4, 0), (4, 4), (0, 4) covers the
+/// same pixels as the rectangle with those corners. The last vertex is
+/// connected back to the first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Polygon {
+    points: Vec<(i32, i32)>,
+}
+
+impl Polygon {
+    /// Creates a new polygon with no vertices.
+    pub fn new() -> Polygon {
+        Polygon::default()
+    }
+    /// Adds a vertex to the end of the polygon.
+    pub fn push(&mut self, x: i32, y: i32) {
+        self.points.push((x, y));
+    }
+    /// Returns every vertex of the polygon, in order.
+    pub fn get_points(&self) -> &[(i32, i32)] {
+        &self.points
+    }
+    /// Returns the smallest rectangle that contains every pixel the polygon
+    /// could cover when filled.
+    pub fn get_bounds(&self) -> Rectangle {
+        let mut points = self.points.iter();
+        let Some((x, y)) = points.next() else {
+            return Rectangle::default();
+        };
+        points.fold(
+            Rectangle {
+                left: *x,
+                top: *y,
+                right: *x,
+                bottom: *y,
+            },
+            |rect, (x, y)| Rectangle {
+                left: rect.left.min(*x),
+                top: rect.top.min(*y),
+                right: rect.right.max(*x),
+                bottom: rect.bottom.max(*y),
+            },
+        )
+    }
+    /// Returns a copy of the polygon, moved by the given number of pixels.
+    pub fn offset_by(&self, dx: i32, dy: i32) -> Polygon {
+        self.points.iter().map(|(x, y)| (x + dx, y + dy)).collect()
+    }
+    /// Returns every edge of the polygon, including the one that closes it.
+    fn get_edges(
+        &self,
+    ) -> impl Iterator<Item = ((i32, i32), (i32, i32))> + '_ {
+        self.points
+            .iter()
+            .copied()
+            .zip(self.points.iter().copied().cycle().skip(1))
+    }
+}
+
+impl FromIterator<(i32, i32)> for Polygon {
+    fn from_iter<T: IntoIterator<Item = (i32, i32)>>(iter: T) -> Polygon {
+        Polygon {
+            points: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<(i32, i32)> for Polygon {
+    fn extend<T: IntoIterator<Item = (i32, i32)>>(&mut self, iter: T) {
+        self.points.extend(iter)
+    }
+}
+
+impl Bitmap {
+    /// Fill in the given polygon. A pixel is filled if its center is inside
+    /// the polygon, according to `fill_rule`. The polygon is scanned a row
+    /// at a time, and each run of pixels inside it is filled a whole word
+    /// at a time.
+    pub fn fill_polygon<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        polygon: &Polygon,
+        fill_rule: FillRule,
+    ) where
+        Mode: TransferMode,
+    {
+        let clip = self.get_clip(clip.into());
+        let bounds = polygon.get_bounds().intersection(clip.bounds);
+        if bounds.is_empty() {
+            return;
+        }
+        // Horizontal edges never cross a pixel center, so they can be left
+        // out.
+        let mut edges: Vec<Edge> = polygon
+            .get_edges()
+            .filter(|(a, b)| a.1 != b.1)
+            .map(|(a, b)| Edge::new(a, b))
+            .collect();
+        edges.sort_unstable_by_key(|edge| edge.top);
+        let mut next_edge = 0;
+        let mut active_edges: Vec<Edge> = vec![];
+        let mut crossings = vec![];
+        for y in bounds.top..bounds.bottom {
+            while next_edge < edges.len() && edges[next_edge].top <= y {
+                active_edges.push(edges[next_edge]);
+                next_edge += 1;
+            }
+            active_edges.retain(|edge| edge.bottom > y);
+            crossings.clear();
+            crossings.extend(
+                active_edges
+                    .iter()
+                    .map(|edge| (edge.get_crossing(y), edge.winding)),
+            );
+            crossings.sort_unstable();
+            let mut winding = 0;
+            let mut span_start = None;
+            for (x, edge_winding) in crossings.iter().copied() {
+                winding = match fill_rule {
+                    FillRule::EvenOdd => winding ^ 1,
+                    FillRule::NonZero => winding + edge_winding,
+                };
+                match (winding != 0, span_start) {
+                    (true, None) => span_start = Some(x),
+                    (false, Some(start)) => {
+                        self.fill_span(&mode, clip, start, x, y);
+                        span_start = None;
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    /// Draw the edges of the given polygon, as `draw_line` would, including
+    /// the edge from the last vertex back to the first. Every pixel that the
+    /// pen passes over is drawn exactly once, even where edges meet or
+    /// cross.
+    pub fn stroke_polygon<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        polygon: &Polygon,
+        pen_w: u32,
+        pen_h: u32,
+    ) where
+        Mode: TransferMode,
+    {
+        let clip = self.get_clip(clip.into());
+        let mut rows: BTreeMap<i32, Vec<(i32, i32)>> = BTreeMap::new();
+        for (a, b) in polygon.get_edges() {
+            get_line_spans(
+                clip.bounds,
+                a,
+                b,
+                (pen_w, pen_h),
+                |left, right, y| {
+                    rows.entry(y).or_default().push((left, right));
+                },
+            );
+        }
+        for (y, mut spans) in rows {
+            spans.sort_unstable();
+            let mut current = spans[0];
+            for span in spans.into_iter().skip(1) {
+                if span.0 <= current.1 {
+                    current.1 = current.1.max(span.1);
+                } else {
+                    self.fill_span(&mode, clip, current.0, current.1, y);
+                    current = span;
+                }
+            }
+            self.fill_span(&mode, clip, current.0, current.1, y);
+        }
+    }
+}
+
+/// One non-horizontal edge of a polygon being filled.
+#[derive(Clone, Copy)]
+struct Edge {
+    /// The first row whose center is at or below the top of the edge.
+    top: i32,
+    /// The first row whose center is at or below the bottom of the edge.
+    bottom: i32,
+    /// The topmost point of the edge.
+    x0: i128,
+    y0: i128,
+    /// How far the edge goes, from its topmost point. `dy` is positive.
+    dx: i128,
+    dy: i128,
+    /// 1 if the edge goes downward, -1 if it goes upward.
+    winding: i32,
+}
+
+impl Edge {
+    fn new((xa, ya): (i32, i32), (xb, yb): (i32, i32)) -> Edge {
+        let (winding, (x0, y0), (x1, y1)) = if ya < yb {
+            (1, (xa, ya), (xb, yb))
+        } else {
+            (-1, (xb, yb), (xa, ya))
+        };
+        Edge {
+            top: y0,
+            bottom: y1,
+            x0: x0 as i128,
+            y0: y0 as i128,
+            dx: x1 as i128 - x0 as i128,
+            dy: y1 as i128 - y0 as i128,
+            winding,
+        }
+    }
+    /// Returns the leftmost pixel whose center is at or to the right of
+    /// where this edge crosses the center of the given row.
+    fn get_crossing(&self, y: i32) -> i32 {
+        // The crossing is at x0 + (y + 0.5 - y0) * dx / dy, and we want the
+        // ceiling of that minus 0.5. This is done in i128, since the products
+        // can overflow i64 when the vertices are far apart.
+        let numerator = 2 * self.x0 * self.dy
+            + (2 * y as i128 + 1 - 2 * self.y0) * self.dx
+            - self.dy;
+        let denominator = 2 * self.dy;
+        let ret = (numerator + denominator - 1).div_euclid(denominator);
+        ret.clamp(i32::MIN as i128, i32::MAX as i128) as i32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn polygons() {
+        let square: Polygon =
+            [(1, 1), (7, 1), (7, 7), (1, 7)].into_iter().collect();
+        let mut bitmap = Bitmap::new(8, 8);
+        bitmap.fill_polygon(ModeOr(()), None, &square, FillRule::EvenOdd);
+        bitmap.fill_rect(
+            ModeXor(()),
+            None,
+            Rectangle {
+                left: 1,
+                top: 1,
+                right: 7,
+                bottom: 7,
+            },
+        );
+        assert!(bitmap.to_bytes().iter().all(|x| *x == 0));
+        let triangle: Polygon = [(0, 0), (8, 0), (0, 8)].into_iter().collect();
+        bitmap.fill_polygon(ModeOr(()), None, &triangle, FillRule::NonZero);
+        assert_eq!(
+            bitmap.to_bytes(),
+            &[0xFE, 0xFC, 0xF8, 0xF0, 0xE0, 0xC0, 0x80, 0x00]
+        );
+        // goes around twice
+        let mut twice = square.clone();
+        twice.extend(square.get_points().iter().copied());
+        let mut bitmap = Bitmap::new(8, 8);
+        bitmap.fill_polygon(ModeOr(()), None, &twice, FillRule::EvenOdd);
+        assert!(bitmap.to_bytes().iter().all(|x| *x == 0));
+        bitmap.fill_polygon(ModeOr(()), None, &twice, FillRule::NonZero);
+        assert_eq!(bitmap.to_bytes()[1], 0x7E);
+        // a star, whose edges cross
+        let star: Polygon = [(20, 0), (32, 36), (0, 14), (40, 14), (8, 36)]
+            .into_iter()
+            .collect();
+        let mut even_odd = Bitmap::new(40, 40);
+        even_odd.fill_polygon(ModeOr(()), None, &star, FillRule::EvenOdd);
+        let mut non_zero = Bitmap::new(40, 40);
+        non_zero.fill_polygon(ModeOr(()), None, &star, FillRule::NonZero);
+        assert!(!even_odd.get_pixel(20, 20) && non_zero.get_pixel(20, 20));
+        assert!(even_odd.get_pixel(20, 5) && non_zero.get_pixel(20, 5));
+        // every pixel of the outline is drawn once
+        let mut or = Bitmap::new(40, 40);
+        or.stroke_polygon(ModeOr(()), None, &star, 2, 2);
+        let mut xor = Bitmap::new(40, 40);
+        xor.stroke_polygon(ModeXor(()), None, &star, 2, 2);
+        assert_eq!(or.to_bytes(), xor.to_bytes());
+        assert!(or.get_pixel(20, 0) && or.get_pixel(8, 35));
+        // vertices as far apart as they can be
+        let huge: Polygon = [
+            (i32::MIN, i32::MIN),
+            (i32::MAX, i32::MAX),
+            (i32::MIN, i32::MAX),
+        ]
+        .into_iter()
+        .collect();
+        let mut bitmap = Bitmap::new(8, 8);
+        bitmap.fill_polygon(ModeOr(()), None, &huge, FillRule::EvenOdd);
+        // everything below the diagonal through the origin
+        assert_eq!(
+            bitmap.to_bytes(),
+            &[0x00, 0x80, 0xC0, 0xE0, 0xF0, 0xF8, 0xFC, 0xFE]
+        );
+    }
+}
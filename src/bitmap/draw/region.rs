@@ -0,0 +1,109 @@
+use super::*;
+
+impl Bitmap {
+    /// Fill in every pixel of the given region.
+    pub fn fill_region<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        region: &Region,
+    ) where
+        Mode: TransferMode,
+    {
+        let clip = self.get_clip(clip.into());
+        let clipped;
+        let region = match clip.region {
+            Some(clip_region) => {
+                clipped = region.intersection(clip_region);
+                &clipped
+            }
+            None => region,
+        };
+        for rectangle in region.get_rectangles() {
+            let rectangle = rectangle.intersection(clip.bounds);
+            if !rectangle.is_empty() {
+                self.fill_rect_unclipped(&mode, rectangle);
+            }
+        }
+    }
+    /// Draw a border around the inside edge of the given region, the way
+    /// `stroke_rect` does for rectangles. The border is `x_thickness` pixels
+    /// thick on the left and right edges, and `y_thickness` pixels thick on
+    /// the top and bottom edges. This is QuickDraw's `FrameRgn`.
+    pub fn stroke_region<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        region: &Region,
+        x_thickness: u32,
+        y_thickness: u32,
+    ) where
+        Mode: TransferMode,
+    {
+        let inside = region.inset_by(x_thickness as i32, y_thickness as i32);
+        self.fill_region(mode, clip, &region.difference(&inside));
+    }
+    /// Invert every pixel of the given region. Inverting the same region
+    /// again puts things back the way they were.
+    pub fn invert_region<'c>(
+        &mut self,
+        clip: impl Into<Clip<'c>>,
+        region: &Region,
+    ) {
+        self.fill_region(ModeXor(()), clip, region);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn region_drawing() {
+        let region = Region::from(Rectangle {
+            left: 0,
+            top: 0,
+            right: 4,
+            bottom: 4,
+        })
+        .union(&Region::from(Rectangle {
+            left: 2,
+            top: 2,
+            right: 8,
+            bottom: 6,
+        }));
+        let mut bitmap = Bitmap::new(8, 8);
+        bitmap.fill_region(ModeOr(()), None, &region);
+        assert_eq!(
+            bitmap.to_bytes(),
+            &[0xF0, 0xF0, 0xFF, 0xFF, 0x3F, 0x3F, 0x00, 0x00]
+        );
+        bitmap.invert_region(None, &region);
+        assert!(bitmap.to_bytes().iter().all(|x| *x == 0));
+        bitmap.stroke_region(ModeOr(()), None, &region, 1, 1);
+        assert_eq!(
+            bitmap.to_bytes(),
+            &[0xF0, 0x90, 0x9F, 0xE1, 0x21, 0x3F, 0x00, 0x00]
+        );
+        // every kind of drawing can be clipped to a region
+        let mut bitmap = Bitmap::new(8, 8);
+        bitmap.fill_rect(ModeOr(()), &region, bitmap.get_bounds());
+        assert_eq!(bitmap.to_bytes(), region.to_bitmap(8, 8).to_bytes());
+        let mut bitmap = Bitmap::new(8, 8);
+        let src = Bitmap::from_bytes(8, 1, &[0xFF]);
+        for y in 0..8 {
+            bitmap.blit_bits(ModeOr(()), &region, &src, None, 0, y);
+        }
+        assert_eq!(bitmap.to_bytes(), region.to_bitmap(8, 8).to_bytes());
+        let mut bitmap = Bitmap::new(8, 8);
+        bitmap.draw_line(ModeXor(()), &region, 0, 3, 7, 3, 1, 1);
+        assert_eq!(bitmap.to_bytes()[3], 0xFF);
+        bitmap.draw_line(ModeXor(()), &region, 0, 4, 7, 4, 1, 1);
+        assert_eq!(bitmap.to_bytes()[4], 0x3F);
+        let mut bitmap = Bitmap::new(8, 8);
+        bitmap.fill_region(ModeOr(()), &region.offset_by(4, 4), &region);
+        assert_eq!(
+            bitmap.to_bytes(),
+            &[0x00, 0x00, 0x00, 0x00, 0x0F, 0x0F, 0x00, 0x00]
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use super::*;
+
+/// How `blit_scaled` decides which destination pixels to set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Scaling {
+    /// Each destination pixel takes the value of the source pixel under its
+    /// center. Enlarging by a whole number repeats every pixel exactly that
+    /// many times. When shrinking, thin lines may disappear.
+    #[default]
+    Nearest,
+    /// When shrinking, a destination pixel is set if *any* of the source
+    /// pixels it covers are set, so that thin lines never disappear (though
+    /// they may get thicker). Enlarging works the same as `Nearest`.
+    OrDown,
+}
+
+impl Bitmap {
+    /// Blit the given rectangle of pixels from the source into ourselves,
+    /// stretching or shrinking it to fill `dst_rect`, like QuickDraw's
+    /// `CopyBits` does when the rectangles are different sizes. If they're
+    /// the same size, this is the same as `blit_bits`.
+    ///
+    /// The scaled image is built a span at a time, and then blitted into
+    /// place with `blit_bits`, so any transfer mode and any clip work as
+    /// usual. Only the part of `dst_rect` that's inside the clip area is
+    /// built.
+    pub fn blit_scaled<'c, Mode: TransferMode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        src: &Bitmap,
+        src_rect: Option<Rectangle>,
+        dst_rect: Rectangle,
+        scaling: Scaling,
+    ) {
+        let src_rect = src_rect.unwrap_or(src.get_bounds());
+        let clip: Clip = clip.into();
+        let visible = dst_rect.intersection(self.get_clip(clip).bounds);
+        if src_rect.is_empty() || visible.is_empty() {
+            return;
+        }
+        let src_size = (src_rect.get_width(), src_rect.get_height());
+        let dst_size = (dst_rect.get_width(), dst_rect.get_height());
+        let mut scaled =
+            Bitmap::new(visible.get_width(), visible.get_height());
+        let words_per_row = scaled.words_per_row as usize;
+        let mut previous_rows = None;
+        for y in visible.top..visible.bottom {
+            let scaled_y = y - visible.top;
+            let (top, bottom) = get_source_range(
+                (y - dst_rect.top) as i64,
+                src_size.1,
+                dst_size.1,
+                scaling,
+            );
+            let rows =
+                (top as i32 + src_rect.top, bottom as i32 + src_rect.top);
+            if previous_rows == Some(rows) {
+                // Same as the row above, as happens when enlarging.
+                let start = scaled_y as usize * words_per_row;
+                scaled
+                    .words
+                    .copy_within(start - words_per_row..start, start);
+                continue;
+            }
+            previous_rows = Some(rows);
+            let left = src_rect.left.max(0);
+            let right = src_rect.right.min(src.width as i32);
+            for src_y in rows.0.max(0)..rows.1.min(src.height as i32) {
+                let mut x = left;
+                while let Some(start) = src.find_set_pixel(src_y, x, right) {
+                    let end = src.find_span_end(src_y, start).min(right);
+                    let (span_left, span_right) = get_destination_span(
+                        (start - src_rect.left) as i64,
+                        (end - src_rect.left) as i64,
+                        src_size.0,
+                        dst_size.0,
+                        scaling,
+                    );
+                    let offset = (dst_rect.left - visible.left) as i64;
+                    let width = visible.get_width() as i64;
+                    let span_left = (span_left + offset).clamp(0, width);
+                    let span_right = (span_right + offset).clamp(0, width);
+                    if span_left < span_right {
+                        scaled.fill_span_unclipped(
+                            ModeOr(()),
+                            span_left as i32,
+                            span_right as i32,
+                            scaled_y,
+                        );
+                    }
+                    x = end;
+                }
+            }
+        }
+        self.blit_bits(mode, clip, &scaled, None, visible.left, visible.top);
+    }
+}
+
+/// Returns the source pixels (start inclusive, end exclusive, relative to
+/// the source rectangle) that destination pixel `dst` (relative to the
+/// destination rectangle) is made from.
+fn get_source_range(
+    dst: i64,
+    src_len: u32,
+    dst_len: u32,
+    scaling: Scaling,
+) -> (i64, i64) {
+    let (src_len, dst_len) = (src_len as i64, dst_len as i64);
+    if scaling == Scaling::OrDown && dst_len < src_len {
+        let start = (dst * src_len).div_euclid(dst_len);
+        let end = ((dst + 1) * src_len + dst_len - 1).div_euclid(dst_len);
+        (start, end)
+    } else {
+        let src = ((2 * dst + 1) * src_len).div_euclid(2 * dst_len);
+        (src, src + 1)
+    }
+}
+
+/// Returns the destination pixels (start inclusive, end exclusive, relative
+/// to the destination rectangle) that are made from any of the source pixels
+/// from `start` to `end` (relative to the source rectangle). This is the
+/// reverse of `get_source_range`.
+fn get_destination_span(
+    start: i64,
+    end: i64,
+    src_len: u32,
+    dst_len: u32,
+    scaling: Scaling,
+) -> (i64, i64) {
+    let (src_len, dst_len) = (src_len as i64, dst_len as i64);
+    if scaling == Scaling::OrDown && dst_len < src_len {
+        let left = (start * dst_len).div_euclid(src_len);
+        let right = (end * dst_len + src_len - 1).div_euclid(src_len);
+        (left, right)
+    } else {
+        // The first destination pixel whose center maps to `x` or later.
+        let first =
+            |x: i64| (2 * x * dst_len + src_len - 1).div_euclid(2 * src_len);
+        (first(start), first(end))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn scaling() {
+        let src = Bitmap::from_bytes(4, 2, &[0x90, 0x60]);
+        let mut bitmap = Bitmap::new(8, 4);
+        let dst_rect = bitmap.get_bounds();
+        bitmap.blit_scaled(
+            ModeOr(()),
+            None,
+            &src,
+            None,
+            dst_rect,
+            Scaling::Nearest,
+        );
+        assert_eq!(bitmap.to_bytes(), &[0xC3, 0xC3, 0x3C, 0x3C]);
+        // a one-pixel line at the left edge disappears, unless ORed down
+        let src = Bitmap::from_bytes(8, 2, &[0x80, 0x80]);
+        let rect = Rectangle {
+            left: 0,
+            top: 0,
+            right: 4,
+            bottom: 1,
+        };
+        let mut bitmap = Bitmap::new(8, 1);
+        bitmap.blit_scaled(
+            ModeOr(()),
+            None,
+            &src,
+            None,
+            rect,
+            Scaling::Nearest,
+        );
+        assert_eq!(bitmap.to_bytes(), &[0x00]);
+        bitmap.blit_scaled(
+            ModeOr(()),
+            None,
+            &src,
+            None,
+            rect,
+            Scaling::OrDown,
+        );
+        assert_eq!(bitmap.to_bytes(), &[0x80]);
+        // clipped, and with a transfer mode
+        let mut bitmap = Bitmap::from_bytes(8, 1, &[0xFF]);
+        let clip = Rectangle {
+            left: 0,
+            top: 0,
+            right: 2,
+            bottom: 1,
+        };
+        bitmap.blit_scaled(
+            ModeXor(()),
+            clip,
+            &Bitmap::from_bytes(1, 1, &[0x80]),
+            None,
+            bitmap.get_bounds(),
+            Scaling::Nearest,
+        );
+        assert_eq!(bitmap.to_bytes(), &[0x3F]);
+    }
+    #[test]
+    fn scaling_matches_pixels() {
+        let bytes: Vec<u8> = (0..60u32)
+            .map(|x| (x.wrapping_mul(2654435761) >> 7) as u8)
+            .collect();
+        let src = Bitmap::from_bytes(29, 15, &bytes);
+        let src_rect = Rectangle {
+            left: 2,
+            top: 1,
+            right: 27,
+            bottom: 14,
+        };
+        for (width, height) in [(13, 7), (50, 26), (75, 5), (7, 39)] {
+            for scaling in [Scaling::Nearest, Scaling::OrDown] {
+                let dst_rect = Rectangle {
+                    left: 3,
+                    top: 2,
+                    right: 3 + width,
+                    bottom: 2 + height,
+                };
+                let mut bitmap = Bitmap::new(80, 42);
+                bitmap.blit_scaled(
+                    ModeOr(()),
+                    None,
+                    &src,
+                    Some(src_rect),
+                    dst_rect,
+                    scaling,
+                );
+                for y in 0..42 {
+                    for x in 0..80 {
+                        let inside = x >= dst_rect.left
+                            && x < dst_rect.right
+                            && y >= dst_rect.top
+                            && y < dst_rect.bottom;
+                        let expected = inside && {
+                            let (x0, x1) = get_source_range(
+                                (x - dst_rect.left) as i64,
+                                25,
+                                width as u32,
+                                scaling,
+                            );
+                            let (y0, y1) = get_source_range(
+                                (y - dst_rect.top) as i64,
+                                13,
+                                height as u32,
+                                scaling,
+                            );
+                            (y0..y1).any(|src_y| {
+                                (x0..x1).any(|src_x| {
+                                    src.get_pixel(
+                                        src_x as i32 + src_rect.left,
+                                        src_y as i32 + src_rect.top,
+                                    )
+                                })
+                            })
+                        };
+                        assert_eq!(
+                            bitmap.get_pixel(x, y),
+                            expected,
+                            "{width}x{height} {scaling:?} at {x}, {y}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
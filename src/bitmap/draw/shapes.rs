@@ -2,21 +2,28 @@ use super::*;
 
 impl Bitmap {
     /// Fill in all pixels of the given rectangle.
-    pub fn fill_rect<Mode>(
+    pub fn fill_rect<'c, Mode>(
         &mut self,
         mode: Mode,
-        clip_rect: Option<Rectangle>,
+        clip: impl Into<Clip<'c>>,
         rectangle: Rectangle,
     ) where
         Mode: TransferMode,
     {
-        let clip_rect = clip_rect
-            .map(|x| x.intersection(self.get_bounds()))
-            .unwrap_or(self.get_bounds());
-        let rectangle = rectangle.intersection(clip_rect);
-        if rectangle.is_empty() {
-            return;
+        let clip = self.get_clip(clip.into());
+        for rectangle in clip.get_rectangles(rectangle) {
+            self.fill_rect_unclipped(&mode, rectangle);
         }
+    }
+    /// Implements `fill_rect`, once the rectangle is known to be entirely
+    /// within the clip area.
+    pub(super) fn fill_rect_unclipped<Mode>(
+        &mut self,
+        mode: Mode,
+        rectangle: Rectangle,
+    ) where
+        Mode: TransferMode,
+    {
         let left = rectangle.left as u32;
         let right = rectangle.right as u32;
         let top = rectangle.top as u32;
@@ -57,24 +64,25 @@ impl Bitmap {
     }
     /// Draw a line border around the given rectangle. The drawn pixels will be
     /// strictly inside the given rectangle.
-    pub fn stroke_rect<Mode>(
+    pub fn stroke_rect<'c, Mode>(
         &mut self,
         mode: Mode,
-        clip_rect: Option<Rectangle>,
+        clip: impl Into<Clip<'c>>,
         rectangle: Rectangle,
         x_thickness: u32,
         y_thickness: u32,
     ) where
         Mode: TransferMode,
     {
+        let clip: Clip = clip.into();
         if rectangle.get_width() <= x_thickness * 2
             || rectangle.get_height() <= y_thickness * 2
         {
-            self.fill_rect(mode, clip_rect, rectangle);
+            self.fill_rect(mode, clip, rectangle);
         } else {
             self.fill_rect(
                 &mode,
-                clip_rect,
+                clip,
                 Rectangle {
                     left: rectangle.left,
                     right: rectangle.right,
@@ -84,7 +92,7 @@ impl Bitmap {
             );
             self.fill_rect(
                 &mode,
-                clip_rect,
+                clip,
                 Rectangle {
                     left: rectangle.left,
                     right: rectangle.left + x_thickness as i32,
@@ -94,7 +102,7 @@ impl Bitmap {
             );
             self.fill_rect(
                 &mode,
-                clip_rect,
+                clip,
                 Rectangle {
                     left: rectangle.right - x_thickness as i32,
                     right: rectangle.right,
@@ -104,7 +112,7 @@ impl Bitmap {
             );
             self.fill_rect(
                 &mode,
-                clip_rect,
+                clip,
                 Rectangle {
                     left: rectangle.left,
                     right: rectangle.right,
@@ -114,4 +122,695 @@ impl Bitmap {
             );
         }
     }
+    /// Draw a line from (`x0`, `y0`) to (`x1`, `y1`) with a rectangular pen
+    /// that is `pen_w` pixels wide and `pen_h` pixels tall, the way
+    /// QuickDraw's `LineTo` does.
+    ///
+    /// The pen hangs below and to the right of the coordinates, so a 1x1 pen
+    /// touches exactly the pixels on the line, and a larger pen also touches
+    /// the pixels below and to the right of them. The line is rasterized
+    /// with Bresenham's algorithm, always starting from the higher endpoint
+    /// so that the same pixels are touched whichever way the line is given.
+    /// Every pixel the pen passes over is drawn exactly once, so `ModeXor`
+    /// can be used to erase a line by drawing it again.
+    ///
+    /// Nothing is drawn if either pen dimension is zero.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_line<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+        pen_w: u32,
+        pen_h: u32,
+    ) where
+        Mode: TransferMode,
+    {
+        let clip = self.get_clip(clip.into());
+        get_line_spans(
+            clip.bounds,
+            (x0, y0),
+            (x1, y1),
+            (pen_w, pen_h),
+            |left, right, y| {
+                self.fill_span(&mode, clip, left, right, y);
+            },
+        );
+    }
+    /// Fill in the oval that fits inside the given rectangle. A pixel is
+    /// part of the oval if its center is.
+    ///
+    /// (Use `ModeXor` to invert an oval, as QuickDraw's `InvertOval` did.
+    /// The same goes for all of the other shapes.)
+    pub fn fill_oval<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        rectangle: Rectangle,
+    ) where
+        Mode: TransferMode,
+    {
+        self.draw_shape(
+            mode,
+            clip,
+            Shape {
+                rectangle,
+                oval_width: u32::MAX,
+                oval_height: u32::MAX,
+                thickness: None,
+                arc: None,
+            },
+        );
+    }
+    /// Draw the outline of the oval that fits inside the given rectangle. The
+    /// outline is the part of that oval that isn't inside the oval that fits
+    /// inside the rectangle after it's been shrunk by the given thicknesses
+    /// on each side, so the drawn pixels will be strictly inside the oval.
+    pub fn stroke_oval<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        rectangle: Rectangle,
+        x_thickness: u32,
+        y_thickness: u32,
+    ) where
+        Mode: TransferMode,
+    {
+        self.draw_shape(
+            mode,
+            clip,
+            Shape {
+                rectangle,
+                oval_width: u32::MAX,
+                oval_height: u32::MAX,
+                thickness: Some((x_thickness, y_thickness)),
+                arc: None,
+            },
+        );
+    }
+    /// Fill in the given rectangle, with its corners rounded off. Each corner
+    /// is a quarter of an oval that is `oval_width` by `oval_height` pixels.
+    /// (If the oval is as big as the rectangle, this is the same as
+    /// `fill_oval`.)
+    pub fn fill_round_rect<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        rectangle: Rectangle,
+        oval_width: u32,
+        oval_height: u32,
+    ) where
+        Mode: TransferMode,
+    {
+        self.draw_shape(
+            mode,
+            clip,
+            Shape {
+                rectangle,
+                oval_width,
+                oval_height,
+                thickness: None,
+                arc: None,
+            },
+        );
+    }
+    /// Draw the outline of a rounded rectangle, as with `stroke_oval`. The
+    /// inside edge of the outline has corners that are smaller by twice the
+    /// thickness.
+    #[allow(clippy::too_many_arguments)]
+    pub fn stroke_round_rect<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        rectangle: Rectangle,
+        oval_width: u32,
+        oval_height: u32,
+        x_thickness: u32,
+        y_thickness: u32,
+    ) where
+        Mode: TransferMode,
+    {
+        self.draw_shape(
+            mode,
+            clip,
+            Shape {
+                rectangle,
+                oval_width,
+                oval_height,
+                thickness: Some((x_thickness, y_thickness)),
+                arc: None,
+            },
+        );
+    }
+    /// Fill in a wedge of the oval that fits inside the given rectangle, like
+    /// a slice of a pie chart.
+    ///
+    /// As in QuickDraw, angles are in degrees, with 0 at the top and
+    /// positive angles going clockwise. The wedge starts at `start_angle`
+    /// and sweeps through `arc_angle` (counterclockwise, if it's negative).
+    /// Angles are measured as if the rectangle were a square, so 45 degrees
+    /// is always toward the top-right corner. An `arc_angle` of 360 or more
+    /// fills the whole oval.
+    pub fn fill_arc<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        rectangle: Rectangle,
+        start_angle: i32,
+        arc_angle: i32,
+    ) where
+        Mode: TransferMode,
+    {
+        let Some(arc) = normalize_arc(start_angle, arc_angle) else {
+            return;
+        };
+        self.draw_shape(
+            mode,
+            clip,
+            Shape {
+                rectangle,
+                oval_width: u32::MAX,
+                oval_height: u32::MAX,
+                thickness: None,
+                arc,
+            },
+        );
+    }
+    /// Draw part of the outline of the oval that fits inside the given
+    /// rectangle: the part that is within the wedge that `fill_arc` would
+    /// fill. The straight edges of the wedge are not drawn.
+    #[allow(clippy::too_many_arguments)]
+    pub fn stroke_arc<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        rectangle: Rectangle,
+        start_angle: i32,
+        arc_angle: i32,
+        x_thickness: u32,
+        y_thickness: u32,
+    ) where
+        Mode: TransferMode,
+    {
+        let Some(arc) = normalize_arc(start_angle, arc_angle) else {
+            return;
+        };
+        self.draw_shape(
+            mode,
+            clip,
+            Shape {
+                rectangle,
+                oval_width: u32::MAX,
+                oval_height: u32::MAX,
+                thickness: Some((x_thickness, y_thickness)),
+                arc,
+            },
+        );
+    }
+    /// Draws any of the rounded shapes, a row at a time, with `fill_span`.
+    fn draw_shape<'c, Mode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        shape: Shape,
+    ) where
+        Mode: TransferMode,
+    {
+        let clip = self.get_clip(clip.into());
+        let outer = shape.rectangle;
+        if outer.is_empty() || clip.bounds.is_empty() {
+            return;
+        }
+        let inner = shape.thickness.and_then(|(x_thickness, y_thickness)| {
+            let rectangle = Rectangle {
+                left: outer.left.saturating_add_unsigned(x_thickness),
+                top: outer.top.saturating_add_unsigned(y_thickness),
+                right: outer.right.saturating_sub_unsigned(x_thickness),
+                bottom: outer.bottom.saturating_sub_unsigned(y_thickness),
+            };
+            (!rectangle.is_empty()).then(|| Shape {
+                rectangle,
+                oval_width: shape
+                    .oval_width
+                    .saturating_sub(x_thickness.saturating_mul(2)),
+                oval_height: shape
+                    .oval_height
+                    .saturating_sub(y_thickness.saturating_mul(2)),
+                ..shape
+            })
+        });
+        let rays = shape.arc.map(|(start, sweep)| {
+            (shape.get_ray(start), shape.get_ray(start + sweep), sweep)
+        });
+        for y in outer.top.max(clip.bounds.top)
+            ..outer.bottom.min(clip.bounds.bottom)
+        {
+            let (left, right) = shape.get_row(y);
+            let hole = inner
+                .as_ref()
+                .filter(|inner| {
+                    y >= inner.rectangle.top && y < inner.rectangle.bottom
+                })
+                .map(|inner| inner.get_row(y))
+                .filter(|(hole_left, hole_right)| hole_left < hole_right);
+            let spans = match hole {
+                Some((hole_left, hole_right)) => [
+                    (left, hole_left.max(left)),
+                    (hole_right.min(right), right),
+                ],
+                None => [(left, right), (right, right)],
+            };
+            let runs = match rays {
+                None => [(i64::MIN, i64::MAX), (i64::MAX, i64::MAX)],
+                Some(rays) => shape.get_arc_runs(rays, y),
+            };
+            for (left, right) in spans {
+                let left = left.max(clip.bounds.left) as i64;
+                let right = right.min(clip.bounds.right) as i64;
+                for (run_left, run_right) in runs {
+                    let (left, right) =
+                        (run_left.max(left), run_right.min(right));
+                    if left < right {
+                        self.fill_span(
+                            &mode,
+                            clip,
+                            left as i32,
+                            right as i32,
+                            y,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Works out the pixels touched by `draw_line`, and calls `emit` with the
+/// left (inclusive) and right (exclusive) edges of the touched pixels on each
+/// row, and the row. Rows that can't reach `clip_rect` are skipped, but the
+/// spans aren't clipped horizontally.
+pub(super) fn get_line_spans(
+    clip_rect: Rectangle,
+    (x0, y0): (i32, i32),
+    (x1, y1): (i32, i32),
+    (pen_w, pen_h): (u32, u32),
+    mut emit: impl FnMut(i32, i32, i32),
+) {
+    if pen_w == 0 || pen_h == 0 || clip_rect.is_empty() {
+        return;
+    }
+    let ((x0, y0), (x1, y1)) = if (y0, x0) <= (y1, x1) {
+        ((x0, y0), (x1, y1))
+    } else {
+        ((x1, y1), (x0, y0))
+    };
+    // Only the rows whose pen rectangles can reach the clip rectangle
+    // are kept track of.
+    let first_row = y0.max(clip_rect.top.saturating_sub(pen_h as i32 - 1));
+    let last_row = y1.min(clip_rect.bottom - 1);
+    if first_row > last_row {
+        return;
+    }
+    // The leftmost and rightmost pen X on each row.
+    let mut rows =
+        vec![(i32::MAX, i32::MIN); (last_row - first_row + 1) as usize];
+    let dx = (x1 as i64 - x0 as i64).abs();
+    let dy = -(y1 as i64 - y0 as i64);
+    let sx = if x0 < x1 { 1 } else { -1 };
+    let (mut x, mut y) = (x0, y0);
+    let mut error = dx + dy;
+    loop {
+        if y >= first_row && y <= last_row {
+            let row = &mut rows[(y - first_row) as usize];
+            *row = (row.0.min(x), row.1.max(x));
+        }
+        if (x == x1 && y == y1) || y > last_row {
+            break;
+        }
+        let double_error = error * 2;
+        if double_error >= dy {
+            error += dy;
+            x += sx;
+        }
+        if double_error <= dx {
+            error += dx;
+            y += 1;
+        }
+    }
+    // Since X only ever moves one way along the line, the pen positions
+    // that reach any given row all lie between the ones on the first and
+    // last rows that reach it.
+    for y in first_row..=last_row.saturating_add(pen_h as i32 - 1) {
+        let top = (y - (pen_h as i32 - 1)).max(first_row);
+        let bottom = y.min(last_row);
+        if top > bottom {
+            continue;
+        }
+        let (top_left, top_right) = rows[(top - first_row) as usize];
+        let (bottom_left, bottom_right) = rows[(bottom - first_row) as usize];
+        emit(
+            top_left.min(bottom_left),
+            top_right.max(bottom_right).saturating_add(pen_w as i32),
+            y,
+        );
+    }
+}
+
+/// A rounded rectangle (or an oval, or a wedge of an oval, or the outline of
+/// any of those), as drawn by `draw_shape`.
+#[derive(Clone, Copy)]
+struct Shape {
+    rectangle: Rectangle,
+    /// The size of the oval that each corner is a quarter of. These are
+    /// clamped to the size of the rectangle.
+    oval_width: u32,
+    oval_height: u32,
+    /// The thickness of the outline, or `None` to fill the shape in.
+    thickness: Option<(u32, u32)>,
+    /// The start and sweep of the wedge, in clockwise degrees from the top,
+    /// or `None` for the whole shape.
+    arc: Option<(i32, i32)>,
+}
+
+impl Shape {
+    /// Returns the left (inclusive) and right (exclusive) edges of the shape
+    /// on the given row, which must be within the rectangle.
+    fn get_row(&self, y: i32) -> (i32, i32) {
+        let width = self.rectangle.get_width();
+        let height = self.rectangle.get_height();
+        let oval_width = self.oval_width.min(width);
+        let oval_height = self.oval_height.min(height);
+        let row = (y - self.rectangle.top) as u32;
+        // The rows of the corner ovals are split between the top and bottom
+        // of the rectangle. Any rows in between aren't rounded at all.
+        let oval_row = if row < oval_height / 2 {
+            Some(row)
+        } else if row >= height - oval_height / 2 {
+            Some(row - (height - oval_height))
+        } else {
+            None
+        };
+        let inset = oval_row
+            .map(|row| get_oval_inset(oval_width, oval_height, row))
+            .unwrap_or(0) as i32;
+        (self.rectangle.left + inset, self.rectangle.right - inset)
+    }
+    /// Returns the direction of the edge of a wedge at the given angle, in
+    /// doubled pixel coordinates (as used by `get_half_row`), to 30 bits of
+    /// precision.
+    fn get_ray(&self, angle: i32) -> (i128, i128) {
+        let (sin, cos) = (angle as f64).to_radians().sin_cos();
+        let fixed = |x: f64| (x * (1 << 30) as f64).round() as i128;
+        (
+            fixed(sin) * self.rectangle.get_width() as i128,
+            -fixed(cos) * self.rectangle.get_height() as i128,
+        )
+    }
+    /// Returns the runs of pixels on the given row whose centers are within
+    /// the wedge whose edges are given by `get_ray`, as two ranges (left
+    /// inclusive, right exclusive) that may be empty or unbounded.
+    fn get_arc_runs(
+        &self,
+        (start, end, sweep): ((i128, i128), (i128, i128), i32),
+        y: i32,
+    ) -> [(i64, i64); 2] {
+        let start = self.get_half_row(start, y);
+        let end = self.get_half_row(end, y);
+        // A wedge of up to 180 degrees is the part of the half starting at
+        // its start that isn't in the half starting at its end. A bigger
+        // one is everything that's in the former or not in the latter.
+        let intersect =
+            |(a, b): (i64, i64), (c, d): (i64, i64)| (a.max(c), b.min(d));
+        if sweep <= 180 {
+            [intersect(start, invert_half_row(end)), (i64::MAX, i64::MAX)]
+        } else {
+            match intersect(invert_half_row(start), end) {
+                (left, right) if left < right => {
+                    [(i64::MIN, left), (right, i64::MAX)]
+                }
+                _ => [(i64::MIN, i64::MAX), (i64::MAX, i64::MAX)],
+            }
+        }
+    }
+    /// Returns the pixels on the given row whose centers are within the
+    /// half of the oval that starts at the given ray and goes 180 degrees
+    /// clockwise from it. The ray itself is included, but the ray opposite
+    /// it isn't, and the center of the oval counts as being straight below
+    /// it (at 180 degrees). The pixels are returned as a range (left
+    /// inclusive, right exclusive) that's unbounded at one end.
+    fn get_half_row(
+        &self,
+        (ray_x, ray_y): (i128, i128),
+        y: i32,
+    ) -> (i64, i64) {
+        // Working in doubled coordinates, relative to the center of the
+        // rectangle, a pixel center (x, y) is clockwise of the ray if
+        //     ray_x * y - ray_y * x > 0
+        // which, on a given row, is a linear function of the pixel's column.
+        let center_x = self.rectangle.left as i128 * 2
+            + self.rectangle.get_width() as i128;
+        let center_y = self.rectangle.top as i128 * 2
+            + self.rectangle.get_height() as i128;
+        let row_y = y as i128 * 2 + 1 - center_y;
+        let slope = -2 * ray_y;
+        let intercept = ray_x * row_y - ray_y * (1 - center_x);
+        let clamp = |x: i128| x.clamp(i64::MIN as i128, i64::MAX as i128);
+        let (left, right) = if slope == 0 {
+            // The ray is horizontal, so only the row through the center can
+            // be on it. That row is split at the center.
+            match intercept.signum() {
+                1 => (i128::MIN, i128::MAX),
+                -1 => (i128::MAX, i128::MAX),
+                _ if ray_x > 0 => (center_x.div_euclid(2), i128::MAX),
+                _ => (i128::MIN, center_x.div_euclid(2)),
+            }
+        } else {
+            // Where the function is zero, the pixel is on the ray if it's on
+            // the same side of the center as the ray points. On the row
+            // through the center, it's the center itself, which is in this
+            // half if straight down is.
+            let on_ray = if row_y == 0 {
+                ray_x > 0 || (ray_x == 0 && ray_y > 0)
+            } else {
+                row_y * ray_y > 0
+            };
+            let exact = intercept % slope == 0;
+            if slope > 0 {
+                let zero = (-intercept).div_euclid(slope);
+                (if exact && on_ray { zero } else { zero + 1 }, i128::MAX)
+            } else {
+                let zero = intercept.div_euclid(-slope);
+                (i128::MIN, if exact && !on_ray { zero } else { zero + 1 })
+            }
+        };
+        (clamp(left) as i64, clamp(right) as i64)
+    }
+}
+
+/// Returns the pixels of a row that aren't in the given range, which must be
+/// unbounded at one end (as returned by `Shape::get_half_row`).
+fn invert_half_row((left, right): (i64, i64)) -> (i64, i64) {
+    if left == i64::MIN {
+        (right, i64::MAX)
+    } else {
+        (i64::MIN, left)
+    }
+}
+
+/// Returns the start and sweep of a wedge, with the sweep made positive, or
+/// `Some(None)` if it's the whole oval, or `None` if it's nothing at all.
+fn normalize_arc(
+    start_angle: i32,
+    arc_angle: i32,
+) -> Option<Option<(i32, i32)>> {
+    if arc_angle == 0 {
+        None
+    } else if arc_angle.unsigned_abs() >= 360 {
+        Some(None)
+    } else if arc_angle < 0 {
+        Some(Some((
+            (start_angle + arc_angle).rem_euclid(360),
+            -arc_angle,
+        )))
+    } else {
+        Some(Some((start_angle.rem_euclid(360), arc_angle)))
+    }
+}
+
+/// Returns how many pixels at each end of the given row are outside the oval
+/// that fits inside a `width` by `height` rectangle.
+fn get_oval_inset(width: u32, height: u32, row: u32) -> u32 {
+    // Working in doubled coordinates, relative to the center of the oval,
+    // a pixel is inside if its center (x, y) satisfies:
+    //     x^2 / width^2 + y^2 / height^2 <= 1
+    let (width, height) = (width as i128, height as i128);
+    let y = row as i128 * 2 + 1 - height;
+    let limit = width * width * (height * height - y * y) / (height * height);
+    let x = limit.isqrt();
+    ((width - x).div_euclid(2)).max(0) as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn lines() {
+        let mut bitmap = Bitmap::new(8, 4);
+        bitmap.draw_line(ModeOr(()), None, 0, 0, 7, 3, 1, 1);
+        assert_eq!(bitmap.to_bytes(), &[0xC0, 0x30, 0x0C, 0x03]);
+        // drawn backwards, the same pixels are touched
+        bitmap.draw_line(ModeXor(()), None, 7, 3, 0, 0, 1, 1);
+        assert!(bitmap.to_bytes().iter().all(|x| *x == 0));
+        // the pen hangs below and to the right
+        bitmap.draw_line(ModeOr(()), None, 1, 0, 3, 0, 2, 3);
+        assert_eq!(bitmap.to_bytes(), &[0x78, 0x78, 0x78, 0x00]);
+        bitmap.draw_line(ModeCopy(MEDIUM_GRAY), None, 0, 3, 7, 3, 1, 1);
+        assert_eq!(bitmap.to_bytes()[3], 0x55);
+        // with a big pen, every pixel is still only drawn once
+        for (x0, y0, x1, y1, pen_w, pen_h) in [
+            (2, 3, 40, 17, 3, 2),
+            (40, 2, 5, 30, 2, 5),
+            (-10, 20, 50, 24, 1, 4),
+            (20, -5, 22, 40, 6, 1),
+            (30, 30, 30, 30, 4, 4),
+        ] {
+            let mut or = Bitmap::new(48, 32);
+            or.draw_line(ModeOr(()), None, x0, y0, x1, y1, pen_w, pen_h);
+            let mut xor = Bitmap::new(48, 32);
+            xor.draw_line(ModeXor(()), None, x0, y0, x1, y1, pen_w, pen_h);
+            assert_eq!(or.to_bytes(), xor.to_bytes());
+            assert!(or.get_pixel((x0 + x1) / 2, (y0 + y1) / 2));
+        }
+        // clipped
+        let mut bitmap = Bitmap::new(8, 4);
+        let clip = Rectangle {
+            left: 2,
+            top: 1,
+            right: 8,
+            bottom: 4,
+        };
+        bitmap.draw_line(ModeOr(()), Some(clip), 0, 1, 7, 1, 1, 2);
+        assert_eq!(bitmap.to_bytes(), &[0x00, 0x3F, 0x3F, 0x00]);
+    }
+
+    #[test]
+    fn ovals() {
+        let rect = Rectangle {
+            left: 0,
+            top: 0,
+            right: 8,
+            bottom: 8,
+        };
+        let mut bitmap = Bitmap::new(8, 8);
+        bitmap.fill_oval(ModeOr(()), None, rect);
+        assert_eq!(
+            bitmap.to_bytes(),
+            &[0x3C, 0x7E, 0xFF, 0xFF, 0xFF, 0xFF, 0x7E, 0x3C]
+        );
+        bitmap.stroke_oval(ModeXor(()), None, rect, 1, 1);
+        assert_eq!(
+            bitmap.to_bytes(),
+            &[0x00, 0x3C, 0x7E, 0x7E, 0x7E, 0x7E, 0x3C, 0x00]
+        );
+        let mut bitmap = Bitmap::new(8, 6);
+        bitmap.fill_round_rect(
+            ModeOr(()),
+            None,
+            Rectangle {
+                left: 0,
+                top: 0,
+                right: 8,
+                bottom: 6,
+            },
+            4,
+            4,
+        );
+        assert_eq!(bitmap.to_bytes(), &[0x7E, 0xFF, 0xFF, 0xFF, 0xFF, 0x7E]);
+        // a quarter of the pie, whichever way it's given
+        for (start, sweep) in [(0, 90), (90, -90), (360, 90)] {
+            let mut bitmap = Bitmap::new(8, 8);
+            bitmap.fill_arc(ModeOr(()), None, rect, start, sweep);
+            assert_eq!(
+                bitmap.to_bytes(),
+                &[0x0C, 0x0E, 0x0F, 0x0F, 0x00, 0x00, 0x00, 0x00]
+            );
+        }
+        // every pixel is only drawn once
+        let rect = Rectangle {
+            left: -3,
+            top: 2,
+            right: 37,
+            bottom: 29,
+        };
+        let mut or = Bitmap::new(40, 32);
+        let mut xor = Bitmap::new(40, 32);
+        or.stroke_round_rect(ModeOr(()), None, rect, 12, 9, 3, 2);
+        xor.stroke_round_rect(ModeXor(()), None, rect, 12, 9, 3, 2);
+        assert_eq!(or.to_bytes(), xor.to_bytes());
+        let mut or = Bitmap::new(40, 32);
+        let mut xor = Bitmap::new(40, 32);
+        or.stroke_arc(ModeOr(()), None, rect, 30, 250, 2, 3);
+        xor.stroke_arc(ModeXor(()), None, rect, 30, 250, 2, 3);
+        or.fill_arc(ModeOr(()), None, rect, -70, 45);
+        xor.fill_arc(ModeXor(()), None, rect, -70, 45);
+        assert_eq!(or.to_bytes(), xor.to_bytes());
+        let mut full = Bitmap::new(40, 32);
+        full.stroke_arc(ModeXor(()), None, rect, 10, 360, 2, 3);
+        full.stroke_oval(ModeXor(()), None, rect, 2, 3);
+        assert!(full.to_bytes().iter().all(|x| *x == 0));
+    }
+    #[test]
+    fn wedges() {
+        // the angle of each pixel center, measured as if the rectangle were
+        // a square
+        let rect = Rectangle {
+            left: -5,
+            top: 3,
+            right: 36,
+            bottom: 30,
+        };
+        let angle = |x: i32, y: i32| {
+            let dx = (x * 2 + 1 - (rect.left * 2 + rect.right - rect.left))
+                as f64
+                / rect.get_width() as f64;
+            let dy = (y * 2 + 1 - (rect.top * 2 + rect.bottom - rect.top))
+                as f64
+                / rect.get_height() as f64;
+            dx.atan2(-dy).to_degrees()
+        };
+        let mut oval = Bitmap::new(40, 32);
+        oval.fill_oval(ModeOr(()), None, rect);
+        for (start, sweep) in [
+            (0, 90),
+            (90, 90),
+            (180, 180),
+            (270, 200),
+            (17, 33),
+            (300, 100),
+            (-45, 180),
+            (100, 250),
+            (5, 359),
+        ] {
+            let mut bitmap = Bitmap::new(40, 32);
+            bitmap.fill_arc(ModeXor(()), None, rect, start, sweep);
+            for y in 0..32 {
+                for x in 0..40 {
+                    let in_arc = (angle(x, y) - start as f64)
+                        .rem_euclid(360.0)
+                        < sweep as f64;
+                    assert_eq!(
+                        bitmap.get_pixel(x, y),
+                        oval.get_pixel(x, y) && in_arc,
+                        "{start}+{sweep} at {x}, {y}"
+                    );
+                }
+            }
+            // the rest of the oval is the wedge that goes the other way
+            bitmap.fill_arc(ModeXor(()), None, rect, start, sweep - 360);
+            assert_eq!(bitmap.to_bytes(), oval.to_bytes());
+        }
+    }
 }
@@ -0,0 +1,420 @@
+use super::bdf::make_box_glyph;
+use super::*;
+
+/// Glyphs packed into a two-dimensional bitmap, instead of a strike. Used for
+/// fonts whose glyphs are too wide, in total, for a strike, or whose offsets
+/// or advances don't fit in a strike's tables.
+///
+/// Each glyph gets a cell as tall as the font's ascent plus its descent, so
+/// that (just like in a strike) the top of a glyph's rectangle is always the
+/// ascent line. Cells are packed left to right into rows, and the rows are
+/// stacked top to bottom.
+#[derive(Clone)]
+pub(super) struct GlyphAtlas {
+    /// Indexed the same way as a strike's tables: one entry for every glyph
+    /// in `glyph_range`, then one for the missing glyph. `None` for glyphs
+    /// that aren't present.
+    glyphs: Vec<Option<AtlasGlyph>>,
+}
+
+#[derive(Clone, Copy)]
+pub(super) struct AtlasGlyph {
+    /// The glyph's cell within the atlas
+    pub(super) rect: Rectangle,
+    /// The amount to add to the pen X coordinate to get the left edge of
+    /// `rect`
+    pub(super) offset: i32,
+    /// The amount to advance the pen X coordinate by
+    pub(super) advance: u32,
+}
+
+impl GlyphAtlas {
+    /// Returns the glyph at the given index. If it isn't present, returns
+    /// the missing glyph instead.
+    pub(super) fn get(&self, index: usize) -> &AtlasGlyph {
+        self.glyphs[index]
+            .as_ref()
+            .or_else(|| self.glyphs.last().unwrap().as_ref())
+            .unwrap()
+    }
+    pub(super) fn is_present(&self, index: usize) -> bool {
+        self.glyphs[index].is_some()
+    }
+    /// Iterates over the index and advance of every present glyph.
+    pub(super) fn advances_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (usize, &mut u32)> {
+        self.glyphs.iter_mut().enumerate().filter_map(|(n, glyph)| {
+            glyph.as_mut().map(|x| (n, &mut x.advance))
+        })
+    }
+}
+
+impl Font {
+    /// Returns true if this font's glyphs are packed into an atlas instead of
+    /// a strike. (This makes no difference to drawing or measuring text, but
+    /// such a font can't be written as NFNT.)
+    pub fn is_atlas(&self) -> bool {
+        self.atlas.is_some()
+    }
+    /// Like `from_loose_glyphs`, but always makes an atlas.
+    pub(super) fn pack_atlas(
+        glyphs: &BTreeMap<u16, LooseGlyph>,
+        missing_glyph: &LooseGlyph,
+        ascent: i32,
+        descent: i32,
+        leading: i32,
+    ) -> anyhow::Result<Font> {
+        let first_glyph = glyphs.keys().next().copied().unwrap_or(0);
+        let last_glyph = glyphs.keys().next_back().copied().unwrap_or(0);
+        if last_glyph == MISSING_GLYPH {
+            return Err(anyhow!(
+                "font didn't leave room for the fallback glyph"
+            ));
+        }
+        let (ascent, descent, leading) =
+            fit_metrics(glyphs, missing_glyph, ascent, descent, leading);
+        let cell_height = (ascent + descent).max(0) as u32;
+        let slots: Vec<Option<&LooseGlyph>> = (first_glyph..=last_glyph)
+            .map(|id| glyphs.get(&id))
+            .chain(std::iter::once(Some(missing_glyph)))
+            .collect();
+        let present = || slots.iter().flatten();
+        let total_width: u64 =
+            present().map(|x| x.bitmap.get_width() as u64).sum();
+        let widest = present().map(|x| x.bitmap.get_width()).max().unwrap();
+        // Aim for a roughly square atlas.
+        let row_width = ((total_width * cell_height as u64).isqrt() as u32)
+            .max(widest)
+            .max(1);
+        let mut x = 0;
+        let mut y = 0;
+        let mut atlas_glyphs = Vec::with_capacity(slots.len());
+        for glyph in slots.iter() {
+            let Some(glyph) = glyph else {
+                atlas_glyphs.push(None);
+                continue;
+            };
+            let width = glyph.bitmap.get_width();
+            if x + width > row_width {
+                x = 0;
+                y += cell_height;
+            }
+            atlas_glyphs.push(Some(AtlasGlyph {
+                rect: Rectangle {
+                    left: x as i32,
+                    top: y as i32,
+                    right: (x + width) as i32,
+                    bottom: (y + cell_height) as i32,
+                },
+                offset: glyph.offset,
+                advance: glyph.advance,
+            }));
+            x += width;
+        }
+        let mut bitmap = Bitmap::new(row_width, y + cell_height);
+        for (glyph, cell) in slots.iter().zip(atlas_glyphs.iter()) {
+            if let (Some(glyph), Some(cell)) = (glyph, cell) {
+                bitmap.blit_bits(
+                    ModeCopy(()),
+                    None,
+                    &glyph.bitmap,
+                    None,
+                    cell.rect.left,
+                    cell.rect.top + ascent - glyph.top,
+                );
+            }
+        }
+        Ok(Font {
+            glyph_range: first_glyph..=last_glyph,
+            bitmap,
+            ascent: ascent.try_into()?,
+            descent: descent.try_into()?,
+            leading: leading.try_into()?,
+            glyph_locations: vec![],
+            glyph_offsetwidths: vec![],
+            glyph_widths: None,
+            image_heights: None,
+            kerning: HashMap::new(),
+            space_width: OnceLock::new(),
+            atlas: Some(GlyphAtlas {
+                glyphs: atlas_glyphs,
+            }),
+        })
+    }
+    /// Applies a style operation (such as `make_bold`) to a font that uses an
+    /// atlas. Each glyph is put into a strike of its own, `op` is applied to
+    /// that, and the results are packed back into an atlas.
+    ///
+    /// Panics if a glyph is too wide for a strike's locations table (more
+    /// than 65535 pixels).
+    pub(super) fn map_atlas(&self, op: impl Fn(&Font) -> Font) -> Font {
+        let atlas = self.atlas.as_ref().unwrap();
+        let mut styled_glyphs = Vec::with_capacity(atlas.glyphs.len());
+        let mut metrics = (self.ascent, self.descent, self.leading);
+        let mut glyph_widths = Vec::with_capacity(atlas.glyphs.len());
+        for (index, glyph) in atlas.glyphs.iter().enumerate() {
+            let Some(glyph) = glyph else {
+                styled_glyphs.push(None);
+                glyph_widths.push(0);
+                continue;
+            };
+            // A strike can only hold an i8 offset and a u8 advance. The rest
+            // is added back afterward.
+            let offset = glyph.offset.clamp(-128, 127);
+            let advance = glyph.advance.min(254);
+            let width = glyph.rect.get_width();
+            assert!(
+                width <= u16::MAX as u32,
+                "glyph {} is {} pixels wide, but styles only support glyphs up \
+                 to {} pixels wide",
+                index,
+                width,
+                u16::MAX
+            );
+            let mut bitmap = Bitmap::new(width, glyph.rect.get_height());
+            bitmap.blit_bits(
+                ModeCopy(()),
+                None,
+                &self.bitmap,
+                Some(glyph.rect),
+                0,
+                0,
+            );
+            let strike = Font {
+                glyph_range: 0..=0,
+                bitmap,
+                ascent: self.ascent,
+                descent: self.descent,
+                leading: self.leading,
+                glyph_locations: vec![0, width as u16, width as u16],
+                glyph_offsetwidths: vec![
+                    (offset as i8, advance as u8),
+                    (0, 0),
+                ],
+                glyph_widths: self
+                    .glyph_widths
+                    .as_ref()
+                    .map(|widths| vec![widths[index], 0]),
+                image_heights: None,
+                kerning: HashMap::new(),
+                space_width: OnceLock::new(),
+                atlas: None,
+            };
+            let styled = op(&strike);
+            metrics = (styled.ascent, styled.descent, styled.leading);
+            let (rect, styled_offset, styled_advance, _) = styled.get_glyph(0);
+            let mut bitmap = Bitmap::new(rect.get_width(), rect.get_height());
+            bitmap.blit_bits(
+                ModeCopy(()),
+                None,
+                &styled.bitmap,
+                Some(rect),
+                0,
+                0,
+            );
+            styled_glyphs.push(Some(LooseGlyph {
+                bitmap,
+                top: styled.get_ascent(),
+                offset: styled_offset + (glyph.offset - offset),
+                advance: (styled_advance as i32 + glyph.advance as i32
+                    - advance as i32)
+                    .max(0) as u32,
+            }));
+            glyph_widths.push(styled.get_fractional_advance(0).unwrap_or(0));
+        }
+        let missing_glyph = styled_glyphs.pop().unwrap().unwrap();
+        let glyphs: BTreeMap<u16, LooseGlyph> = self
+            .glyph_range
+            .clone()
+            .zip(styled_glyphs)
+            .filter_map(|(id, glyph)| glyph.map(|glyph| (id, glyph)))
+            .collect();
+        let (ascent, descent, leading) = metrics;
+        let mut ret = Font::pack_atlas(
+            &glyphs,
+            &missing_glyph,
+            ascent as i32,
+            descent as i32,
+            leading as i32,
+        )
+        .expect("Internal error: restyled atlas couldn't be packed");
+        if self.glyph_widths.is_some() {
+            ret.glyph_widths =
+                Some(glyph_widths.into_iter().map(|x| x as u16).collect());
+        }
+        ret.kerning = self.kerning.clone();
+        ret
+    }
+}
+
+/// Builds a font out of individual glyph images, such as ones rendered at
+/// runtime. The result is a strike if the glyphs fit in one, and an atlas
+/// otherwise.
+///
+/// To add glyphs to an existing font, start with `FontBuilder::from_font`.
+#[derive(Clone, Default)]
+pub struct FontBuilder {
+    ascent: i32,
+    descent: i32,
+    leading: i32,
+    glyphs: BTreeMap<u16, LooseGlyph>,
+    missing_glyph: Option<LooseGlyph>,
+    kerning: HashMap<(u16, u16), i32>,
+}
+
+impl FontBuilder {
+    /// Starts building a font with the given metrics, and no glyphs. (The
+    /// ascent and descent will be increased if any glyphs stick out past
+    /// them, with the leading reduced to match.)
+    pub fn new(ascent: i32, descent: i32, leading: i32) -> FontBuilder {
+        FontBuilder {
+            ascent,
+            descent,
+            leading,
+            ..FontBuilder::default()
+        }
+    }
+    /// Starts building a font with every glyph, the missing glyph, the
+    /// metrics, and the kerning of an existing font.
+    pub fn from_font(font: &Font) -> FontBuilder {
+        FontBuilder {
+            ascent: font.get_ascent(),
+            descent: font.get_descent(),
+            leading: font.get_leading(),
+            glyphs: font
+                .glyph_range
+                .clone()
+                .filter(|id| font.get_glyph(*id).3)
+                .map(|id| (id, font.get_loose_glyph(id)))
+                .collect(),
+            missing_glyph: Some(font.get_loose_glyph(MISSING_GLYPH)),
+            kerning: font.kerning.clone(),
+        }
+    }
+    /// Adds a glyph, replacing any glyph that already had the same ID.
+    /// Adding `MISSING_GLYPH` sets the missing glyph. (If there isn't one,
+    /// a hollow box is used.)
+    ///
+    /// - `top` is how far the top row of `bitmap` is above the baseline.
+    /// - `offset` is the amount to add to the pen X coordinate to get the
+    ///   left edge of `bitmap`.
+    /// - `advance` is the amount to advance the pen X coordinate by.
+    pub fn add_glyph(
+        &mut self,
+        glyph_id: u16,
+        bitmap: Bitmap,
+        top: i32,
+        offset: i32,
+        advance: u32,
+    ) {
+        let glyph = LooseGlyph {
+            bitmap,
+            top,
+            offset,
+            advance,
+        };
+        if glyph_id == MISSING_GLYPH {
+            self.missing_glyph = Some(glyph);
+        } else {
+            self.glyphs.insert(glyph_id, glyph);
+        }
+    }
+    /// Removes a glyph, if it was present.
+    pub fn remove_glyph(&mut self, glyph_id: u16) {
+        if glyph_id == MISSING_GLYPH {
+            self.missing_glyph = None;
+        } else {
+            self.glyphs.remove(&glyph_id);
+        }
+    }
+    /// Replaces the kerning pairs. See `Font::set_kerning`.
+    pub fn set_kerning(
+        &mut self,
+        pairs: impl IntoIterator<Item = ((u16, u16), i32)>,
+    ) {
+        self.kerning = pairs.into_iter().filter(|x| x.1 != 0).collect();
+    }
+    /// Makes the font, as a strike if possible, and as an atlas otherwise.
+    pub fn build(&self) -> anyhow::Result<Font> {
+        self.build_with(false)
+    }
+    /// Makes the font, as an atlas even if it would fit in a strike.
+    pub fn build_atlas(&self) -> anyhow::Result<Font> {
+        self.build_with(true)
+    }
+    /// Implements `build` and `build_atlas`.
+    fn build_with(&self, always_atlas: bool) -> anyhow::Result<Font> {
+        let missing_glyph = match self.missing_glyph.as_ref() {
+            Some(glyph) => glyph.clone(),
+            None => make_box_glyph(
+                (self.ascent / 2).max(3) as u32,
+                self.ascent.max(3) as u32,
+            ),
+        };
+        let pack = if always_atlas {
+            Font::pack_atlas
+        } else {
+            Font::from_loose_glyphs
+        };
+        let mut font = pack(
+            &self.glyphs,
+            &missing_glyph,
+            self.ascent,
+            self.descent,
+            self.leading,
+        )?;
+        font.kerning = self.kerning.clone();
+        Ok(font)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn atlas() {
+        let strike = super::super::test::make_test_font().make_bold();
+        let atlas = FontBuilder::from_font(&strike).build_atlas().unwrap();
+        assert!(atlas.is_atlas());
+        assert!(atlas.get_bitmap().get_height() > 9);
+        super::super::test::assert_same_glyphs(&strike, &atlas);
+        // styles work the same way on both
+        super::super::test::assert_same_glyphs(
+            &strike.make_italic().make_underline().make_shadow(),
+            &atlas.make_italic().make_underline().make_shadow(),
+        );
+        super::super::test::assert_same_glyphs(
+            &strike.make_condensed(2),
+            &atlas.make_condensed(2),
+        );
+        assert!(atlas.write_mac_font(std::io::sink()).is_err());
+    }
+    #[test]
+    fn too_big_for_a_strike() {
+        let mut builder = FontBuilder::new(8, 2, 0);
+        let mut bitmap = Bitmap::new(3, 10);
+        bitmap.fill_rect(ModeCopy(()), None, bitmap.get_bounds());
+        builder.add_glyph(0x41, bitmap.clone(), 8, -200, 300);
+        builder.add_glyph(0x2000, bitmap, 8, 0, 4);
+        let font = builder.build().unwrap();
+        assert!(font.is_atlas());
+        assert_eq!(font.get_glyph(0x41).1, -200);
+        assert_eq!(font.get_glyph(0x41).2, 300);
+        assert_eq!(font.get_space_width(), font.get_glyph(0x20).2);
+        let mut bitmap = Bitmap::new(8, 10);
+        bitmap.draw_glyph(ModeCopy(()), None, 202, 8, &[&font], 0x41);
+        assert_eq!(bitmap.to_bytes(), vec![0x38; 10]);
+        // a font with an offset that fits is still a strike
+        let mut builder = FontBuilder::new(8, 2, 0);
+        builder.add_glyph(0x41, Bitmap::new(3, 10), 8, -2, 4);
+        assert!(!builder.build().unwrap().is_atlas());
+    }
+    #[test]
+    #[should_panic(expected = "styles only support glyphs up to 65535")]
+    fn too_wide_to_style() {
+        let mut builder = FontBuilder::new(8, 2, 0);
+        builder.add_glyph(0x41, Bitmap::new(65536, 10), 8, 0, 4);
+        builder.build().unwrap().make_bold();
+    }
+}
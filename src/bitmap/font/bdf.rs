@@ -0,0 +1,401 @@
+use std::io::Write;
+use std::io::{BufRead, BufReader};
+
+use super::*;
+
+impl Font {
+    /// Attempts to parse the given input as a Glyph Bitmap Distribution
+    /// Format (BDF) font.
+    ///
+    /// - Each glyph's `ENCODING` becomes its glyph ID. Glyphs with no
+    ///   encoding, or an encoding above 0xFFFE, are skipped.
+    /// - `FONT_ASCENT` and `FONT_DESCENT` become the ascent and descent. (If
+    ///   they're missing, they're calculated from `FONTBOUNDINGBOX`.) The
+    ///   strike is made tall enough to hold every glyph; see the note about
+    ///   leading below.
+    /// - `DWIDTH` becomes the advance, and the X offset of `BBX` becomes the
+    ///   offset.
+    /// - If `DEFAULT_CHAR` names a glyph in the font, it is also used as the
+    ///   missing glyph. Otherwise, an unencoded glyph named `.notdef` is used
+    ///   as the missing glyph. Failing that, a hollow box is used.
+    ///
+    /// BDF has no concept of leading, so the leading is zero, unless some
+    /// glyphs stick out above the ascent or below the descent. In that case,
+    /// the ascent and descent are increased to fit them, and the leading
+    /// becomes negative by the same amount.
+    ///
+    /// See: <https://adobe-type-tools.github.io/font-tech-notes/pdfs/5005.BDF_Spec.pdf>
+    pub fn read_bdf(i: impl Read) -> anyhow::Result<Font> {
+        let reader = BufReader::new(i);
+        let mut lines = reader.lines().enumerate();
+        let mut next_line = || -> anyhow::Result<(usize, String)> {
+            match lines.next() {
+                Some((n, line)) => Ok((n + 1, line?)),
+                None => Err(anyhow!("Unexpected end of BDF file")),
+            }
+        };
+        let (_, first_line) = next_line()?;
+        if !first_line.starts_with("STARTFONT") {
+            return Err(anyhow!("Input is not a BDF font"));
+        }
+        let mut bounding_box = None;
+        let mut font_ascent = None;
+        let mut font_descent = None;
+        let mut default_char = None;
+        let mut default_dwidth = None;
+        let mut glyphs = BTreeMap::new();
+        let mut notdef = None;
+        loop {
+            let (n, line) = next_line()?;
+            let mut words = line.split_ascii_whitespace();
+            match words.next() {
+                Some("FONTBOUNDINGBOX") => {
+                    bounding_box = Some(
+                        parse_numbers::<4>(words)
+                            .with_context(|| format!("line {n}"))?,
+                    );
+                }
+                Some("FONT_ASCENT") => {
+                    font_ascent = Some(
+                        parse_numbers::<1>(words)
+                            .with_context(|| format!("line {n}"))?[0],
+                    );
+                }
+                Some("FONT_DESCENT") => {
+                    font_descent = Some(
+                        parse_numbers::<1>(words)
+                            .with_context(|| format!("line {n}"))?[0],
+                    );
+                }
+                Some("DEFAULT_CHAR") => {
+                    default_char = Some(
+                        parse_numbers::<1>(words)
+                            .with_context(|| format!("line {n}"))?[0],
+                    );
+                }
+                Some("DWIDTH") => {
+                    default_dwidth = Some(
+                        parse_numbers::<2>(words)
+                            .with_context(|| format!("line {n}"))?[0],
+                    );
+                }
+                Some("STARTCHAR") => {
+                    let name = words.next();
+                    match read_bdf_glyph(&mut next_line, default_dwidth)? {
+                        (Some(id), glyph) => {
+                            glyphs.insert(id, glyph);
+                        }
+                        (None, glyph) if name == Some(".notdef") => {
+                            notdef = Some(glyph);
+                        }
+                        (None, _) => (),
+                    }
+                }
+                Some("ENDFONT") => break,
+                _ => (),
+            }
+        }
+        let (ascent, descent) = match (font_ascent, font_descent, bounding_box)
+        {
+            (Some(ascent), Some(descent), _) => (ascent, descent),
+            (_, _, Some([_, height, _, y_offset])) => {
+                (height + y_offset, -y_offset)
+            }
+            _ => {
+                return Err(anyhow!(
+                    "BDF font has neither FONT_ASCENT/FONT_DESCENT nor \
+                     FONTBOUNDINGBOX"
+                ))
+            }
+        };
+        let missing_glyph = match default_char
+            .and_then(|x| u16::try_from(x).ok())
+            .and_then(|x| glyphs.get(&x))
+        {
+            Some(glyph) => LooseGlyph {
+                bitmap: glyph.bitmap.clone(),
+                ..*glyph
+            },
+            None => notdef.unwrap_or_else(|| {
+                let width = bounding_box
+                    .map(|x| x[0] - 1)
+                    .unwrap_or(ascent / 2)
+                    .max(3);
+                make_box_glyph(width as u32, ascent.max(3) as u32)
+            }),
+        };
+        Font::from_loose_glyphs(&glyphs, &missing_glyph, ascent, descent, 0)
+    }
+    /// Writes this font as a BDF font, with the given name on the `FONT`
+    /// line. (This should be an XLFD name, if you want X11 to be happy with
+    /// it.)
+    ///
+    /// - Each glyph ID becomes the `ENCODING` of a glyph.
+    /// - The missing glyph is written as an unencoded glyph named `.notdef`,
+    ///   which `read_bdf` will pick back up.
+    /// - Blank rows above and below each glyph are trimmed.
+    /// - The size is the ascent plus the descent, at 72 DPI.
+    ///
+    /// Leading, fractional advances, image heights, and kerning can't be
+    /// expressed in BDF, so they're lost.
+    pub fn write_bdf(
+        &self,
+        mut writer: impl Write,
+        name: &str,
+    ) -> std::io::Result<()> {
+        let ids = self
+            .glyph_range
+            .clone()
+            .filter(|id| self.get_glyph(*id).3)
+            .map(Some)
+            .chain([None]);
+        let glyphs: Vec<(Option<u16>, LooseGlyph)> = ids
+            .map(|id| (id, self.get_loose_glyph(id.unwrap_or(MISSING_GLYPH))))
+            .collect();
+        let pixel_size = (self.get_ascent() + self.get_descent()).max(1);
+        let bounds = glyphs
+            .iter()
+            .map(|(_, glyph)| glyph.get_bounds())
+            .filter(|rect| !rect.is_empty())
+            .reduce(|a, b| a.union(b))
+            .unwrap_or_default();
+        writeln!(writer, "STARTFONT 2.1")?;
+        writeln!(writer, "FONT {name}")?;
+        writeln!(writer, "SIZE {pixel_size} 72 72")?;
+        writeln!(
+            writer,
+            "FONTBOUNDINGBOX {} {} {} {}",
+            bounds.get_width(),
+            bounds.get_height(),
+            bounds.left,
+            -bounds.bottom,
+        )?;
+        writeln!(writer, "STARTPROPERTIES 2")?;
+        writeln!(writer, "FONT_ASCENT {}", self.get_ascent())?;
+        writeln!(writer, "FONT_DESCENT {}", self.get_descent())?;
+        writeln!(writer, "ENDPROPERTIES")?;
+        writeln!(writer, "CHARS {}", glyphs.len())?;
+        for (id, glyph) in glyphs.iter() {
+            match id {
+                Some(id) => {
+                    writeln!(writer, "STARTCHAR char{id}")?;
+                    writeln!(writer, "ENCODING {id}")?;
+                }
+                None => {
+                    writeln!(writer, "STARTCHAR .notdef")?;
+                    writeln!(writer, "ENCODING -1")?;
+                }
+            }
+            let swidth = glyph.advance as i32 * 1000 / pixel_size;
+            writeln!(writer, "SWIDTH {swidth} 0")?;
+            writeln!(writer, "DWIDTH {} 0", glyph.advance)?;
+            let width = glyph.bitmap.get_width();
+            let height = glyph.bitmap.get_height();
+            writeln!(
+                writer,
+                "BBX {width} {height} {} {}",
+                glyph.offset,
+                glyph.top - height as i32,
+            )?;
+            writeln!(writer, "BITMAP")?;
+            let row_bytes = width.div_ceil(8) as usize;
+            if row_bytes > 0 {
+                for row in glyph.bitmap.to_bytes().chunks(row_bytes) {
+                    for byte in row {
+                        write!(writer, "{byte:02X}")?;
+                    }
+                    writeln!(writer)?;
+                }
+            }
+            writeln!(writer, "ENDCHAR")?;
+        }
+        writeln!(writer, "ENDFONT")
+    }
+}
+
+/// Reads everything between `STARTCHAR` and `ENDCHAR`. The glyph ID is `None`
+/// if the glyph has no usable encoding.
+fn read_bdf_glyph(
+    next_line: &mut impl FnMut() -> anyhow::Result<(usize, String)>,
+    default_dwidth: Option<i32>,
+) -> anyhow::Result<(Option<u16>, LooseGlyph)> {
+    let mut encoding = None;
+    let mut dwidth = default_dwidth;
+    let mut bbx = None;
+    loop {
+        let (n, line) = next_line()?;
+        let mut words = line.split_ascii_whitespace();
+        match words.next() {
+            Some("ENCODING") => {
+                encoding = Some(
+                    words
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing number"))
+                        .and_then(|x| Ok(x.parse::<i32>()?))
+                        .with_context(|| format!("line {n}"))?,
+                );
+            }
+            Some("DWIDTH") => {
+                dwidth = Some(
+                    parse_numbers::<2>(words)
+                        .with_context(|| format!("line {n}"))?[0],
+                );
+            }
+            Some("BBX") => {
+                bbx = Some(
+                    parse_numbers::<4>(words)
+                        .with_context(|| format!("line {n}"))?,
+                );
+            }
+            Some("BITMAP") => break,
+            Some("ENDCHAR") => {
+                return Err(anyhow!("line {n}: glyph has no BITMAP"));
+            }
+            _ => (),
+        }
+    }
+    let [width, height, x_offset, y_offset] =
+        bbx.ok_or_else(|| anyhow!("BDF glyph has no BBX"))?;
+    if width < 0 || height < 0 {
+        return Err(anyhow!("BDF glyph has a negative size"));
+    }
+    let (width, height) = (width as u32, height as u32);
+    let row_bytes = width.div_ceil(8) as usize;
+    let mut bytes = Vec::with_capacity(row_bytes * height as usize);
+    for _ in 0..height {
+        let (n, line) = next_line()?;
+        let line = line.trim();
+        for i in 0..row_bytes {
+            let byte = line
+                .get(i * 2..i * 2 + 2)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .ok_or_else(|| anyhow!("line {n}: invalid BITMAP row"))?;
+            bytes.push(byte);
+        }
+    }
+    let (n, line) = next_line()?;
+    if line.trim() != "ENDCHAR" {
+        return Err(anyhow!("line {n}: expected ENDCHAR"));
+    }
+    let dwidth = dwidth
+        .ok_or_else(|| anyhow!("line {n}: glyph has no DWIDTH"))?
+        .max(0) as u32;
+    let id = encoding
+        .and_then(|x| u16::try_from(x).ok())
+        .filter(|x| *x != MISSING_GLYPH);
+    Ok((
+        id,
+        LooseGlyph {
+            bitmap: Bitmap::from_bytes(width, height, &bytes),
+            top: y_offset + height as i32,
+            offset: x_offset,
+            advance: dwidth,
+        },
+    ))
+}
+
+/// Parses exactly N whitespace-separated integers.
+fn parse_numbers<'a, const N: usize>(
+    mut words: impl Iterator<Item = &'a str>,
+) -> anyhow::Result<[i32; N]> {
+    let mut ret = [0; N];
+    for x in ret.iter_mut() {
+        *x = words
+            .next()
+            .ok_or_else(|| anyhow!("Missing number"))?
+            .parse()?;
+    }
+    Ok(ret)
+}
+
+/// Makes a hollow box that can serve as the missing glyph, for fonts that
+/// don't have one of their own. It sits on the baseline, and has one pixel
+/// of space after it.
+pub(super) fn make_box_glyph(width: u32, height: u32) -> LooseGlyph {
+    let mut bitmap = Bitmap::new(width, height);
+    bitmap.stroke_rect(ModeCopy(()), None, bitmap.get_bounds(), 1, 1);
+    LooseGlyph {
+        bitmap,
+        top: height as i32,
+        offset: 0,
+        advance: width + 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    const TEST_BDF: &str = "STARTFONT 2.1
+FONT -test-fixed-medium-r-normal--8-80-75-75-c-50-iso10646-1
+SIZE 8 75 75
+FONTBOUNDINGBOX 5 8 0 -1
+STARTPROPERTIES 2
+FONT_ASCENT 7
+FONT_DESCENT 1
+ENDPROPERTIES
+CHARS 2
+STARTCHAR A
+ENCODING 65
+SWIDTH 625 0
+DWIDTH 5 0
+BBX 4 5 0 0
+BITMAP
+60
+90
+F0
+90
+90
+ENDCHAR
+STARTCHAR uni2588
+ENCODING 9608
+SWIDTH 625 0
+DWIDTH 5 0
+BBX 5 9 0 -1
+BITMAP
+F8
+F8
+F8
+F8
+F8
+F8
+F8
+F8
+F8
+ENDCHAR
+ENDFONT
+";
+    #[test]
+    fn read_bdf() {
+        let font = Font::read_bdf(TEST_BDF.as_bytes()).unwrap();
+        // The full block sticks out one pixel above FONT_ASCENT.
+        assert_eq!(font.get_ascent(), 8);
+        assert_eq!(font.get_descent(), 1);
+        assert_eq!(font.get_leading(), -1);
+        let (rect, offset, advance, present) = font.get_glyph(65);
+        assert_eq!(
+            (rect.get_width(), offset, advance, present),
+            (4, 0, 5, true)
+        );
+        let (rect, _, advance, present) = font.get_glyph(0x2588);
+        assert_eq!((rect.get_width(), advance, present), (5, 5, true));
+        assert!(!font.get_glyph(66).3);
+        let mut bitmap = Bitmap::new(8, 10);
+        bitmap.draw_glyph(ModeCopy(()), None, 0, 8, &[&font], 65);
+        assert_eq!(
+            bitmap.to_bytes(),
+            &[0, 0, 0, 0x60, 0x90, 0xF0, 0x90, 0x90, 0, 0]
+        );
+    }
+    #[test]
+    fn bdf_round_trip() {
+        let mut font = Font::read_bdf(TEST_BDF.as_bytes()).unwrap();
+        font.glyph_offsetwidths[0].0 = -1;
+        let mut bdf = Vec::new();
+        font.write_bdf(&mut bdf, "test").unwrap();
+        let read = Font::read_bdf(&bdf[..]).unwrap();
+        super::super::test::assert_same_glyphs(&font, &read);
+        // the full block's extra row of ascent survives, but the
+        // corresponding negative leading doesn't
+        assert_eq!(read.get_leading(), 0);
+    }
+}
@@ -0,0 +1,1234 @@
+use std::{cmp::Ordering, ops::Range};
+
+use super::*;
+
+/// The bidirectional character types of the Unicode Bidirectional Algorithm
+/// (Unicode Standard Annex #9).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BidiClass {
+    /// Strong left-to-right: most letters.
+    L,
+    /// Strong right-to-left: Hebrew, and other non-Arabic RTL scripts.
+    R,
+    /// Strong right-to-left: Arabic, Syriac, and Thaana.
+    AL,
+    /// European number: digits.
+    EN,
+    /// European number separator: plus and minus.
+    ES,
+    /// European number terminator: degrees, currency symbols, and the like.
+    ET,
+    /// Arabic number: Arabic-Indic digits.
+    AN,
+    /// Common number separator: comma, period, colon, and the like.
+    CS,
+    /// Nonspacing mark: combining accents and vowel points.
+    NSM,
+    /// Boundary neutral: control characters and invisible formatting.
+    BN,
+    /// Paragraph separator.
+    B,
+    /// Segment separator: tabs.
+    S,
+    /// Whitespace.
+    WS,
+    /// Other neutral: most punctuation and symbols.
+    ON,
+    /// Left-to-right embedding.
+    LRE,
+    /// Left-to-right override.
+    LRO,
+    /// Right-to-left embedding.
+    RLE,
+    /// Right-to-left override.
+    RLO,
+    /// Pop directional format.
+    PDF,
+    /// Left-to-right isolate.
+    LRI,
+    /// Right-to-left isolate.
+    RLI,
+    /// First strong isolate.
+    FSI,
+    /// Pop directional isolate.
+    PDI,
+}
+
+use BidiClass::*;
+
+impl BidiClass {
+    /// Returns the bidirectional type of the given character.
+    ///
+    /// This uses a built-in table that covers the scripts likely to turn up
+    /// in bitmap fonts: Latin, Greek, Cyrillic, Hebrew, Arabic, Syriac,
+    /// Thaana, N'Ko, and the other right-to-left scripts, along with general
+    /// and CJK punctuation, symbols, and combining marks. Characters it
+    /// doesn't cover are treated as strong left-to-right, which most of them
+    /// are.
+    pub fn of(c: char) -> BidiClass {
+        let c = c as u32;
+        BIDI_CLASSES
+            .binary_search_by(|(first, last, _)| {
+                if *last < c {
+                    Ordering::Less
+                } else if *first > c {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .map(|n| BIDI_CLASSES[n].2)
+            .unwrap_or(L)
+    }
+    /// Returns true for the characters that rule X9 removes: the explicit
+    /// embeddings and overrides, and boundary neutrals.
+    fn is_removed(self) -> bool {
+        matches!(self, RLE | LRE | RLO | LRO | PDF | BN)
+    }
+    /// Returns true for the isolate initiators.
+    fn is_isolate_initiator(self) -> bool {
+        matches!(self, LRI | RLI | FSI)
+    }
+    /// Returns true for the neutral and isolate formatting types, which are
+    /// resolved by rules N1 and N2.
+    fn is_neutral(self) -> bool {
+        matches!(self, B | S | WS | ON | LRI | RLI | FSI | PDI)
+    }
+    /// Returns the direction this type counts as for rules N0 through N2
+    /// (numbers count as right-to-left), or `None` if it's not strong.
+    fn get_strong(self) -> Option<BidiClass> {
+        match self {
+            L => Some(L),
+            R | AL | EN | AN => Some(R),
+            _ => None,
+        }
+    }
+}
+
+/// One paragraph of text, with the embedding level of every character
+/// resolved by the Unicode Bidirectional Algorithm.
+///
+/// Everything up to, but not including, rule L1 is done once for the whole
+/// paragraph. The rest depends on where the paragraph is broken into lines,
+/// and is done by `reorder_line`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BidiParagraph {
+    /// The byte range of the source text covered by this paragraph,
+    /// including the paragraph separator that ended it, if any.
+    pub text_range: Range<usize>,
+    /// The paragraph's base direction, either as given or as detected from
+    /// its first strong character.
+    pub direction: Direction,
+    /// The byte offset, within the source text, of every character.
+    offsets: Vec<usize>,
+    /// The original type of every character.
+    classes: Vec<BidiClass>,
+    /// The resolved embedding level of every character. Even levels are
+    /// left to right, odd levels are right to left.
+    levels: Vec<u8>,
+}
+
+impl BidiParagraph {
+    /// Splits the given text into paragraphs, and resolves the embedding
+    /// levels of each one. A paragraph separator (such as `'\n'`) is kept
+    /// with the paragraph it ends, and `"\r\n"` counts as one separator.
+    ///
+    /// `direction` is the base direction of every paragraph, or `None` to
+    /// detect each one from its first strong character (defaulting to left
+    /// to right if there are none).
+    ///
+    /// Explicit embeddings, overrides, and isolates are all supported, as are
+    /// bracket pairs.
+    pub fn split(
+        text: &str,
+        direction: Option<Direction>,
+    ) -> Vec<BidiParagraph> {
+        let mut ret = vec![];
+        let mut chars = text.char_indices().peekable();
+        while chars.peek().is_some() {
+            let mut offsets = vec![];
+            let mut text_chars = vec![];
+            let mut classes = vec![];
+            while let Some((offset, c)) = chars.next() {
+                offsets.push(offset);
+                text_chars.push(c);
+                classes.push(BidiClass::of(c));
+                if c == '\r' && chars.peek().map(|(_, c)| *c) == Some('\n') {
+                    continue;
+                }
+                if classes.last() == Some(&B) {
+                    break;
+                }
+            }
+            let end = chars.peek().map(|(x, _)| *x).unwrap_or(text.len());
+            let (direction, levels) =
+                resolve_levels(&text_chars, &classes, direction);
+            ret.push(BidiParagraph {
+                text_range: offsets[0]..end,
+                direction,
+                offsets,
+                classes,
+                levels,
+            });
+        }
+        ret
+    }
+    /// Returns the byte offset and resolved embedding level of every
+    /// character in the paragraph, in logical order. These are the levels
+    /// before any line is reordered, so trailing whitespace hasn't been
+    /// reset to the paragraph level yet.
+    pub fn get_levels(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        self.offsets
+            .iter()
+            .copied()
+            .zip(self.levels.iter().copied())
+    }
+    /// Returns the characters of one line of this paragraph in visual order,
+    /// from left to right, as byte offsets into the source text along with
+    /// their final embedding levels. `line` is a byte range of the source
+    /// text that should lie within `text_range`. Characters on an odd level
+    /// should be drawn with mirrored glyphs.
+    ///
+    /// Pass `text_range` to reorder the whole paragraph as a single line.
+    pub fn reorder_line(&self, line: Range<usize>) -> Vec<(usize, u8)> {
+        let start = self.offsets.partition_point(|x| *x < line.start);
+        let end = self.offsets.partition_point(|x| *x < line.end);
+        let paragraph_level = self.get_paragraph_level();
+        // L1: separators, and whitespace before them or at the end of the
+        // line, go back to the paragraph level.
+        let mut levels = self.levels[start..end].to_vec();
+        let mut trailing = true;
+        for (level, class) in
+            levels.iter_mut().zip(&self.classes[start..end]).rev()
+        {
+            match class {
+                B | S => {
+                    *level = paragraph_level;
+                    trailing = true;
+                }
+                WS | LRI | RLI | FSI | PDI if trailing => {
+                    *level = paragraph_level;
+                }
+                class if class.is_removed() && trailing => {
+                    *level = paragraph_level;
+                }
+                _ => trailing = false,
+            }
+        }
+        // L2: from the highest level down to the lowest odd level, reverse
+        // every run of characters at that level or higher.
+        let mut order: Vec<usize> = (0..levels.len()).collect();
+        let highest = levels.iter().copied().max().unwrap_or(0);
+        let lowest_odd = levels.iter().copied().min().unwrap_or(0) | 1;
+        for level in (lowest_odd..=highest).rev() {
+            let mut n = 0;
+            while n < order.len() {
+                if levels[order[n]] < level {
+                    n += 1;
+                    continue;
+                }
+                let run_start = n;
+                while n < order.len() && levels[order[n]] >= level {
+                    n += 1;
+                }
+                order[run_start..n].reverse();
+            }
+        }
+        order
+            .into_iter()
+            .map(|n| (self.offsets[start + n], levels[n]))
+            .collect()
+    }
+    fn get_paragraph_level(&self) -> u8 {
+        match self.direction {
+            Direction::LeftToRight => 0,
+            Direction::RightToLeft => 1,
+        }
+    }
+}
+
+/// Like `encode_text`, but for text that may contain right-to-left
+/// characters.
+///
+/// Each paragraph is run through the Unicode Bidirectional Algorithm (see
+/// `BidiParagraph`), and its glyphs are emitted in visual order. A
+/// right-to-left paragraph is preceded by
+/// `TextElement::SetDirection(Direction::RightToLeft)` and drawn leftward
+/// from the pen, so that its right edge is at the starting pen position;
+/// a left-to-right paragraph is drawn as usual. Paragraph separators become
+/// `TextElement::NewLine`.
+///
+/// Characters on right-to-left runs are swapped for their mirror images
+/// (so that `(` becomes `)`), and the invisible directional formatting
+/// characters are not drawn at all.
+pub fn encode_text_bidi(
+    encoding: impl EncodingTrait,
+    text: &str,
+    direction: Option<Direction>,
+) -> Vec<TextElement> {
+    let mut ret = vec![];
+    for paragraph in BidiParagraph::split(text, direction) {
+        ret.push(TextElement::SetDirection(paragraph.direction));
+        let mut order = paragraph.reorder_line(paragraph.text_range.clone());
+        if paragraph.direction == Direction::RightToLeft {
+            order.reverse();
+        }
+        let mut ends_line = false;
+        for (offset, level) in order {
+            let c = text[offset..].chars().next().unwrap();
+            match BidiClass::of(c) {
+                B => ends_line = true,
+                class if class.is_removed() => (),
+                LRI | RLI | FSI | PDI => (),
+                _ => {
+                    let c = if level % 2 == 1 { mirror(c) } else { c };
+                    ret.push(TextElement::DrawGlyph(
+                        encoding.encode_char(c).unwrap_or(MISSING_GLYPH),
+                    ));
+                }
+            }
+        }
+        if ends_line {
+            ret.push(TextElement::NewLine);
+        }
+    }
+    ret
+}
+
+/// The deepest embedding level allowed by rules X1 through X8.
+const MAX_DEPTH: u8 = 125;
+
+/// The deepest nesting of bracket pairs that rule BD16 keeps track of.
+const MAX_BRACKET_DEPTH: usize = 63;
+
+/// One entry in the directional status stack of rules X1 through X8.
+#[derive(Clone, Copy)]
+struct DirectionalStatus {
+    level: u8,
+    override_class: Option<BidiClass>,
+    isolate: bool,
+}
+
+/// Applies rules P2 through I2 to a single paragraph, returning its base
+/// direction and the resolved embedding level of every character.
+fn resolve_levels(
+    chars: &[char],
+    classes: &[BidiClass],
+    direction: Option<Direction>,
+) -> (Direction, Vec<u8>) {
+    // P2, P3
+    let direction = direction
+        .or_else(|| first_strong(classes))
+        .unwrap_or_default();
+    let paragraph_level = match direction {
+        Direction::LeftToRight => 0,
+        Direction::RightToLeft => 1,
+    };
+    let matching_pdis = match_isolates(classes);
+    let mut types = classes.to_vec();
+    let mut levels = vec![paragraph_level; classes.len()];
+    resolve_explicit(
+        classes,
+        &matching_pdis,
+        paragraph_level,
+        &mut types,
+        &mut levels,
+    );
+    // X10 compares each sequence with the embedding levels of its
+    // neighbors, not the levels they resolve to, so keep a copy of them.
+    let explicit = levels.clone();
+    for sequence in isolating_run_sequences(classes, &matching_pdis, &explicit)
+    {
+        let level = explicit[sequence[0]];
+        let first = sequence[0];
+        let last = *sequence.last().unwrap();
+        // Compare with the nearest characters outside the sequence that X9
+        // didn't remove.
+        let before = (0..first)
+            .rev()
+            .find(|n| !classes[*n].is_removed())
+            .map(|n| explicit[n])
+            .unwrap_or(paragraph_level);
+        let after = if classes[last].is_isolate_initiator() {
+            paragraph_level
+        } else {
+            (last + 1..classes.len())
+                .find(|n| !classes[*n].is_removed())
+                .map(|n| explicit[n])
+                .unwrap_or(paragraph_level)
+        };
+        let boundary = |other: u8| {
+            if level.max(other) % 2 == 1 {
+                R
+            } else {
+                L
+            }
+        };
+        resolve_sequence(
+            &sequence,
+            chars,
+            classes,
+            &mut types,
+            level,
+            (boundary(before), boundary(after)),
+        );
+        // I1, I2
+        for n in sequence {
+            levels[n] = match (levels[n] % 2, types[n]) {
+                (0, R) => levels[n] + 1,
+                (0, AN | EN) => levels[n] + 2,
+                (1, L | EN | AN) => levels[n] + 1,
+                _ => levels[n],
+            };
+        }
+    }
+    // Characters removed by X9 take on the level of the character before
+    // them, so that they don't break up runs when reordering.
+    for n in 0..classes.len() {
+        if classes[n].is_removed() {
+            levels[n] = if n == 0 {
+                paragraph_level
+            } else {
+                levels[n - 1]
+            };
+        }
+    }
+    (direction, levels)
+}
+
+/// Finds the direction of the first strong character, skipping over
+/// isolates (rule P2).
+fn first_strong(classes: &[BidiClass]) -> Option<Direction> {
+    let mut isolates = 0;
+    for class in classes {
+        match class {
+            L if isolates == 0 => return Some(Direction::LeftToRight),
+            R | AL if isolates == 0 => return Some(Direction::RightToLeft),
+            LRI | RLI | FSI => isolates += 1,
+            PDI if isolates > 0 => isolates -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Finds the matching PDI of every isolate initiator (rule BD9).
+fn match_isolates(classes: &[BidiClass]) -> Vec<Option<usize>> {
+    let mut ret = vec![None; classes.len()];
+    let mut open = vec![];
+    for (n, class) in classes.iter().enumerate() {
+        match class {
+            LRI | RLI | FSI => open.push(n),
+            PDI => {
+                if let Some(initiator) = open.pop() {
+                    ret[initiator] = Some(n);
+                }
+            }
+            _ => (),
+        }
+    }
+    ret
+}
+
+/// Applies the explicit embeddings, overrides, and isolates (rules X1
+/// through X8).
+fn resolve_explicit(
+    classes: &[BidiClass],
+    matching_pdis: &[Option<usize>],
+    paragraph_level: u8,
+    types: &mut [BidiClass],
+    levels: &mut [u8],
+) {
+    let next_level = |level: u8, rtl: bool| {
+        if rtl {
+            (level + 1) | 1
+        } else {
+            (level + 2) & !1
+        }
+    };
+    let mut stack = vec![DirectionalStatus {
+        level: paragraph_level,
+        override_class: None,
+        isolate: false,
+    }];
+    let mut overflow_isolates = 0;
+    let mut overflow_embeddings = 0;
+    let mut valid_isolates = 0;
+    for (n, class) in classes.iter().enumerate() {
+        let top = *stack.last().unwrap();
+        match class {
+            RLE | LRE | RLO | LRO => {
+                levels[n] = top.level;
+                let level = next_level(top.level, matches!(class, RLE | RLO));
+                if level <= MAX_DEPTH
+                    && overflow_isolates == 0
+                    && overflow_embeddings == 0
+                {
+                    stack.push(DirectionalStatus {
+                        level,
+                        override_class: match class {
+                            RLO => Some(R),
+                            LRO => Some(L),
+                            _ => None,
+                        },
+                        isolate: false,
+                    });
+                } else if overflow_isolates == 0 {
+                    overflow_embeddings += 1;
+                }
+            }
+            RLI | LRI | FSI => {
+                levels[n] = top.level;
+                if let Some(override_class) = top.override_class {
+                    types[n] = override_class;
+                }
+                let rtl = match class {
+                    RLI => true,
+                    LRI => false,
+                    _ => {
+                        let end = matching_pdis[n].unwrap_or(classes.len());
+                        first_strong(&classes[n + 1..end])
+                            == Some(Direction::RightToLeft)
+                    }
+                };
+                let level = next_level(top.level, rtl);
+                if level <= MAX_DEPTH
+                    && overflow_isolates == 0
+                    && overflow_embeddings == 0
+                {
+                    valid_isolates += 1;
+                    stack.push(DirectionalStatus {
+                        level,
+                        override_class: None,
+                        isolate: true,
+                    });
+                } else {
+                    overflow_isolates += 1;
+                }
+            }
+            PDI => {
+                if overflow_isolates > 0 {
+                    overflow_isolates -= 1;
+                } else if valid_isolates > 0 {
+                    overflow_embeddings = 0;
+                    while !stack.last().unwrap().isolate {
+                        stack.pop();
+                    }
+                    stack.pop();
+                    valid_isolates -= 1;
+                }
+                let top = *stack.last().unwrap();
+                levels[n] = top.level;
+                if let Some(override_class) = top.override_class {
+                    types[n] = override_class;
+                }
+            }
+            PDF => {
+                levels[n] = top.level;
+                if overflow_isolates > 0 {
+                    // inside an isolate that overflowed; nothing to pop
+                } else if overflow_embeddings > 0 {
+                    overflow_embeddings -= 1;
+                } else if !top.isolate && stack.len() >= 2 {
+                    stack.pop();
+                }
+            }
+            B => levels[n] = paragraph_level,
+            BN => levels[n] = top.level,
+            _ => {
+                levels[n] = top.level;
+                if let Some(override_class) = top.override_class {
+                    types[n] = override_class;
+                }
+            }
+        }
+    }
+}
+
+/// Divides the paragraph into isolating run sequences (rules X9 and X10).
+/// Each sequence is a list of character indices, in logical order, skipping
+/// the characters removed by X9.
+fn isolating_run_sequences(
+    classes: &[BidiClass],
+    matching_pdis: &[Option<usize>],
+    levels: &[u8],
+) -> Vec<Vec<usize>> {
+    let mut runs: Vec<Vec<usize>> = vec![];
+    for n in (0..classes.len()).filter(|n| !classes[*n].is_removed()) {
+        match runs.last_mut() {
+            Some(run) if levels[*run.last().unwrap()] == levels[n] => {
+                run.push(n)
+            }
+            _ => runs.push(vec![n]),
+        }
+    }
+    let run_starting_at: HashMap<usize, usize> = runs
+        .iter()
+        .enumerate()
+        .map(|(index, run)| (run[0], index))
+        .collect();
+    let mut used = vec![false; runs.len()];
+    let mut ret = vec![];
+    for index in 0..runs.len() {
+        if used[index] {
+            continue;
+        }
+        let mut sequence = runs[index].clone();
+        // An isolate initiator at the end of a run continues the sequence
+        // with the run that starts with its matching PDI.
+        while let Some(next) = matching_pdis[*sequence.last().unwrap()]
+            .and_then(|pdi| run_starting_at.get(&pdi))
+        {
+            if used[*next] {
+                break;
+            }
+            used[*next] = true;
+            sequence.extend_from_slice(&runs[*next]);
+        }
+        ret.push(sequence);
+    }
+    ret
+}
+
+/// Resolves the weak types, bracket pairs, and neutrals of one isolating run
+/// sequence (rules W1 through N2). `level` is the embedding level of the
+/// sequence, and `sos` and `eos` are the directions (`L` or `R`) at its start
+/// and end.
+fn resolve_sequence(
+    sequence: &[usize],
+    chars: &[char],
+    classes: &[BidiClass],
+    types: &mut [BidiClass],
+    level: u8,
+    (sos, eos): (BidiClass, BidiClass),
+) {
+    let len = sequence.len();
+    let embedding = if level % 2 == 1 { R } else { L };
+    let get = |types: &[BidiClass], n: usize| types[sequence[n]];
+    // W1: nonspacing marks take on the type of the character before them.
+    for n in 0..len {
+        if get(types, n) == NSM {
+            types[sequence[n]] = match n.checked_sub(1).map(|x| get(types, x))
+            {
+                None => sos,
+                Some(LRI | RLI | FSI | PDI) => ON,
+                Some(previous) => previous,
+            };
+        }
+    }
+    // W2: European numbers after Arabic letters are Arabic numbers.
+    let mut last_strong = sos;
+    for n in sequence.iter().copied() {
+        match types[n] {
+            L | R | AL => last_strong = types[n],
+            EN if last_strong == AL => types[n] = AN,
+            _ => (),
+        }
+    }
+    // W3: Arabic letters are right-to-left.
+    for n in sequence.iter().copied() {
+        if types[n] == AL {
+            types[n] = R;
+        }
+    }
+    // W4: a single separator between two numbers of the same kind joins
+    // them.
+    for n in 1..len.saturating_sub(1) {
+        match (get(types, n - 1), get(types, n), get(types, n + 1)) {
+            (EN, ES | CS, EN) => types[sequence[n]] = EN,
+            (AN, CS, AN) => types[sequence[n]] = AN,
+            _ => (),
+        }
+    }
+    // W5: terminators next to European numbers become part of them.
+    let mut n = 0;
+    while n < len {
+        if get(types, n) != ET {
+            n += 1;
+            continue;
+        }
+        let run_start = n;
+        while n < len && get(types, n) == ET {
+            n += 1;
+        }
+        if (run_start > 0 && get(types, run_start - 1) == EN)
+            || (n < len && get(types, n) == EN)
+        {
+            for x in &sequence[run_start..n] {
+                types[*x] = EN;
+            }
+        }
+    }
+    // W6: any other separators and terminators are neutral.
+    for n in sequence.iter().copied() {
+        if matches!(types[n], ES | ET | CS) {
+            types[n] = ON;
+        }
+    }
+    // W7: European numbers in left-to-right text are left-to-right.
+    let mut last_strong = sos;
+    for n in sequence.iter().copied() {
+        match types[n] {
+            L | R => last_strong = types[n],
+            EN if last_strong == L => types[n] = L,
+            _ => (),
+        }
+    }
+    // N0: bracket pairs take on the direction of their contents, if it's
+    // consistent with the embedding direction or the context.
+    for (open, close) in find_bracket_pairs(sequence, chars, types) {
+        let mut found_embedding = false;
+        let mut found_opposite = false;
+        for n in open + 1..close {
+            match get(types, n).get_strong() {
+                Some(x) if x == embedding => found_embedding = true,
+                Some(_) => found_opposite = true,
+                None => (),
+            }
+        }
+        let class = if found_embedding {
+            embedding
+        } else if found_opposite {
+            (0..open)
+                .rev()
+                .find_map(|n| get(types, n).get_strong())
+                .unwrap_or(sos)
+        } else {
+            continue;
+        };
+        for bracket in [open, close] {
+            types[sequence[bracket]] = class;
+            // Marks on the bracket go along with it.
+            for n in sequence[bracket + 1..].iter().copied() {
+                if classes[n] != NSM {
+                    break;
+                }
+                types[n] = class;
+            }
+        }
+    }
+    // N1, N2: neutrals between two characters of the same direction take on
+    // that direction. Any others take on the embedding direction.
+    let mut n = 0;
+    while n < len {
+        if !get(types, n).is_neutral() {
+            n += 1;
+            continue;
+        }
+        let run_start = n;
+        while n < len && get(types, n).is_neutral() {
+            n += 1;
+        }
+        let before = match run_start {
+            0 => sos,
+            _ => get(types, run_start - 1).get_strong().unwrap(),
+        };
+        let after = match n {
+            _ if n == len => eos,
+            _ => get(types, n).get_strong().unwrap(),
+        };
+        let class = if before == after { before } else { embedding };
+        for x in &sequence[run_start..n] {
+            types[*x] = class;
+        }
+    }
+}
+
+/// Finds the bracket pairs in an isolating run sequence (rule BD16).
+/// Returns the positions, within the sequence, of each pair's opening and
+/// closing bracket, sorted by the opening bracket.
+fn find_bracket_pairs(
+    sequence: &[usize],
+    chars: &[char],
+    types: &[BidiClass],
+) -> Vec<(usize, usize)> {
+    // U+2329 and U+232A are canonically equivalent to U+3008 and U+3009.
+    let canonical = |c: char| match c {
+        '\u{2329}' => '\u{3008}',
+        '\u{232A}' => '\u{3009}',
+        c => c,
+    };
+    let mut stack: Vec<(char, usize)> = vec![];
+    let mut ret = vec![];
+    for (position, n) in sequence.iter().copied().enumerate() {
+        if types[n] != ON {
+            continue;
+        }
+        let c = canonical(chars[n]);
+        if let Some((_, close)) = BRACKETS.iter().find(|(x, _)| *x == c) {
+            if stack.len() == MAX_BRACKET_DEPTH {
+                break;
+            }
+            stack.push((canonical(*close), position));
+        } else if BRACKETS.iter().any(|(_, x)| *x == c) {
+            if let Some(depth) = stack.iter().rposition(|(x, _)| *x == c) {
+                ret.push((stack[depth].1, position));
+                stack.truncate(depth);
+            }
+        }
+    }
+    ret.sort_unstable();
+    ret
+}
+
+/// Returns the mirror image of the given character, if it has one (rule L4),
+/// or the character itself if it doesn't.
+fn mirror(c: char) -> char {
+    BRACKETS
+        .iter()
+        .chain(MIRRORED.iter())
+        .find_map(|(a, b)| {
+            if *a == c {
+                Some(*b)
+            } else if *b == c {
+                Some(*a)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(c)
+}
+
+/// Ranges of characters and their bidirectional types, sorted and not
+/// overlapping. Anything not in here is `L`.
+const BIDI_CLASSES: &[(u32, u32, BidiClass)] = &[
+    (0x0000, 0x0008, BN),
+    (0x0009, 0x0009, S),
+    (0x000A, 0x000A, B),
+    (0x000B, 0x000B, S),
+    (0x000C, 0x000C, WS),
+    (0x000D, 0x000D, B),
+    (0x000E, 0x001B, BN),
+    (0x001C, 0x001E, B),
+    (0x001F, 0x001F, S),
+    (0x0020, 0x0020, WS),
+    (0x0021, 0x0022, ON),
+    (0x0023, 0x0025, ET),
+    (0x0026, 0x002A, ON),
+    (0x002B, 0x002B, ES),
+    (0x002C, 0x002C, CS),
+    (0x002D, 0x002D, ES),
+    (0x002E, 0x002F, CS),
+    (0x0030, 0x0039, EN),
+    (0x003A, 0x003A, CS),
+    (0x003B, 0x0040, ON),
+    (0x005B, 0x0060, ON),
+    (0x007B, 0x007E, ON),
+    (0x007F, 0x0084, BN),
+    (0x0085, 0x0085, B),
+    (0x0086, 0x009F, BN),
+    (0x00A0, 0x00A0, CS),
+    (0x00A1, 0x00A1, ON),
+    (0x00A2, 0x00A5, ET),
+    (0x00A6, 0x00A9, ON),
+    (0x00AB, 0x00AC, ON),
+    (0x00AD, 0x00AD, BN),
+    (0x00AE, 0x00AF, ON),
+    (0x00B0, 0x00B1, ET),
+    (0x00B2, 0x00B3, EN),
+    (0x00B4, 0x00B4, ON),
+    (0x00B6, 0x00B8, ON),
+    (0x00B9, 0x00B9, EN),
+    (0x00BB, 0x00BF, ON),
+    (0x00D7, 0x00D7, ON),
+    (0x00F7, 0x00F7, ON),
+    (0x02B9, 0x02BA, ON),
+    (0x02C2, 0x02CF, ON),
+    (0x02D2, 0x02DF, ON),
+    (0x02E5, 0x02ED, ON),
+    (0x02EF, 0x02FF, ON),
+    (0x0300, 0x036F, NSM),
+    (0x0374, 0x0375, ON),
+    (0x037E, 0x037E, ON),
+    (0x0384, 0x0385, ON),
+    (0x0387, 0x0387, ON),
+    (0x03F6, 0x03F6, ON),
+    (0x0483, 0x0489, NSM),
+    (0x058A, 0x058A, ON),
+    (0x058D, 0x058E, ON),
+    (0x058F, 0x058F, ET),
+    (0x0590, 0x0590, R),
+    (0x0591, 0x05BD, NSM),
+    (0x05BE, 0x05BE, R),
+    (0x05BF, 0x05BF, NSM),
+    (0x05C0, 0x05C0, R),
+    (0x05C1, 0x05C2, NSM),
+    (0x05C3, 0x05C3, R),
+    (0x05C4, 0x05C5, NSM),
+    (0x05C6, 0x05C6, R),
+    (0x05C7, 0x05C7, NSM),
+    (0x05C8, 0x05FF, R),
+    (0x0600, 0x0605, AN),
+    (0x0606, 0x0607, ON),
+    (0x0608, 0x0608, AL),
+    (0x0609, 0x060A, ET),
+    (0x060B, 0x060B, AL),
+    (0x060C, 0x060C, CS),
+    (0x060D, 0x060D, AL),
+    (0x060E, 0x060F, ON),
+    (0x0610, 0x061A, NSM),
+    (0x061B, 0x064A, AL),
+    (0x064B, 0x065F, NSM),
+    (0x0660, 0x0669, AN),
+    (0x066A, 0x066A, ET),
+    (0x066B, 0x066C, AN),
+    (0x066D, 0x066F, AL),
+    (0x0670, 0x0670, NSM),
+    (0x0671, 0x06D5, AL),
+    (0x06D6, 0x06DC, NSM),
+    (0x06DD, 0x06DD, AN),
+    (0x06DE, 0x06DE, ON),
+    (0x06DF, 0x06E4, NSM),
+    (0x06E5, 0x06E6, AL),
+    (0x06E7, 0x06E8, NSM),
+    (0x06E9, 0x06E9, ON),
+    (0x06EA, 0x06ED, NSM),
+    (0x06EE, 0x06EF, AL),
+    (0x06F0, 0x06F9, EN),
+    (0x06FA, 0x0710, AL),
+    (0x0711, 0x0711, NSM),
+    (0x0712, 0x072F, AL),
+    (0x0730, 0x074A, NSM),
+    (0x074B, 0x07A5, AL),
+    (0x07A6, 0x07B0, NSM),
+    (0x07B1, 0x07BF, AL),
+    (0x07C0, 0x07EA, R),
+    (0x07EB, 0x07F3, NSM),
+    (0x07F4, 0x07F5, R),
+    (0x07F6, 0x07F9, ON),
+    (0x07FA, 0x07FC, R),
+    (0x07FD, 0x07FD, NSM),
+    (0x07FE, 0x0815, R),
+    (0x0816, 0x0819, NSM),
+    (0x081A, 0x081A, R),
+    (0x081B, 0x0823, NSM),
+    (0x0824, 0x0824, R),
+    (0x0825, 0x0827, NSM),
+    (0x0828, 0x0828, R),
+    (0x0829, 0x082D, NSM),
+    (0x082E, 0x0858, R),
+    (0x0859, 0x085B, NSM),
+    (0x085C, 0x085F, R),
+    (0x0860, 0x088F, AL),
+    (0x0890, 0x0891, AN),
+    (0x0892, 0x0897, AL),
+    (0x0898, 0x089F, NSM),
+    (0x08A0, 0x08C9, AL),
+    (0x08CA, 0x08E1, NSM),
+    (0x08E2, 0x08E2, AN),
+    (0x08E3, 0x0902, NSM),
+    (0x1680, 0x1680, WS),
+    (0x180E, 0x180E, BN),
+    (0x2000, 0x200A, WS),
+    (0x200B, 0x200D, BN),
+    (0x200F, 0x200F, R),
+    (0x2010, 0x2027, ON),
+    (0x2028, 0x2028, WS),
+    (0x2029, 0x2029, B),
+    (0x202A, 0x202A, LRE),
+    (0x202B, 0x202B, RLE),
+    (0x202C, 0x202C, PDF),
+    (0x202D, 0x202D, LRO),
+    (0x202E, 0x202E, RLO),
+    (0x202F, 0x202F, CS),
+    (0x2030, 0x2034, ET),
+    (0x2035, 0x2043, ON),
+    (0x2044, 0x2044, CS),
+    (0x2045, 0x205E, ON),
+    (0x205F, 0x205F, WS),
+    (0x2060, 0x2065, BN),
+    (0x2066, 0x2066, LRI),
+    (0x2067, 0x2067, RLI),
+    (0x2068, 0x2068, FSI),
+    (0x2069, 0x2069, PDI),
+    (0x206A, 0x206F, BN),
+    (0x2070, 0x2070, EN),
+    (0x2074, 0x2079, EN),
+    (0x207A, 0x207B, ES),
+    (0x207C, 0x207E, ON),
+    (0x2080, 0x2089, EN),
+    (0x208A, 0x208B, ES),
+    (0x208C, 0x208E, ON),
+    (0x20A0, 0x20CF, ET),
+    (0x20D0, 0x20F0, NSM),
+    (0x2100, 0x2101, ON),
+    (0x2103, 0x2106, ON),
+    (0x2108, 0x2109, ON),
+    (0x2114, 0x2114, ON),
+    (0x2116, 0x2118, ON),
+    (0x211E, 0x2123, ON),
+    (0x2125, 0x2125, ON),
+    (0x2127, 0x2127, ON),
+    (0x2129, 0x2129, ON),
+    (0x212E, 0x212E, ET),
+    (0x213A, 0x213B, ON),
+    (0x2140, 0x2144, ON),
+    (0x214A, 0x214D, ON),
+    (0x2150, 0x215F, ON),
+    (0x2189, 0x218B, ON),
+    (0x2190, 0x2211, ON),
+    (0x2212, 0x2212, ES),
+    (0x2213, 0x2213, ET),
+    (0x2214, 0x2335, ON),
+    (0x237B, 0x2394, ON),
+    (0x2396, 0x2426, ON),
+    (0x2440, 0x244A, ON),
+    (0x2460, 0x2487, ON),
+    (0x2488, 0x249B, EN),
+    (0x24EA, 0x26AB, ON),
+    (0x26AD, 0x27FF, ON),
+    (0x2900, 0x2B73, ON),
+    (0x2B76, 0x2B95, ON),
+    (0x2B97, 0x2BFF, ON),
+    (0x2CE5, 0x2CEA, ON),
+    (0x2CEF, 0x2CF1, NSM),
+    (0x2CF9, 0x2CFF, ON),
+    (0x2DE0, 0x2DFF, NSM),
+    (0x2E00, 0x2E5D, ON),
+    (0x2E80, 0x2FFF, ON),
+    (0x3000, 0x3000, WS),
+    (0x3001, 0x3004, ON),
+    (0x3008, 0x3020, ON),
+    (0x302A, 0x302D, NSM),
+    (0x3030, 0x3030, ON),
+    (0x3036, 0x3037, ON),
+    (0x303D, 0x303F, ON),
+    (0x3099, 0x309A, NSM),
+    (0x309B, 0x309C, ON),
+    (0x30A0, 0x30A0, ON),
+    (0x30FB, 0x30FB, ON),
+    (0xA490, 0xA4C6, ON),
+    (0xA60D, 0xA60F, ON),
+    (0xA66F, 0xA672, NSM),
+    (0xA673, 0xA673, ON),
+    (0xA674, 0xA67D, NSM),
+    (0xA67E, 0xA67F, ON),
+    (0xA69E, 0xA69F, NSM),
+    (0xA6F0, 0xA6F1, NSM),
+    (0xA700, 0xA721, ON),
+    (0xA788, 0xA788, ON),
+    (0xFB1D, 0xFB1D, R),
+    (0xFB1E, 0xFB1E, NSM),
+    (0xFB1F, 0xFB28, R),
+    (0xFB29, 0xFB29, ES),
+    (0xFB2A, 0xFB4F, R),
+    (0xFB50, 0xFD3D, AL),
+    (0xFD3E, 0xFD4F, ON),
+    (0xFD50, 0xFDCE, AL),
+    (0xFDCF, 0xFDCF, ON),
+    (0xFDD0, 0xFDEF, BN),
+    (0xFDF0, 0xFDFC, AL),
+    (0xFDFD, 0xFDFF, ON),
+    (0xFE00, 0xFE0F, NSM),
+    (0xFE10, 0xFE19, ON),
+    (0xFE20, 0xFE2F, NSM),
+    (0xFE30, 0xFE4F, ON),
+    (0xFE50, 0xFE50, CS),
+    (0xFE51, 0xFE51, ON),
+    (0xFE52, 0xFE52, CS),
+    (0xFE54, 0xFE54, ON),
+    (0xFE55, 0xFE55, CS),
+    (0xFE56, 0xFE5E, ON),
+    (0xFE5F, 0xFE5F, ET),
+    (0xFE60, 0xFE61, ON),
+    (0xFE62, 0xFE63, ES),
+    (0xFE64, 0xFE66, ON),
+    (0xFE68, 0xFE68, ON),
+    (0xFE69, 0xFE6A, ET),
+    (0xFE6B, 0xFE6B, ON),
+    (0xFE70, 0xFEFE, AL),
+    (0xFEFF, 0xFEFF, BN),
+    (0xFF01, 0xFF02, ON),
+    (0xFF03, 0xFF05, ET),
+    (0xFF06, 0xFF0A, ON),
+    (0xFF0B, 0xFF0B, ES),
+    (0xFF0C, 0xFF0C, CS),
+    (0xFF0D, 0xFF0D, ES),
+    (0xFF0E, 0xFF0F, CS),
+    (0xFF10, 0xFF19, EN),
+    (0xFF1A, 0xFF1A, CS),
+    (0xFF1B, 0xFF20, ON),
+    (0xFF3B, 0xFF40, ON),
+    (0xFF5B, 0xFF65, ON),
+    (0xFFE0, 0xFFE1, ET),
+    (0xFFE2, 0xFFE4, ON),
+    (0xFFE5, 0xFFE6, ET),
+    (0xFFE8, 0xFFEE, ON),
+    (0xFFF0, 0xFFF8, BN),
+    (0xFFF9, 0xFFFD, ON),
+    (0xFFFE, 0xFFFF, BN),
+    (0x10800, 0x10CFF, R),
+    (0x10D00, 0x10D23, AL),
+    (0x10D24, 0x10D27, NSM),
+    (0x10D30, 0x10D39, AN),
+    (0x10D40, 0x10E5F, R),
+    (0x10E60, 0x10E7E, AN),
+    (0x10E80, 0x10EFF, R),
+    (0x10F00, 0x10F2F, R),
+    (0x10F30, 0x10F45, AL),
+    (0x10F46, 0x10F50, NSM),
+    (0x10F51, 0x10F6F, AL),
+    (0x10F70, 0x10FFF, R),
+    (0x1D167, 0x1D169, NSM),
+    (0x1D17B, 0x1D182, NSM),
+    (0x1D7CE, 0x1D7FF, EN),
+    (0x1E800, 0x1EC6F, R),
+    (0x1EC70, 0x1ECBF, AL),
+    (0x1ECC0, 0x1ECFF, R),
+    (0x1ED00, 0x1ED4F, AL),
+    (0x1ED50, 0x1EDFF, R),
+    (0x1EE00, 0x1EEEF, AL),
+    (0x1EEF0, 0x1EEF1, ON),
+    (0x1EEF2, 0x1EFFF, R),
+    (0x1F000, 0x1F0FF, ON),
+    (0x1F100, 0x1F10A, EN),
+    (0x1F10B, 0x1F10F, ON),
+    (0x1F12F, 0x1F12F, ON),
+    (0x1F16A, 0x1F16F, ON),
+    (0x1F1AD, 0x1F1AD, ON),
+    (0x1F260, 0x1F265, ON),
+    (0x1F300, 0x1FBEF, ON),
+    (0x1FBF0, 0x1FBF9, EN),
+    (0xE0001, 0xE0001, BN),
+    (0xE0020, 0xE007F, BN),
+    (0xE0100, 0xE01EF, NSM),
+];
+
+/// Bracket pairs (rule BD16), which are also mirror images of each other.
+const BRACKETS: &[(char, char)] = &[
+    ('(', ')'),
+    ('[', ']'),
+    ('{', '}'),
+    ('\u{2045}', '\u{2046}'),
+    ('\u{207D}', '\u{207E}'),
+    ('\u{208D}', '\u{208E}'),
+    ('\u{2308}', '\u{2309}'),
+    ('\u{230A}', '\u{230B}'),
+    ('\u{2329}', '\u{232A}'),
+    ('\u{2768}', '\u{2769}'),
+    ('\u{276A}', '\u{276B}'),
+    ('\u{276C}', '\u{276D}'),
+    ('\u{276E}', '\u{276F}'),
+    ('\u{2770}', '\u{2771}'),
+    ('\u{2772}', '\u{2773}'),
+    ('\u{2774}', '\u{2775}'),
+    ('\u{27E6}', '\u{27E7}'),
+    ('\u{27E8}', '\u{27E9}'),
+    ('\u{27EA}', '\u{27EB}'),
+    ('\u{2983}', '\u{2984}'),
+    ('\u{2985}', '\u{2986}'),
+    ('\u{2987}', '\u{2988}'),
+    ('\u{2989}', '\u{298A}'),
+    ('\u{298B}', '\u{298C}'),
+    ('\u{3008}', '\u{3009}'),
+    ('\u{300A}', '\u{300B}'),
+    ('\u{300C}', '\u{300D}'),
+    ('\u{300E}', '\u{300F}'),
+    ('\u{3010}', '\u{3011}'),
+    ('\u{3014}', '\u{3015}'),
+    ('\u{3016}', '\u{3017}'),
+    ('\u{3018}', '\u{3019}'),
+    ('\u{301A}', '\u{301B}'),
+    ('\u{FF08}', '\u{FF09}'),
+    ('\u{FF3B}', '\u{FF3D}'),
+    ('\u{FF5B}', '\u{FF5D}'),
+    ('\u{FF5F}', '\u{FF60}'),
+    ('\u{FF62}', '\u{FF63}'),
+];
+
+/// Other characters that are mirror images of each other.
+const MIRRORED: &[(char, char)] = &[
+    ('<', '>'),
+    ('\u{00AB}', '\u{00BB}'),
+    ('\u{2039}', '\u{203A}'),
+    ('\u{2208}', '\u{220B}'),
+    ('\u{2209}', '\u{220C}'),
+    ('\u{2264}', '\u{2265}'),
+    ('\u{2266}', '\u{2267}'),
+    ('\u{226A}', '\u{226B}'),
+    ('\u{2282}', '\u{2283}'),
+    ('\u{2286}', '\u{2287}'),
+    ('\u{FF1C}', '\u{FF1E}'),
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    /// Reorders each paragraph of the text as a single line, and returns the
+    /// characters in visual order, leaving out the invisible ones.
+    fn visual(text: &str, direction: Option<Direction>) -> String {
+        BidiParagraph::split(text, direction)
+            .iter()
+            .flat_map(|x| x.reorder_line(x.text_range.clone()))
+            .map(|(offset, _)| text[offset..].chars().next().unwrap())
+            .filter(|c| !BidiClass::of(*c).is_removed())
+            .collect()
+    }
+    #[test]
+    fn classes() {
+        assert!(BIDI_CLASSES.windows(2).all(|x| x[0].1 < x[1].0));
+        assert_eq!(BidiClass::of('a'), L);
+        assert_eq!(BidiClass::of('\u{05D0}'), R);
+        assert_eq!(BidiClass::of('\u{0627}'), AL);
+        assert_eq!(BidiClass::of('7'), EN);
+        assert_eq!(BidiClass::of('\u{0663}'), AN);
+        assert_eq!(BidiClass::of(' '), WS);
+        assert_eq!(BidiClass::of('\u{05B4}'), NSM);
+        assert_eq!(BidiClass::of('\u{4E00}'), L);
+    }
+    #[test]
+    fn reorder() {
+        // Hebrew letters alef through he are U+05D0 through U+05D4.
+        assert_eq!(visual("abc אבג def", None), "abc גבא def");
+        assert_eq!(visual("אבג 123 דה", None), "הד 123 גבא");
+        assert_eq!(visual("abc", Some(Direction::RightToLeft)), "abc");
+        // W2: numbers after Arabic letters are Arabic numbers
+        assert_eq!(visual("\u{0627} 12", None), "12 \u{0627}");
+        // N0: brackets follow the context when their contents disagree
+        assert_eq!(visual("ab(גד)", None), "ab(דג)");
+        // an override, and an isolate
+        assert_eq!(visual("a\u{202E}bc\u{202C}d", None), "acbd");
+        assert_eq!(
+            visual("א \u{2066}ab\u{2069}!", None),
+            "!\u{2069}ab\u{2066} א"
+        );
+        let paragraphs = BidiParagraph::split("abc\nאב", None);
+        assert_eq!(paragraphs.len(), 2);
+        assert_eq!(paragraphs[0].text_range, 0..4);
+        assert_eq!(paragraphs[0].direction, Direction::LeftToRight);
+        assert_eq!(paragraphs[1].text_range, 4..8);
+        assert_eq!(paragraphs[1].direction, Direction::RightToLeft);
+        // L1: trailing whitespace goes to the paragraph level
+        let paragraph = &BidiParagraph::split("ab\u{202B}ג ", None)[0];
+        assert_eq!(paragraph.get_levels().last(), Some((7, 1)));
+        let line = paragraph.reorder_line(paragraph.text_range.clone());
+        assert_eq!(line.last(), Some(&(7, 0)));
+        // X10: the start of the embedding is compared with the explicit
+        // level of the "1" before it, not the level it resolved to
+        let text = "\u{05D0}1\u{202B}!a\u{202C}";
+        let paragraph =
+            &BidiParagraph::split(text, Some(Direction::LeftToRight))[0];
+        assert!(paragraph.get_levels().any(|x| x == (6, 1)));
+    }
+    #[test]
+    fn encode() {
+        let elements = encode_text_bidi(Ucs2, "אב (ג)\nab", None);
+        let glyph = |c: char| TextElement::DrawGlyph(c as u16);
+        assert_eq!(
+            elements,
+            [
+                TextElement::SetDirection(Direction::RightToLeft),
+                glyph('א'),
+                glyph('ב'),
+                glyph(' '),
+                glyph(')'),
+                glyph('ג'),
+                glyph('('),
+                TextElement::NewLine,
+                TextElement::SetDirection(Direction::LeftToRight),
+                glyph('a'),
+                glyph('b'),
+            ]
+        );
+    }
+}
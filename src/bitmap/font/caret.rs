@@ -0,0 +1,270 @@
+use std::ops::Range;
+
+use super::*;
+
+impl TextMeasurements {
+    /// Returns where the caret goes when it's before the glyph with the
+    /// given index (as counted in `glyph_pens`). Any index past the last
+    /// glyph puts the caret at `pen_end`.
+    pub fn get_caret(&self, index: usize) -> (i32, i32) {
+        self.glyph_pens.get(index).copied().unwrap_or(self.pen_end)
+    }
+    /// Returns the caret position (as would be passed to `get_caret`) that is
+    /// nearest to the given point. The line is chosen by finding the
+    /// baseline nearest to `y`, and then the caret is placed on whichever
+    /// edge of a glyph on that line is nearest to `x`. Clicking on the left
+    /// half of a left-to-right glyph puts the caret before it, and clicking
+    /// on the right half puts it after.
+    pub fn hit_test(&self, x: i32, y: i32) -> usize {
+        let baseline = self
+            .glyph_pens
+            .iter()
+            .map(|(_, pen_y)| *pen_y)
+            .chain([self.pen_end.1])
+            .min_by_key(|baseline| (baseline - y).abs())
+            .unwrap();
+        let glyph_edges = self
+            .glyph_pens
+            .iter()
+            .zip(self.glyph_advances.iter())
+            .enumerate()
+            .filter(|(_, ((_, pen_y), _))| *pen_y == baseline)
+            .flat_map(|(n, ((pen_x, _), advance))| {
+                [(n, *pen_x), (n + 1, pen_x + advance)]
+            });
+        let end = Some((self.glyph_pens.len(), self.pen_end.0))
+            .filter(|_| self.pen_end.1 == baseline);
+        glyph_edges
+            .chain(end)
+            .min_by_key(|(_, caret_x)| (caret_x - x).abs())
+            .map(|(n, _)| n)
+            .unwrap()
+    }
+}
+
+impl LineBox {
+    /// Returns the X coordinate, relative to the left edge of the paragraph,
+    /// of the caret when it's at the given byte offset. Offsets past the last
+    /// glyph on the line put the caret after that glyph.
+    pub fn get_caret_x(&self, text_offset: usize) -> i32 {
+        let x = match self
+            .glyphs
+            .iter()
+            .find(|glyph| glyph.text_offset >= text_offset)
+        {
+            Some(glyph) => glyph.pen_x,
+            None => self
+                .glyphs
+                .last()
+                .map(|glyph| glyph.pen_x + glyph.advance as i32)
+                .unwrap_or(0),
+        };
+        self.pen_x + x
+    }
+    /// Returns the byte offset of the caret position on this line that is
+    /// nearest to the given X coordinate (relative to the left edge of the
+    /// paragraph).
+    ///
+    /// The end of a line that was broken with a soft break is the same
+    /// offset as the start of the next line, so it is never returned; the
+    /// caret goes before the last glyph instead.
+    pub fn hit_test(&self, x: i32) -> usize {
+        let x = x - self.pen_x;
+        let mut carets = vec![];
+        for (n, glyph) in self.glyphs.iter().enumerate() {
+            carets.push((glyph.text_offset, glyph.pen_x));
+            let next_offset = match self.glyphs.get(n + 1) {
+                Some(next) => Some(next.text_offset),
+                None => match self.break_kind {
+                    LineBreak::Soft => None,
+                    // don't go past the newline
+                    LineBreak::Hard => Some(self.text_range.end - 1),
+                    LineBreak::End => Some(self.text_range.end),
+                },
+            };
+            if let Some(next_offset) = next_offset {
+                carets.push((next_offset, glyph.pen_x + glyph.advance as i32));
+            }
+        }
+        carets
+            .into_iter()
+            .min_by_key(|(_, caret_x)| (caret_x - x).abs())
+            .map(|(offset, _)| offset)
+            .unwrap_or(self.text_range.start)
+    }
+}
+
+impl Paragraph {
+    /// Returns the index of the line that the caret is on when it's at the
+    /// given byte offset. An offset where one line ends and the next begins
+    /// is on the later line.
+    pub fn find_line(&self, text_offset: usize) -> usize {
+        let lines = self.get_lines();
+        lines
+            .iter()
+            .position(|line| text_offset < line.text_range.end)
+            .unwrap_or(lines.len() - 1)
+    }
+    /// Returns the rectangle, relative to the top-left corner of the
+    /// paragraph, covered by the caret when it's at the given byte offset.
+    /// It's one pixel wide, and as tall as the line's ascent and descent.
+    pub fn get_caret_rect(&self, text_offset: usize) -> Rectangle {
+        let line = &self.get_lines()[self.find_line(text_offset)];
+        let x = line.get_caret_x(text_offset);
+        Rectangle {
+            left: x,
+            top: line.baseline - line.ascent,
+            right: x + 1,
+            bottom: line.baseline + line.descent,
+        }
+    }
+    /// Returns the byte offset of the caret position nearest to the given
+    /// point, relative to the top-left corner of the paragraph. Points above
+    /// the first line or below the last are treated as being on that line.
+    pub fn hit_test(&self, x: i32, y: i32) -> usize {
+        let lines = self.get_lines();
+        lines
+            .iter()
+            .find(|line| y < line.baseline + line.descent + line.leading)
+            .unwrap_or(lines.last().unwrap())
+            .hit_test(x)
+    }
+}
+
+impl Bitmap {
+    /// Draws the caret for the given byte offset of a paragraph that was
+    /// drawn by `draw_paragraph` with its top-left corner at the given
+    /// coordinates. The caret is inverted, so drawing it again erases it.
+    pub fn draw_caret<'c>(
+        &mut self,
+        clip: impl Into<Clip<'c>>,
+        left: i32,
+        top: i32,
+        paragraph: &Paragraph,
+        text_offset: usize,
+    ) {
+        let rect = paragraph.get_caret_rect(text_offset);
+        self.fill_rect(ModeXor(()), clip, rect.offset_by(left, top));
+    }
+    /// Highlights the part of the given byte range that lies on one line of a
+    /// paragraph, which was drawn by `draw_paragraph` with its top-left
+    /// corner at the given coordinates. The highlight covers the selected
+    /// glyphs, and the full height of the line including leading.
+    ///
+    /// The highlight is inverted with `ModeXor`, so highlighting the same
+    /// range again removes it.
+    pub fn highlight_line<'c>(
+        &mut self,
+        clip: impl Into<Clip<'c>>,
+        left: i32,
+        top: i32,
+        line: &LineBox,
+        selection: Range<usize>,
+    ) {
+        let start = selection.start.max(line.text_range.start);
+        let end = selection.end.min(line.text_range.end);
+        if start >= end {
+            return;
+        }
+        let (start_x, end_x) =
+            (line.get_caret_x(start), line.get_caret_x(end));
+        let rect = Rectangle {
+            left: start_x.min(end_x),
+            top: line.baseline - line.ascent,
+            right: start_x.max(end_x),
+            bottom: line.baseline - line.ascent + line.get_height(),
+        };
+        self.fill_rect(ModeXor(()), clip, rect.offset_by(left, top));
+    }
+    /// Highlights the given byte range of a paragraph, with `highlight_line`
+    /// on every line.
+    pub fn highlight_selection<'c>(
+        &mut self,
+        clip: impl Into<Clip<'c>>,
+        left: i32,
+        top: i32,
+        paragraph: &Paragraph,
+        selection: Range<usize>,
+    ) {
+        let clip: Clip = clip.into();
+        for line in paragraph.get_lines() {
+            self.highlight_line(clip, left, top, line, selection.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::make_test_font;
+    use super::*;
+    #[test]
+    fn hit_test_measurements() {
+        let font = make_test_font();
+        let elements = encode_text(MacRoman, "AB\nC");
+        let measured =
+            Font::measure_text(|n| [&font].get(n).copied(), elements);
+        assert_eq!(measured.glyph_advances, &[5, 5, 5]);
+        assert_eq!(measured.hit_test(-3, 0), 0);
+        assert_eq!(measured.hit_test(2, 0), 0);
+        assert_eq!(measured.hit_test(3, -5), 1);
+        assert_eq!(measured.hit_test(40, 1), 2);
+        assert_eq!(measured.hit_test(4, 9), 3);
+        assert_eq!(measured.get_caret(1), (5, 0));
+        assert_eq!(measured.get_caret(3), (5, 10));
+        // right to left: the first glyph is on the right
+        let elements = [
+            TextElement::SetDirection(Direction::RightToLeft),
+            TextElement::DrawGlyph(b'A' as u16),
+            TextElement::DrawGlyph(b'B' as u16),
+        ];
+        let measured =
+            Font::measure_text(|n| [&font].get(n).copied(), elements);
+        assert_eq!(measured.glyph_advances, &[-5, -5]);
+        assert_eq!(measured.hit_test(-1, 0), 0);
+        assert_eq!(measured.hit_test(-4, 0), 1);
+        assert_eq!(measured.hit_test(-9, 0), 2);
+    }
+    #[test]
+    fn paragraph_carets() {
+        let font = make_test_font();
+        // "aaa bb " / "cc\n" / "d", each character 5 pixels wide
+        let paragraph = Paragraph::layout(
+            "aaa bb cc\nd",
+            MacRoman,
+            &[&font],
+            35,
+            Alignment::Left,
+        );
+        assert_eq!(paragraph.get_lines().len(), 3);
+        assert_eq!(paragraph.find_line(6), 0);
+        assert_eq!(paragraph.find_line(7), 1);
+        assert_eq!(paragraph.find_line(9), 1);
+        assert_eq!(paragraph.find_line(11), 2);
+        assert_eq!(
+            paragraph.get_caret_rect(8),
+            Rectangle {
+                left: 5,
+                top: 10,
+                right: 6,
+                bottom: 19,
+            }
+        );
+        assert_eq!(paragraph.hit_test(7, 0), 1);
+        assert_eq!(paragraph.hit_test(100, 0), 6);
+        assert_eq!(paragraph.hit_test(100, 12), 9);
+        assert_eq!(paragraph.hit_test(100, 100), 11);
+        for offset in 0..=11 {
+            let rect = paragraph.get_caret_rect(offset);
+            assert_eq!(paragraph.hit_test(rect.left, rect.top), offset);
+        }
+        let mut bitmap = Bitmap::new(40, 30);
+        bitmap.highlight_selection(None, 0, 0, &paragraph, 5..8);
+        assert!(!bitmap.get_pixel(24, 0) && bitmap.get_pixel(25, 0));
+        assert!(bitmap.get_pixel(34, 9) && !bitmap.get_pixel(35, 9));
+        assert!(bitmap.get_pixel(0, 10) && !bitmap.get_pixel(5, 10));
+        bitmap.highlight_selection(None, 0, 0, &paragraph, 5..8);
+        bitmap.draw_caret(None, 0, 0, &paragraph, 0);
+        bitmap.draw_caret(None, 0, 0, &paragraph, 0);
+        assert!(bitmap.to_bytes().iter().all(|x| *x == 0));
+    }
+}
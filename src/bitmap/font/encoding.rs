@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use super::*;
+
+/// A glyph ID that no font can contain. (`read_mac_font` refuses to load
+/// fonts that would contain it.) Looking it up always results in the missing
+/// glyph.
+pub const MISSING_GLYPH: u16 = 0xFFFF;
+
+/// Any means of mapping Unicode characters to glyph IDs.
+///
+/// Implemented by:
+/// - [`MacRoman`](struct.MacRoman.html): the Macintosh Roman character set,
+///   used by most Macintosh fonts.
+/// - [`Ucs2`](struct.Ucs2.html): every character in the Basic Multilingual
+///   Plane maps to the glyph with the same number.
+/// - [`Encoding`](struct.Encoding.html): a table you build yourself.
+pub trait EncodingTrait {
+    /// Returns the glyph ID for the given character, or `None` if this
+    /// encoding has no glyph for it.
+    fn encode_char(&self, c: char) -> Option<u16>;
+}
+
+impl<T: EncodingTrait> EncodingTrait for &T {
+    fn encode_char(&self, c: char) -> Option<u16> {
+        (*self).encode_char(c)
+    }
+}
+
+/// The Macintosh Roman character set. Characters 0x00 through 0x7F are the
+/// same as ASCII. 0xDB is mapped from both the euro sign and the generic
+/// currency sign, since it was changed from the latter to the former in Mac
+/// OS 8.5.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MacRoman;
+
+impl EncodingTrait for MacRoman {
+    fn encode_char(&self, c: char) -> Option<u16> {
+        if (c as u32) < 0x80 {
+            Some(c as u16)
+        } else if c == '\u{00A4}' {
+            Some(0xDB)
+        } else {
+            MAC_ROMAN_HIGH
+                .iter()
+                .position(|x| *x == c)
+                .map(|n| n as u16 + 0x80)
+        }
+    }
+}
+
+impl MacRoman {
+    /// Turns Macintosh Roman bytes into a string.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .map(|x| {
+                if *x < 0x80 {
+                    *x as char
+                } else {
+                    MAC_ROMAN_HIGH[*x as usize - 0x80]
+                }
+            })
+            .collect()
+    }
+}
+
+/// Maps every character in the Basic Multilingual Plane to the glyph with the
+/// same number. Characters outside the BMP (and U+FFFF) have no glyph.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ucs2;
+
+impl EncodingTrait for Ucs2 {
+    fn encode_char(&self, c: char) -> Option<u16> {
+        u16::try_from(c as u32).ok().filter(|x| *x != MISSING_GLYPH)
+    }
+}
+
+/// A user-supplied mapping from characters to glyph IDs.
+#[derive(Clone, Debug, Default)]
+pub struct Encoding {
+    map: HashMap<char, u16>,
+}
+
+impl Encoding {
+    /// Create a new, empty encoding. No characters will have glyphs.
+    pub fn new() -> Encoding {
+        Encoding::default()
+    }
+    /// Create an encoding from a table of characters, where the character at
+    /// index N maps to glyph `first_glyph + N`.
+    pub fn from_table(first_glyph: u16, table: &[char]) -> Encoding {
+        table
+            .iter()
+            .zip(first_glyph..)
+            .map(|(c, glyph)| (*c, glyph))
+            .collect()
+    }
+    /// Map the given character to the given glyph, replacing any previous
+    /// mapping for that character.
+    pub fn insert(&mut self, c: char, glyph: u16) {
+        self.map.insert(c, glyph);
+    }
+    /// Remove any mapping for the given character.
+    pub fn remove(&mut self, c: char) {
+        self.map.remove(&c);
+    }
+}
+
+impl FromIterator<(char, u16)> for Encoding {
+    fn from_iter<T: IntoIterator<Item = (char, u16)>>(iter: T) -> Encoding {
+        Encoding {
+            map: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<(char, u16)> for Encoding {
+    fn extend<T: IntoIterator<Item = (char, u16)>>(&mut self, iter: T) {
+        self.map.extend(iter)
+    }
+}
+
+impl EncodingTrait for Encoding {
+    fn encode_char(&self, c: char) -> Option<u16> {
+        self.map.get(&c).copied()
+    }
+}
+
+/// Turns a string into a stream of `TextElement`s, suitable for `draw_text`
+/// or `measure_text`. Newlines (`'\n'`) become `TextElement::NewLine`.
+/// Characters that the encoding can't map become `MISSING_GLYPH`, so that the
+/// fonts' missing glyph is drawn in their place.
+pub fn encode_text<'a>(
+    encoding: impl EncodingTrait + 'a,
+    text: &'a str,
+) -> impl Iterator<Item = TextElement> + 'a {
+    text.chars().map(move |c| {
+        if c == '\n' {
+            TextElement::NewLine
+        } else {
+            TextElement::DrawGlyph(
+                encoding.encode_char(c).unwrap_or(MISSING_GLYPH),
+            )
+        }
+    })
+}
+
+/// Macintosh Roman characters 0x80 through 0xFF.
+const MAC_ROMAN_HIGH: [char; 128] = [
+    'Ä', 'Å', 'Ç', 'É', 'Ñ', 'Ö', 'Ü', 'á', 'à', 'â', 'ä', 'ã', 'å', 'ç', 'é',
+    'è', 'ê', 'ë', 'í', 'ì', 'î', 'ï', 'ñ', 'ó', 'ò', 'ô', 'ö', 'õ', 'ú', 'ù',
+    'û', 'ü', '†', '°', '¢', '£', '§', '•', '¶', 'ß', '®', '©', '™', '´', '¨',
+    '≠', 'Æ', 'Ø', '∞', '±', '≤', '≥', '¥', 'µ', '∂', '∑', '∏', 'π', '∫', 'ª',
+    'º', 'Ω', 'æ', 'ø', '¿', '¡', '¬', '√', 'ƒ', '≈', '∆', '«', '»', '…',
+    '\u{00A0}', 'À', 'Ã', 'Õ', 'Œ', 'œ', '–', '—', '“', '”', '‘', '’', '÷',
+    '◊', 'ÿ', 'Ÿ', '⁄', '€', '‹', '›', 'ﬁ', 'ﬂ', '‡', '·', '‚', '„', '‰', 'Â',
+    'Ê', 'Á', 'Ë', 'È', 'Í', 'Î', 'Ï', 'Ì', 'Ó', 'Ô', '\u{F8FF}', 'Ò', 'Ú',
+    'Û', 'Ù', 'ı', 'ˆ', '˜', '¯', '˘', '˙', '˚', '¸', '˝', '˛', 'ˇ',
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn mac_roman() {
+        assert_eq!(MacRoman.encode_char('A'), Some(0x41));
+        assert_eq!(MacRoman.encode_char('é'), Some(0x8E));
+        assert_eq!(MacRoman.encode_char('€'), Some(0xDB));
+        assert_eq!(MacRoman.encode_char('\u{00A4}'), Some(0xDB));
+        assert_eq!(MacRoman.encode_char('Ж'), None);
+        assert_eq!(
+            encode_text(MacRoman, "Ж").collect::<Vec<_>>(),
+            &[TextElement::DrawGlyph(MISSING_GLYPH)]
+        );
+        // every byte survives a round trip, and 0xDB decodes to the euro
+        // sign rather than the generic currency sign
+        let bytes: Vec<u8> = (0..=255).collect();
+        let decoded = MacRoman.decode(&bytes);
+        assert_eq!(decoded.chars().nth(0xDB), Some('€'));
+        let encoded: Vec<u8> = decoded
+            .chars()
+            .map(|c| MacRoman.encode_char(c).unwrap() as u8)
+            .collect();
+        assert_eq!(encoded, bytes);
+    }
+    #[test]
+    fn ucs2() {
+        assert_eq!(Ucs2.encode_char('é'), Some(0xE9));
+        assert_eq!(Ucs2.encode_char('\u{FFFD}'), Some(0xFFFD));
+        assert_eq!(Ucs2.encode_char('\u{FFFF}'), None);
+        assert_eq!(Ucs2.encode_char('😀'), None);
+    }
+    #[test]
+    fn user_encoding() {
+        let mut encoding = Encoding::from_table(0x10, &['a', 'b', 'c']);
+        assert_eq!(encoding.encode_char('a'), Some(0x10));
+        assert_eq!(encoding.encode_char('c'), Some(0x12));
+        assert_eq!(encoding.encode_char('d'), None);
+        encoding.insert('d', 0x20);
+        encoding.insert('a', 0x21);
+        assert_eq!(encoding.encode_char('d'), Some(0x20));
+        assert_eq!(encoding.encode_char('a'), Some(0x21));
+        encoding.remove('b');
+        assert_eq!(encoding.encode_char('b'), None);
+    }
+    #[test]
+    fn newlines() {
+        assert_eq!(
+            encode_text(MacRoman, "a\nb").collect::<Vec<_>>(),
+            &[
+                TextElement::DrawGlyph(0x61),
+                TextElement::NewLine,
+                TextElement::DrawGlyph(0x62),
+            ]
+        );
+    }
+}
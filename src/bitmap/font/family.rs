@@ -0,0 +1,258 @@
+use super::*;
+
+/// A set of strikes of the same typeface, at various sizes and styles, from
+/// which a `Font` for any size and style can be had.
+///
+/// If there's a strike for exactly the requested size and style, that's what
+/// you get. Otherwise, one is made, and kept for next time:
+///
+/// 1. The strike at the requested size whose style has the most of the
+///    requested styles (and none that weren't requested) is chosen, and the
+///    rest of the styles are applied with `Font::derive_style`. Since
+///    `derive_style` applies styles in a fixed order, a strike is only
+///    chosen if none of the styles left to apply come before any of its own
+///    in that order. (e.g. bold isn't applied to a native italic strike.)
+/// 2. If there's no such strike, the plain strike at the best other size is
+///    scaled with `Font::scaled`, and all the styles are applied to that.
+///    The best size is the largest one that divides evenly into the
+///    requested size, or failing that, the nearest one (preferring larger
+///    sizes in a tie).
+///
+/// Since making a font requires changing the family, fonts are made with
+/// `get_font` (or `prepare`), after which `get` can hand out any number of
+/// them at once, e.g. to pass to `draw_text`:
+///
+/// ```rust,ignore
+/// family.prepare([(12, StyleFlags::PLAIN), (12, StyleFlags::BOLD)])?;
+/// let fonts = [
+///     family.get(12, StyleFlags::PLAIN).unwrap(),
+///     family.get(12, StyleFlags::BOLD).unwrap(),
+/// ];
+/// bitmap.draw_text(mode, None, x, y, |n| fonts.get(n).copied(), elements);
+/// ```
+#[derive(Clone, Default)]
+pub struct FontFamily {
+    /// Strikes that were provided to us.
+    strikes: BTreeMap<(u16, StyleFlags), Font>,
+    /// Strikes that we made ourselves.
+    derived: HashMap<(u16, StyleFlags), Font>,
+}
+
+impl FontFamily {
+    /// Creates a new family with no strikes in it.
+    pub fn new() -> FontFamily {
+        FontFamily::default()
+    }
+    /// Adds a strike of the given size and style, replacing any that was
+    /// there before. Any fonts that were made from the old strikes are
+    /// forgotten.
+    pub fn add_strike(&mut self, size: u16, style: StyleFlags, font: Font) {
+        self.strikes.insert((size, style), font);
+        self.derived.clear();
+    }
+    /// Returns the size and style of every strike that was added.
+    pub fn get_strikes(&self) -> impl Iterator<Item = (u16, StyleFlags)> + '_ {
+        self.strikes.keys().copied()
+    }
+    /// Returns true if there are no strikes in this family.
+    pub fn is_empty(&self) -> bool {
+        self.strikes.is_empty()
+    }
+    /// Returns a font of the given size and style, making it first if
+    /// necessary. Returns an error if the family is empty, or if scaling
+    /// fails.
+    pub fn get_font(
+        &mut self,
+        size: u16,
+        style: StyleFlags,
+    ) -> anyhow::Result<&Font> {
+        self.prepare([(size, style)])?;
+        Ok(self.get(size, style).unwrap())
+    }
+    /// Makes every font in the given list of sizes and styles that hasn't
+    /// already been made, so that `get` will return them.
+    pub fn prepare(
+        &mut self,
+        requests: impl IntoIterator<Item = (u16, StyleFlags)>,
+    ) -> anyhow::Result<()> {
+        for (size, style) in requests {
+            if self.get(size, style).is_some() {
+                continue;
+            }
+            let font = self.make_font(size, style)?;
+            self.derived.insert((size, style), font);
+        }
+        Ok(())
+    }
+    /// Returns a font of the given size and style, if there's a strike for
+    /// it, or if it's already been made by `get_font` or `prepare`.
+    pub fn get(&self, size: u16, style: StyleFlags) -> Option<&Font> {
+        self.strikes
+            .get(&(size, style))
+            .or_else(|| self.derived.get(&(size, style)))
+    }
+    /// Makes a font of the given size and style, as described in the
+    /// type-level docs. (A plain strike made by scaling is kept as well.)
+    fn make_font(
+        &mut self,
+        size: u16,
+        style: StyleFlags,
+    ) -> anyhow::Result<Font> {
+        let base = self
+            .strikes
+            .range((size, StyleFlags::PLAIN)..=(size, StyleFlags(!0)))
+            .filter(|((_, x), _)| can_derive(*x, style))
+            .max_by_key(|((_, x), _)| x.0.count_ones());
+        if let Some(((_, base_style), base)) = base {
+            return Ok(base.derive_style(StyleFlags(style.0 & !base_style.0)));
+        }
+        let source_size = self
+            .pick_source_size(size)
+            .ok_or_else(|| anyhow!("Font family has no plain strikes"))?;
+        if self.get(size, StyleFlags::PLAIN).is_none() {
+            let source = &self.strikes[&(source_size, StyleFlags::PLAIN)];
+            let scaled = source.scaled(size as u32, source_size as u32)?;
+            self.derived.insert((size, StyleFlags::PLAIN), scaled);
+        }
+        let plain = self.get(size, StyleFlags::PLAIN).unwrap();
+        Ok(plain.derive_style(style))
+    }
+    /// Picks the size of a plain strike to scale to get the given size.
+    fn pick_source_size(&self, size: u16) -> Option<u16> {
+        let sizes = || {
+            self.strikes
+                .keys()
+                .filter(|(_, style)| style.is_plain())
+                .map(|(size, _)| *size)
+                .filter(|size| *size != 0)
+        };
+        sizes()
+            .filter(|x| size.is_multiple_of(*x))
+            .max()
+            .or_else(|| sizes().min_by_key(|x| (x.abs_diff(size), !x)))
+    }
+}
+
+/// Returns true if a strike of style `base` can be made into `style` with
+/// `Font::derive_style`: every style of `base` must be requested, and every
+/// style that's left to apply must come after all of them in the order that
+/// `derive_style` applies them in.
+fn can_derive(base: StyleFlags, style: StyleFlags) -> bool {
+    // Outline and shadow are one step, as are condense and extend.
+    const STEPS: [u16; 5] = [
+        StyleFlags::BOLD.0,
+        StyleFlags::ITALIC.0,
+        StyleFlags::UNDERLINE.0,
+        StyleFlags::OUTLINE.0 | StyleFlags::SHADOW.0,
+        StyleFlags::CONDENSE.0 | StyleFlags::EXTEND.0,
+    ];
+    let rest = style.0 & !base.0;
+    let last_base = STEPS.iter().rposition(|x| base.0 & x != 0);
+    let first_rest = STEPS.iter().position(|x| rest & x != 0);
+    style.contains(base)
+        && match (last_base, first_rest) {
+            (Some(last_base), Some(first_rest)) => last_base < first_rest,
+            _ => true,
+        }
+}
+
+impl ResourceFork {
+    /// Reads every strike of the given font family (as listed by
+    /// `list_fonts`) into a `FontFamily`. Strikes with no known size are
+    /// skipped.
+    pub fn read_family(&self, family_id: u16) -> anyhow::Result<FontFamily> {
+        let mut ret = FontFamily::new();
+        for info in self.list_fonts()? {
+            let Some(size) = info.size.filter(|_| info.family_id == family_id)
+            else {
+                continue;
+            };
+            ret.add_strike(
+                size,
+                StyleFlags(info.style),
+                self.read_font(&info)?,
+            );
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn font_family() {
+        let mut family = FontFamily::new();
+        assert!(family.get_font(9, StyleFlags::PLAIN).is_err());
+        let plain = super::super::test::make_test_font();
+        let italic = plain.make_italic();
+        family.add_strike(9, StyleFlags::PLAIN, plain);
+        family.add_strike(9, StyleFlags::ITALIC, italic);
+        let advance = |family: &mut FontFamily, size, style| {
+            family.get_font(size, style).unwrap().get_glyph(0x41).2
+        };
+        assert_eq!(advance(&mut family, 9, StyleFlags::PLAIN), 5);
+        assert_eq!(advance(&mut family, 9, StyleFlags::BOLD), 6);
+        // made from the native italic strike
+        assert_eq!(
+            advance(&mut family, 9, StyleFlags::ITALIC | StyleFlags::EXTEND),
+            6
+        );
+        // scaled from size 9
+        assert_eq!(advance(&mut family, 18, StyleFlags::PLAIN), 10);
+        assert_eq!(advance(&mut family, 18, StyleFlags::BOLD), 11);
+        assert_eq!(advance(&mut family, 12, StyleFlags::PLAIN), 7);
+        // cached
+        let a = family.get(18, StyleFlags::BOLD).unwrap() as *const Font;
+        family.prepare([(18, StyleFlags::BOLD)]).unwrap();
+        let b = family.get(18, StyleFlags::BOLD).unwrap() as *const Font;
+        assert_eq!(a, b);
+        assert!(family.get(24, StyleFlags::PLAIN).is_none());
+    }
+    #[test]
+    fn font_family_fallbacks() {
+        let draw = |font: &Font| {
+            let mut bitmap = Bitmap::new(32, 24);
+            bitmap.draw_glyph(ModeOr(()), None, 4, 16, &[font], 0x41);
+            (bitmap.to_bytes(), font.get_glyph(0x41).2)
+        };
+        let plain = super::super::test::make_test_font();
+        let mut family = FontFamily::new();
+        family.add_strike(9, StyleFlags::PLAIN, plain.clone());
+        // a native italic strike that's nothing like a derived one
+        let italic = plain.make_italic().make_extended(3);
+        family.add_strike(9, StyleFlags::ITALIC, italic.clone());
+        assert_eq!(
+            draw(family.get_font(9, StyleFlags::ITALIC).unwrap()),
+            draw(&italic)
+        );
+        // bold has to come before italic, so the native italic strike can't
+        // be used
+        let bold_italic = StyleFlags::BOLD | StyleFlags::ITALIC;
+        assert_eq!(
+            draw(family.get_font(9, bold_italic).unwrap()),
+            draw(&plain.make_bold().make_italic())
+        );
+        assert_ne!(
+            draw(&plain.make_bold().make_italic()),
+            draw(&italic.make_bold())
+        );
+        // the only strike at size 12 is bold, so the others are scaled
+        let bold = plain.scaled(12, 9).unwrap().make_extended(2);
+        family.add_strike(12, StyleFlags::BOLD, bold.clone());
+        let scaled = plain.scaled(12, 9).unwrap();
+        assert_eq!(
+            draw(family.get_font(12, StyleFlags::PLAIN).unwrap()),
+            draw(&scaled)
+        );
+        assert_eq!(
+            draw(family.get_font(12, StyleFlags::ITALIC).unwrap()),
+            draw(&scaled.make_italic())
+        );
+        // but the bold strike is still used when it can be
+        assert_eq!(
+            draw(family.get_font(12, bold_italic).unwrap()),
+            draw(&bold.make_italic())
+        );
+    }
+}
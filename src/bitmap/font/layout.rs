@@ -0,0 +1,377 @@
+use std::ops::Range;
+
+use super::*;
+
+/// How the lines of a paragraph are positioned within its width.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Alignment {
+    /// Lines start at the left edge.
+    #[default]
+    Left,
+    /// Lines end at the right edge.
+    Right,
+    /// Lines are centered, rounding to the left.
+    Center,
+    /// Spaces are widened so that lines touch both edges. The last line, and
+    /// any line that ends in a newline, is aligned left instead.
+    Justified,
+}
+
+/// Why a line ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineBreak {
+    /// The next word didn't fit, so it was moved to the next line.
+    Soft,
+    /// The line ended with a newline character.
+    Hard,
+    /// The line ended because the text did.
+    End,
+}
+
+/// A single glyph that has been placed on a line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LaidOutGlyph {
+    /// The byte offset, within the source text, of the character this glyph
+    /// came from.
+    pub text_offset: usize,
+    /// The glyph to draw.
+    pub glyph: u16,
+    /// Where the pen is when drawing this glyph, relative to the line's pen
+    /// start.
+    pub pen_x: i32,
+    /// How far the pen advances after this glyph, including any extra space
+    /// added by justification.
+    pub advance: u32,
+}
+
+/// One line of a laid out paragraph.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineBox {
+    /// The byte range of the source text covered by this line, including any
+    /// trailing spaces and the newline (if any).
+    pub text_range: Range<usize>,
+    /// Why this line ended.
+    pub break_kind: LineBreak,
+    /// Where the pen starts, relative to the left edge of the paragraph.
+    pub pen_x: i32,
+    /// Y coordinate of the baseline, relative to the top of the paragraph.
+    pub baseline: i32,
+    /// Width of the line, not counting trailing spaces.
+    pub width: u32,
+    /// The largest ascent of any font used on this line.
+    pub ascent: i32,
+    /// The largest descent of any font used on this line.
+    pub descent: i32,
+    /// The largest leading of any font used on this line.
+    pub leading: i32,
+    /// Every glyph on the line, including trailing spaces, in order.
+    pub glyphs: Vec<LaidOutGlyph>,
+}
+
+impl LineBox {
+    /// Returns the vertical distance from the top of this line to the top of
+    /// the next.
+    pub fn get_height(&self) -> i32 {
+        self.ascent + self.descent + self.leading
+    }
+}
+
+/// A paragraph of text that has been broken into lines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Paragraph {
+    lines: Vec<LineBox>,
+    width: u32,
+    height: u32,
+}
+
+impl Paragraph {
+    /// Break the given text into lines that are no wider than `width`, using
+    /// `encoding` to turn characters into glyphs, and the first font in
+    /// `fonts` that has each glyph. Characters that `encoding` can't map are
+    /// laid out as the missing glyph.
+    ///
+    /// Lines are broken after runs of spaces, and always after a newline
+    /// (`'\n'`). Spaces at the end of a line are allowed to hang past the
+    /// right edge. A word that is too long to fit on a line by itself is
+    /// broken between characters. A line will always contain at least one
+    /// character, even if that character is wider than `width`. Adjacent
+    /// glyphs from the same font are kerned, as with `draw_text`.
+    pub fn layout(
+        text: &str,
+        encoding: impl EncodingTrait,
+        fonts: &[&Font],
+        width: u32,
+        alignment: Alignment,
+    ) -> Paragraph {
+        let mut lines = Vec::new();
+        let mut line_start = 0;
+        let mut glyphs: Vec<LaidOutGlyph> = Vec::new();
+        let mut x = 0;
+        // Index into `glyphs`, and byte offset into `text`, of the first
+        // character after the most recent run of spaces.
+        let mut break_opportunity: Option<(usize, usize)> = None;
+        for (offset, c) in text.char_indices() {
+            if c == '\n' {
+                lines.push(finish_line(
+                    text,
+                    fonts,
+                    line_start..offset + 1,
+                    LineBreak::Hard,
+                    std::mem::take(&mut glyphs),
+                ));
+                line_start = offset + 1;
+                x = 0;
+                break_opportunity = None;
+                continue;
+            }
+            let glyph = encoding.encode_char(c).unwrap_or(MISSING_GLYPH);
+            let (_, _, _, advance, _) = lookup_glyph(fonts, glyph);
+            let kerning = glyphs
+                .last()
+                .map(|previous| get_pair_kerning(fonts, previous.glyph, glyph))
+                .unwrap_or(0);
+            if c == ' ' {
+                break_opportunity = Some((glyphs.len() + 1, offset + 1));
+            } else if !glyphs.is_empty()
+                && x + kerning + advance as i32 > width as i32
+            {
+                let (split_index, split_offset) =
+                    break_opportunity.take().unwrap_or((glyphs.len(), offset));
+                let mut rest = glyphs.split_off(split_index);
+                lines.push(finish_line(
+                    text,
+                    fonts,
+                    line_start..split_offset,
+                    LineBreak::Soft,
+                    std::mem::take(&mut glyphs),
+                ));
+                line_start = split_offset;
+                x = 0;
+                for mut moved in rest.drain(..) {
+                    if let Some(previous) = glyphs.last() {
+                        x += get_pair_kerning(
+                            fonts,
+                            previous.glyph,
+                            moved.glyph,
+                        );
+                    }
+                    moved.pen_x = x;
+                    x += moved.advance as i32;
+                    glyphs.push(moved);
+                }
+            }
+            if let Some(previous) = glyphs.last() {
+                x += get_pair_kerning(fonts, previous.glyph, glyph);
+            }
+            glyphs.push(LaidOutGlyph {
+                text_offset: offset,
+                glyph,
+                pen_x: x,
+                advance,
+            });
+            x += advance as i32;
+        }
+        lines.push(finish_line(
+            text,
+            fonts,
+            line_start..text.len(),
+            LineBreak::End,
+            glyphs,
+        ));
+        let mut y = 0;
+        for line in lines.iter_mut() {
+            line.baseline = y + line.ascent;
+            y += line.get_height();
+            align_line(text, line, width, alignment);
+        }
+        Paragraph {
+            lines,
+            width,
+            height: y.max(0) as u32,
+        }
+    }
+    /// Returns the lines of this paragraph, from top to bottom.
+    pub fn get_lines(&self) -> &[LineBox] {
+        &self.lines
+    }
+    /// Returns the width this paragraph was laid out to fit within.
+    pub fn get_width(&self) -> u32 {
+        self.width
+    }
+    /// Returns the total height of all the lines in this paragraph.
+    pub fn get_height(&self) -> u32 {
+        self.height
+    }
+}
+
+impl Bitmap {
+    /// Render a paragraph that was laid out by `Paragraph::layout`, with its
+    /// top-left corner at the given coordinates. `fonts` should be the same
+    /// fonts that were used to lay it out.
+    pub fn draw_paragraph<'c, Mode: TransferMode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        left: i32,
+        top: i32,
+        fonts: &[&Font],
+        paragraph: &Paragraph,
+    ) {
+        let clip: Clip = clip.into();
+        for line in paragraph.get_lines() {
+            for glyph in line.glyphs.iter() {
+                self.draw_glyph(
+                    &mode,
+                    clip,
+                    left + line.pen_x + glyph.pen_x,
+                    top + line.baseline,
+                    fonts,
+                    glyph.glyph,
+                );
+            }
+        }
+    }
+}
+
+/// Returns true if the given glyph came from a space character.
+fn is_space(text: &str, glyph: &LaidOutGlyph) -> bool {
+    text.as_bytes()[glyph.text_offset] == b' '
+}
+
+/// Build a `LineBox` out of the given glyphs, calculating its width and
+/// vertical metrics. Its position will be filled in later.
+fn finish_line(
+    text: &str,
+    fonts: &[&Font],
+    text_range: Range<usize>,
+    break_kind: LineBreak,
+    glyphs: Vec<LaidOutGlyph>,
+) -> LineBox {
+    let width = glyphs
+        .iter()
+        .rev()
+        .find(|glyph| !is_space(text, glyph))
+        .map(|glyph| (glyph.pen_x + glyph.advance as i32) as u32)
+        .unwrap_or(0);
+    let (mut ascent, mut descent, mut leading) = if glyphs.is_empty() {
+        let font = fonts.first().expect("At least one font must be provided!");
+        (font.get_ascent(), font.get_descent(), font.get_leading())
+    } else {
+        (i32::MIN, i32::MIN, i32::MIN)
+    };
+    for glyph in glyphs.iter() {
+        let (font, _, _, _, _) = lookup_glyph(fonts, glyph.glyph);
+        ascent = ascent.max(font.get_ascent());
+        descent = descent.max(font.get_descent());
+        leading = leading.max(font.get_leading());
+    }
+    LineBox {
+        text_range,
+        break_kind,
+        pen_x: 0,
+        baseline: 0,
+        width,
+        ascent,
+        descent,
+        leading,
+        glyphs,
+    }
+}
+
+/// Position a line horizontally within the given width.
+fn align_line(
+    text: &str,
+    line: &mut LineBox,
+    width: u32,
+    alignment: Alignment,
+) {
+    let slack = width.saturating_sub(line.width);
+    match alignment {
+        Alignment::Left => (),
+        Alignment::Right => line.pen_x = slack as i32,
+        Alignment::Center => line.pen_x = (slack / 2) as i32,
+        Alignment::Justified => {
+            if line.break_kind != LineBreak::Soft || slack == 0 {
+                return;
+            }
+            let content_end = line
+                .glyphs
+                .iter()
+                .rposition(|glyph| !is_space(text, glyph))
+                .map(|n| n + 1)
+                .unwrap_or(0);
+            let num_spaces = line.glyphs[..content_end]
+                .iter()
+                .filter(|glyph| is_space(text, glyph))
+                .count() as u32;
+            if num_spaces == 0 {
+                return;
+            }
+            let (per_space, remainder) =
+                (slack / num_spaces, slack % num_spaces);
+            let mut extra = 0;
+            let mut spaces_seen = 0;
+            for glyph in line.glyphs[..content_end].iter_mut() {
+                glyph.pen_x += extra as i32;
+                if is_space(text, glyph) {
+                    let widen = per_space + (spaces_seen < remainder) as u32;
+                    glyph.advance += widen;
+                    extra += widen;
+                    spaces_seen += 1;
+                }
+            }
+            for glyph in line.glyphs[content_end..].iter_mut() {
+                glyph.pen_x += extra as i32;
+            }
+            line.width += extra;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::make_test_font;
+    use super::*;
+    #[test]
+    fn wrap_and_justify() {
+        let font = make_test_font();
+        // Every glyph in the test font advances by 5 pixels.
+        let paragraph = Paragraph::layout(
+            "aaa bb cccc\ndd",
+            MacRoman,
+            &[&font],
+            40,
+            Alignment::Justified,
+        );
+        let lines = paragraph.get_lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].text_range, 0..7);
+        assert_eq!(lines[0].break_kind, LineBreak::Soft);
+        assert_eq!(lines[0].width, 40);
+        // The one space on the first line absorbs all the slack.
+        assert_eq!(lines[0].glyphs[4].pen_x, 30);
+        assert_eq!(lines[1].text_range, 7..12);
+        assert_eq!(lines[1].break_kind, LineBreak::Hard);
+        assert_eq!(lines[1].width, 20);
+        assert_eq!(lines[2].text_range, 12..14);
+        assert_eq!(lines[2].break_kind, LineBreak::End);
+        assert_eq!(lines[2].baseline, 7 + 10 * 2);
+        assert_eq!(paragraph.get_height(), 30);
+    }
+    #[test]
+    fn break_long_word() {
+        let font = make_test_font();
+        let paragraph = Paragraph::layout(
+            "abcdefg",
+            MacRoman,
+            &[&font],
+            15,
+            Alignment::Right,
+        );
+        let lines = paragraph.get_lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].text_range, 0..3);
+        assert_eq!(lines[2].text_range, 6..7);
+        assert_eq!(lines[2].pen_x, 10);
+    }
+}
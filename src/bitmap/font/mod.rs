@@ -0,0 +1,1624 @@
+use super::*;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Write},
+    ops::RangeInclusive,
+    sync::OnceLock,
+};
+
+use anyhow::{anyhow, Context};
+
+mod atlas;
+pub use atlas::*;
+mod bdf;
+mod bidi;
+pub use bidi::*;
+mod caret;
+mod encoding;
+pub use encoding::*;
+mod family;
+pub use family::*;
+mod layout;
+pub use layout::*;
+mod psf;
+mod resource_fork;
+pub use resource_fork::*;
+mod scale;
+mod style;
+pub use style::*;
+
+/// A loaded bitmap font.
+#[derive(Clone)]
+pub struct Font {
+    glyph_range: RangeInclusive<u16>,
+    /// bits!
+    bitmap: Bitmap,
+    /// number of pixels between top of topmost bit and baseline
+    ascent: i16,
+    /// number of pixels between baseline and bottom of bottommost bit
+    descent: i16,
+    /// extra pixels between lines
+    leading: i16,
+    /// X coordinates of left edges of glyphs (empty if `atlas` is present)
+    glyph_locations: Vec<u16>,
+    /// Offsets and advances of glyphs (empty if `atlas` is present)
+    glyph_offsetwidths: Vec<(i8, u8)>,
+    /// Fractional advances of glyphs (8.8 fixed point), if known
+    glyph_widths: Option<Vec<u16>>,
+    /// First row and number of rows of each glyph's image, if known
+    image_heights: Option<Vec<(u8, u8)>>,
+    /// Pixels to add to the advance between particular pairs of glyphs
+    kerning: HashMap<(u16, u16), i32>,
+    /// Cached width of space character
+    space_width: OnceLock<u32>,
+    /// If present, `bitmap` is an atlas instead of a strike
+    atlas: Option<GlyphAtlas>,
+}
+
+impl Font {
+    /// Attempts to parse the given input as a Macintosh Toolbox FONT/NFNT.
+    ///
+    /// If present, the optional glyph-width and image-height tables are also
+    /// read. Kerning information is not part of a FONT/NFNT; it comes from
+    /// the `FOND`. (`ResourceFork::read_font` will take care of this for you.)
+    pub fn read_mac_font(mut i: impl Read) -> anyhow::Result<Font> {
+        let mut header_buf = [0; 26];
+        i.read_exact(&mut header_buf)
+            .context("Error reading NFNT header.")?;
+        let font_type = u16::from_be_bytes([header_buf[0], header_buf[1]]);
+        if font_type & 0x280 != 0 {
+            return Err(anyhow!("Color NFNTs are not supported."));
+        }
+        let first_glyph = u16::from_be_bytes([header_buf[2], header_buf[3]]);
+        let last_glyph = u16::from_be_bytes([header_buf[4], header_buf[5]]);
+        if last_glyph < first_glyph {
+            return Err(anyhow!(
+                "last_glyph is before first_glyph (nonsense!)"
+            ));
+        } else if last_glyph == 0xFFFF {
+            return Err(anyhow!(
+                "font didn't leave room for the fallback glyph"
+            ));
+        }
+        let kern_max = i16::from_be_bytes([header_buf[8], header_buf[9]]);
+        let font_height = u16::from_be_bytes([header_buf[14], header_buf[15]]);
+        let ascent = i16::from_be_bytes([header_buf[18], header_buf[19]]);
+        let descent = i16::from_be_bytes([header_buf[20], header_buf[21]]);
+        let leading = i16::from_be_bytes([header_buf[22], header_buf[23]]);
+        let rowshorts = u16::from_be_bytes([header_buf[24], header_buf[25]]);
+        let mut buf = vec![0; rowshorts as usize * 2 * font_height as usize];
+        i.read_exact(&mut buf)
+            .context("Error reading NFNT bitmap data.")?;
+        let bitmap = Bitmap::from_bytes(
+            rowshorts as u32 * 16,
+            font_height as u32,
+            &buf,
+        );
+        let num_glyphs = last_glyph as usize - first_glyph as usize + 2;
+        let num_locations = num_glyphs + 1;
+        buf.resize((num_locations + num_glyphs) * 2, 0);
+        i.read_exact(&mut buf)
+            .context("Error reading NFNT glyph location data.")?;
+        let mut glyph_locations = Vec::with_capacity(num_locations);
+        for i in (0..num_locations * 2).step_by(2) {
+            glyph_locations.push(u16::from_be_bytes([buf[i], buf[i + 1]]));
+        }
+        if glyph_locations.windows(2).any(|w| w[0] > w[1]) {
+            return Err(anyhow!("Glyph locations in NFNT were not in ascending order. The font is corrupted."));
+        }
+        if *glyph_locations.last().unwrap() as u32 > bitmap.get_width() {
+            return Err(anyhow!("Glyph locations in NFNT extend past the right edge of the bitmap. The font is corrupted."));
+        }
+        let mut glyph_offsetwidths = Vec::with_capacity(num_glyphs);
+        for i in (num_locations * 2..buf.len()).step_by(2) {
+            if (buf[i], buf[i + 1]) == (0xFF, 0xFF) {
+                glyph_offsetwidths.push((-1, 255));
+                continue;
+            }
+            // The stored offset is relative to kernMax.
+            let offset =
+                (buf[i] as i32 + kern_max as i32).try_into().map_err(
+                    |_| anyhow!("Glyph offset in NFNT is out of range."),
+                )?;
+            glyph_offsetwidths.push((offset, buf[i + 1]));
+        }
+        let mut glyph_widths = None;
+        let mut image_heights = None;
+        if font_type & 0x0003 != 0 {
+            // Skip the -1 that terminates the offset/width table. Each of the
+            // optional tables is terminated the same way.
+            buf.resize(2, 0);
+            i.read_exact(&mut buf)
+                .context("Error reading NFNT offset/width table.")?;
+            buf.resize(num_locations * 2, 0);
+            if font_type & 0x0002 != 0 {
+                i.read_exact(&mut buf)
+                    .context("Error reading NFNT glyph-width table.")?;
+                glyph_widths = Some(
+                    buf[..num_glyphs * 2]
+                        .chunks_exact(2)
+                        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+                        .collect(),
+                );
+            }
+            if font_type & 0x0001 != 0 {
+                i.read_exact(&mut buf)
+                    .context("Error reading NFNT image-height table.")?;
+                image_heights = Some(
+                    buf[..num_glyphs * 2]
+                        .chunks_exact(2)
+                        .map(|x| (x[0], x[1]))
+                        .collect(),
+                );
+            }
+        }
+        Ok(Font {
+            glyph_range: first_glyph..=last_glyph,
+            bitmap,
+            ascent,
+            descent,
+            leading,
+            glyph_locations,
+            glyph_offsetwidths,
+            glyph_widths,
+            image_heights,
+            kerning: HashMap::new(),
+            space_width: OnceLock::new(),
+            atlas: None,
+        })
+    }
+    /// Writes this font as a Macintosh Toolbox NFNT resource body, suitable
+    /// for `read_mac_font` or for putting into an `NFNT` resource. The
+    /// glyph-width and image-height tables are written if this font has them.
+    /// Kerning pairs are not written, since they belong in the `FOND`.
+    ///
+    /// Returns an error if this font uses an atlas; see `FontBuilder`.
+    pub fn write_mac_font(
+        &self,
+        mut writer: impl Write,
+    ) -> std::io::Result<()> {
+        if self.atlas.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Fonts that use an atlas can't be written as NFNT",
+            ));
+        }
+        let num_glyphs = self.glyph_offsetwidths.len();
+        let real_glyphs = || {
+            self.glyph_locations
+                .windows(2)
+                .zip(self.glyph_offsetwidths.iter())
+                .filter(|(_, offsetwidth)| **offsetwidth != (-1, 255))
+        };
+        let kern_max = real_glyphs()
+            .map(|(_, (offset, _))| *offset as i16)
+            .min()
+            .unwrap_or(0)
+            .min(0);
+        let wid_max = real_glyphs()
+            .map(|(_, (_, advance))| *advance as u16)
+            .max()
+            .unwrap_or(0);
+        let rect_right = real_glyphs()
+            .map(|(w, (offset, _))| *offset as i32 + (w[1] - w[0]) as i32)
+            .max()
+            .unwrap_or(0);
+        let rect_width = (rect_right - kern_max as i32).max(0) as u16;
+        let row_words = self.bitmap.get_width().div_ceil(16) as usize;
+        let row_bytes = row_words * 2;
+        let strike_bytes = row_bytes * self.bitmap.get_height() as usize;
+        // owTLoc is measured in words, from the owTLoc field itself.
+        let ow_t_loc =
+            (26 + strike_bytes + self.glyph_locations.len() * 2 - 16) / 2;
+        let n_descent = if ow_t_loc > 0xFFFF {
+            (ow_t_loc >> 16) as i16
+        } else {
+            -self.descent
+        };
+        let mut font_type = 0x9000u16;
+        if self.glyph_widths.is_some() {
+            font_type |= 0x0002;
+        }
+        if self.image_heights.is_some() {
+            font_type |= 0x0001;
+        }
+        let mut buf = Vec::with_capacity(26 + strike_bytes + num_glyphs * 8);
+        for x in [
+            font_type,
+            *self.glyph_range.start(),
+            *self.glyph_range.end(),
+            wid_max,
+            kern_max as u16,
+            n_descent as u16,
+            rect_width,
+            self.bitmap.get_height() as u16,
+            ow_t_loc as u16,
+            self.ascent as u16,
+            self.descent as u16,
+            self.leading as u16,
+            row_words as u16,
+        ] {
+            buf.extend_from_slice(&x.to_be_bytes());
+        }
+        let src_row_bytes = (self.bitmap.get_width() as usize).div_ceil(8);
+        for row in self.bitmap.to_bytes().chunks(src_row_bytes.max(1)) {
+            buf.extend_from_slice(row);
+            buf.resize(buf.len() + row_bytes - row.len(), 0);
+        }
+        for location in self.glyph_locations.iter() {
+            buf.extend_from_slice(&location.to_be_bytes());
+        }
+        for (offset, advance) in self.glyph_offsetwidths.iter() {
+            if (*offset, *advance) == (-1, 255) {
+                buf.extend_from_slice(&[0xFF, 0xFF]);
+            } else {
+                buf.push((*offset as i16 - kern_max) as u8);
+                buf.push(*advance);
+            }
+        }
+        buf.extend_from_slice(&[0xFF, 0xFF]);
+        if let Some(glyph_widths) = self.glyph_widths.as_ref() {
+            for width in glyph_widths.iter() {
+                buf.extend_from_slice(&width.to_be_bytes());
+            }
+            buf.extend_from_slice(&[0xFF, 0xFF]);
+        }
+        if let Some(image_heights) = self.image_heights.as_ref() {
+            for (first_row, num_rows) in image_heights.iter() {
+                buf.extend_from_slice(&[*first_row, *num_rows]);
+            }
+            buf.extend_from_slice(&[0xFF, 0xFF]);
+        }
+        writer.write_all(&buf)
+    }
+    /// Get the number of pixels that are above the baseline.
+    pub fn get_ascent(&self) -> i32 {
+        self.ascent as i32
+    }
+    /// Get the number of pixels that are below the baseline.
+    pub fn get_descent(&self) -> i32 {
+        self.descent as i32
+    }
+    /// Get the number of pixels that should be added/removed between
+    /// lines (in addition to the ascent and descent).
+    pub fn get_leading(&self) -> i32 {
+        self.leading as i32
+    }
+    /// Get a reference to the entire font bitmap.
+    pub fn get_bitmap(&self) -> &Bitmap {
+        &self.bitmap
+    }
+    /// Gets enough information to render a given glyph in this font.
+    /// Returns:
+    /// - The rectangle within the font bitmap that contains this glyph's
+    ///   pixels.
+    /// - The amount to add to the pen X coordinate to get the left edge
+    ///   of the glyph bitmap.
+    /// - The amount to advance the pen X coordinate by, where the next
+    ///   character should begin.
+    /// - True if the glyph actually exists in the font. (If false, the
+    ///   returned information is for the fallback glyph.)
+    pub fn get_glyph(&self, glyph_id: u16) -> (Rectangle, i32, u32, bool) {
+        let (glyph_index, present) = self.get_glyph_index(glyph_id);
+        if let Some(atlas) = self.atlas.as_ref() {
+            let glyph = atlas.get(glyph_index);
+            return (glyph.rect, glyph.offset, glyph.advance, present);
+        }
+        let (offset, advance) = self.glyph_offsetwidths[glyph_index];
+        let left = self.glyph_locations[glyph_index] as i32;
+        let right = self.glyph_locations[glyph_index + 1] as i32;
+        (
+            Rectangle {
+                left,
+                top: 0,
+                right,
+                bottom: self.bitmap.get_height() as i32,
+            },
+            offset as i32,
+            advance as u32,
+            present,
+        )
+    }
+    /// Returns the index into our tables of the given glyph, and whether the
+    /// glyph is present. (If it isn't, the index is that of the missing
+    /// glyph.)
+    fn get_glyph_index(&self, glyph_id: u16) -> (usize, bool) {
+        if self.glyph_range.contains(&glyph_id) {
+            let glyph_index = (glyph_id - self.glyph_range.start()) as usize;
+            let present = match self.atlas.as_ref() {
+                Some(atlas) => atlas.is_present(glyph_index),
+                None => self.glyph_offsetwidths[glyph_index] != (-1, 255),
+            };
+            if present {
+                return (glyph_index, true);
+            }
+        }
+        (self.glyph_range.clone().count(), false)
+    }
+    /// Returns the fractional advance of the given glyph (or the missing
+    /// glyph), as 8.8 fixed point, if this font has a glyph-width table.
+    pub fn get_fractional_advance(&self, glyph_id: u16) -> Option<u32> {
+        let (glyph_index, _) = self.get_glyph_index(glyph_id);
+        self.glyph_widths
+            .as_ref()
+            .map(|widths| widths[glyph_index] as u32)
+    }
+    /// Returns the first row of the font bitmap that contains any set pixels
+    /// of the given glyph (or the missing glyph), and how many rows, if this
+    /// font has an image-height table.
+    pub fn get_image_height(&self, glyph_id: u16) -> Option<(u32, u32)> {
+        let (glyph_index, _) = self.get_glyph_index(glyph_id);
+        self.image_heights.as_ref().map(|heights| {
+            let (first_row, num_rows) = heights[glyph_index];
+            (first_row as u32, num_rows as u32)
+        })
+    }
+    /// Returns the number of pixels to add to the pen X coordinate between
+    /// drawing `left` and drawing `right`. (Usually negative or zero.)
+    pub fn get_kerning(&self, left: u16, right: u16) -> i32 {
+        self.kerning.get(&(left, right)).copied().unwrap_or(0)
+    }
+    /// Replaces this font's kerning pairs. Each pair of glyphs is given the
+    /// number of pixels to add to the pen X coordinate between them.
+    pub fn set_kerning(
+        &mut self,
+        pairs: impl IntoIterator<Item = ((u16, u16), i32)>,
+    ) {
+        self.kerning = pairs.into_iter().filter(|x| x.1 != 0).collect();
+    }
+    /// Makes a version of this font that is bolder.
+    ///
+    /// - Every glyph is made to advance by one more pixel.
+    /// - For every marked pixel in a glyph, its neighbor to the right is also
+    ///   marked. (A one-pixel stem becomes two, a two-pixel stem becomes
+    ///   three, etc.)
+    pub fn make_bold(&self) -> Font {
+        if self.atlas.is_some() {
+            return self.map_atlas(Font::make_bold);
+        }
+        let mut new_glyph_locations =
+            Vec::with_capacity(self.glyph_locations.len());
+        new_glyph_locations.push(0);
+        let mut x = 0;
+        for w in self.glyph_locations.windows(2) {
+            let (start, stop) = (w[0], w[1]);
+            if start != stop {
+                x = x + (stop - start) + 1;
+            }
+            new_glyph_locations.push(x);
+        }
+        let mut new_bitmap = Bitmap::new(
+            *new_glyph_locations.last().unwrap() as u32,
+            self.bitmap.height,
+        );
+        let mut dst_x = 0;
+        for w in self.glyph_locations.windows(2) {
+            let (start, stop) = (w[0], w[1]);
+            if start != stop {
+                let dst_left = dst_x;
+                dst_x += stop - start + 1;
+                new_bitmap.blit_bits(
+                    ModeCopy(()),
+                    None,
+                    &self.bitmap,
+                    Some(Rectangle {
+                        left: start as i32,
+                        right: stop as i32,
+                        ..self.bitmap.get_bounds()
+                    }),
+                    dst_left as i32,
+                    0,
+                );
+                new_bitmap.blit_bits(
+                    ModeOr(()),
+                    None,
+                    &self.bitmap,
+                    Some(Rectangle {
+                        left: start as i32,
+                        right: stop as i32,
+                        ..self.bitmap.get_bounds()
+                    }),
+                    dst_left as i32 + 1,
+                    0,
+                );
+            }
+        }
+        Font {
+            glyph_range: self.glyph_range.clone(),
+            bitmap: new_bitmap,
+            ascent: self.ascent,
+            descent: self.descent,
+            leading: self.leading,
+            glyph_locations: new_glyph_locations,
+            glyph_offsetwidths: self
+                .glyph_offsetwidths
+                .iter()
+                .map(|(offset, advance)| (*offset, advance.saturating_add(1)))
+                .collect(),
+            glyph_widths: self.glyph_widths.as_ref().map(|widths| {
+                widths.iter().map(|x| x.saturating_add(0x100)).collect()
+            }),
+            image_heights: self.image_heights.clone(),
+            kerning: self.kerning.clone(),
+            space_width: OnceLock::new(),
+            atlas: None,
+        }
+    }
+    /// Makes a version of this font that is italic. (If it is also to be made
+    /// bold, that should be done first. `derive_style` takes care of this.)
+    ///
+    /// - Each two rows is offset one pixel to the left of the two rows above
+    ///   it.
+    pub fn make_italic(&self) -> Font {
+        if self.atlas.is_some() {
+            return self.map_atlas(Font::make_italic);
+        }
+        let total_height = self.get_ascent() + self.get_descent();
+        let (num_steps, offset_offset) = if total_height & 1 == 0 {
+            (total_height / 2, 0)
+        } else {
+            (((total_height + 1) / 2), 1)
+        };
+        let mut new_glyph_locations =
+            Vec::with_capacity(self.glyph_locations.len());
+        new_glyph_locations.push(0);
+        let mut x = 0;
+        for w in self.glyph_locations.windows(2) {
+            let (start, stop) = (w[0], w[1]);
+            if start != stop {
+                x += (stop - start) + (num_steps - 1).max(0) as u16;
+            }
+            new_glyph_locations.push(x);
+        }
+        let mut new_bitmap = Bitmap::new(
+            *new_glyph_locations.last().unwrap() as u32,
+            self.bitmap.height,
+        );
+        let mut dst_x = 0;
+        for w in self.glyph_locations.windows(2) {
+            let (start, stop) = (w[0], w[1]);
+            if start != stop {
+                let dst_left = dst_x;
+                dst_x += (stop - start) + (num_steps - 1).max(0) as u16;
+                for offset in 0..num_steps {
+                    let bottom = (num_steps - offset) * 2 - offset_offset;
+                    new_bitmap.blit_bits(
+                        ModeCopy(()),
+                        None,
+                        &self.bitmap,
+                        Some(Rectangle {
+                            left: start as i32,
+                            right: stop as i32,
+                            top: bottom - 2,
+                            bottom,
+                        }),
+                        dst_left as i32 + offset,
+                        bottom - 2,
+                    );
+                }
+            }
+        }
+        Font {
+            glyph_range: self.glyph_range.clone(),
+            bitmap: new_bitmap,
+            ascent: self.ascent,
+            descent: self.descent,
+            leading: self.leading,
+            glyph_locations: new_glyph_locations,
+            glyph_offsetwidths: self.glyph_offsetwidths.clone(),
+            glyph_widths: self.glyph_widths.clone(),
+            image_heights: self.image_heights.clone(),
+            kerning: self.kerning.clone(),
+            space_width: OnceLock::new(),
+            atlas: None,
+        }
+    }
+    /// Makes a version of this font that is underlined. (If it is also to be
+    /// made bold and/or italic, those should be done first. `derive_style`
+    /// takes care of this.)
+    ///
+    /// - If the glyph bitmap is not wide enough to contain both its start and
+    ///   end pen points, it is widened accordingly.
+    /// - A one-pixel thick horizontal line is drawn on the second row below
+    ///   the baseline, starting at the start pen point, and ending at the end
+    ///   pen point.
+    /// - Any part of this line which has a set pixel on any of its 8 neighbors
+    ///   is erased.
+    pub fn make_underline(&self) -> Font {
+        if self.atlas.is_some() {
+            return self.map_atlas(Font::make_underline);
+        }
+        let mut new_glyph_locations =
+            Vec::with_capacity(self.glyph_locations.len());
+        let mut new_glyph_offsetwidths =
+            Vec::with_capacity(self.glyph_offsetwidths.len());
+        new_glyph_locations.push(0);
+        let mut x = 0;
+        for (w, (offset, advance)) in self
+            .glyph_locations
+            .windows(2)
+            .zip(self.glyph_offsetwidths.iter())
+        {
+            if (*offset, *advance) == (!0, !0) {
+                new_glyph_locations.push(x);
+                new_glyph_offsetwidths.push((*offset, *advance));
+                continue;
+            }
+            let (mut offset, advance) = (*offset, *advance);
+            let (start, stop) = (w[0], w[1]);
+            let mut width = stop - start;
+            if offset > 0 {
+                // extend bitmap to left
+                width += offset as u16;
+                offset = 0;
+            }
+            if (advance as u16) > width {
+                // extend bitmap to right
+                let shortfall = advance as u16 - width;
+                width += shortfall;
+            }
+            x += width;
+            new_glyph_locations.push(x);
+            new_glyph_offsetwidths.push((offset, advance));
+        }
+        let new_descent = self.descent.max(2);
+        let new_height = self
+            .bitmap
+            .get_height()
+            .max(self.ascent as u32 + new_descent as u32);
+        let mut new_bitmap = Bitmap::new(
+            *new_glyph_locations.last().unwrap() as u32,
+            new_height,
+        );
+        let mut dst_x = 0;
+        for (w, (offset, advance)) in self
+            .glyph_locations
+            .windows(2)
+            .zip(self.glyph_offsetwidths.iter())
+        {
+            if (*offset, *advance) == (!0, !0) {
+                continue;
+            }
+            let (start, stop) = (w[0], w[1]);
+            let mut width = stop - start;
+            let (offset, advance) = (*offset, *advance);
+            let mut dst_left = dst_x;
+            let mut underline_left = dst_x;
+            if offset > 0 {
+                // extend bitmap to left
+                width += offset as u16;
+                dst_left += offset as i32;
+            } else {
+                // push underline to right
+                underline_left -= offset as i32;
+            }
+            if (advance as u16) > width {
+                // extend bitmap to right
+                let shortfall = advance as u16 - width;
+                width += shortfall;
+            }
+            let underline_right = underline_left + advance as i32;
+            new_bitmap.fill_rect(
+                ModeCopy(()),
+                None,
+                Rectangle {
+                    left: underline_left,
+                    right: underline_right,
+                    top: self.ascent as i32 + 1,
+                    bottom: self.ascent as i32 + 2,
+                },
+            );
+            for dy in [-1, 0, 1] {
+                for dx in [-1, 0, 1] {
+                    if dx == dy {
+                        continue;
+                    }
+                    new_bitmap.blit_bits(
+                        ModeInverseAnd(()),
+                        Some(Rectangle {
+                            left: dst_x,
+                            right: dst_x + width as i32,
+                            ..new_bitmap.get_bounds()
+                        }),
+                        &self.bitmap,
+                        Some(Rectangle {
+                            left: start as i32,
+                            right: stop as i32,
+                            ..self.bitmap.get_bounds()
+                        }),
+                        dst_left + dx,
+                        dy,
+                    );
+                }
+            }
+            new_bitmap.blit_bits(
+                ModeOr(()),
+                None,
+                &self.bitmap,
+                Some(Rectangle {
+                    left: start as i32,
+                    right: stop as i32,
+                    ..self.bitmap.get_bounds()
+                }),
+                dst_left,
+                0,
+            );
+            dst_x += width as i32;
+        }
+        Font {
+            glyph_range: self.glyph_range.clone(),
+            bitmap: new_bitmap,
+            ascent: self.ascent,
+            descent: new_descent,
+            leading: self.leading,
+            glyph_locations: new_glyph_locations,
+            glyph_offsetwidths: new_glyph_offsetwidths,
+            glyph_widths: self.glyph_widths.clone(),
+            // The underline changes which rows are touched.
+            image_heights: None,
+            kerning: self.kerning.clone(),
+            space_width: OnceLock::new(),
+            atlas: None,
+        }
+    }
+    /// Makes a version of this font that is outlined. (If it is also to be
+    /// made bold, italic, and/or underlined, those should be done first.)
+    ///
+    /// - Every glyph is made to advance by one more pixel, and its image
+    ///   grows by one pixel on every side.
+    /// - Every pixel that is next to (including diagonally) a marked pixel,
+    ///   but is not itself marked, is marked. Marked pixels are then erased,
+    ///   leaving only the outline.
+    /// - The ascent and descent each grow by one pixel.
+    pub fn make_outline(&self) -> Font {
+        self.make_hollow(1)
+    }
+    /// Makes a version of this font that is shadowed. (If it is also to be
+    /// made bold, italic, and/or underlined, those should be done first. It
+    /// shouldn't also be outlined; the shadow includes an outline.)
+    ///
+    /// - Every glyph is made to advance by two more pixels, and its image
+    ///   grows by one pixel on the top and left, and by two pixels on the
+    ///   bottom and right.
+    /// - As with `make_outline`, but the outline is two pixels thick on the
+    ///   bottom and right.
+    /// - The ascent grows by one pixel, and the descent by two.
+    pub fn make_shadow(&self) -> Font {
+        self.make_hollow(2)
+    }
+    /// Makes a version of this font with its glyphs closer together. Every
+    /// glyph advances by `n` fewer pixels (but never less than zero). The
+    /// glyph images are unchanged.
+    pub fn make_condensed(&self, n: u8) -> Font {
+        self.adjust_advances(-(n as i32))
+    }
+    /// Makes a version of this font with its glyphs further apart. Every
+    /// glyph advances by `n` more pixels. The glyph images are unchanged.
+    pub fn make_extended(&self, n: u8) -> Font {
+        self.adjust_advances(n as i32)
+    }
+    /// Implements `make_condensed` and `make_extended`. The fractional
+    /// advances, if any, are changed by the same amount as the whole ones.
+    fn adjust_advances(&self, delta: i32) -> Font {
+        let mut ret = self.clone();
+        ret.space_width = OnceLock::new();
+        let mut adjusted = vec![];
+        if let Some(atlas) = ret.atlas.as_mut() {
+            for (n, advance) in atlas.advances_mut() {
+                let new_advance = (*advance as i32 + delta).max(0) as u32;
+                adjusted.push((n, new_advance as i32 - *advance as i32));
+                *advance = new_advance;
+            }
+        } else {
+            for (n, (offset, advance)) in
+                ret.glyph_offsetwidths.iter_mut().enumerate()
+            {
+                if (*offset, *advance) == (-1, 255) {
+                    continue;
+                }
+                let new_advance =
+                    (*advance as i32 + delta).clamp(0, 255) as u8;
+                adjusted.push((n, new_advance as i32 - *advance as i32));
+                *advance = new_advance;
+            }
+        }
+        if let Some(glyph_widths) = ret.glyph_widths.as_mut() {
+            for (n, delta) in adjusted {
+                glyph_widths[n] = (glyph_widths[n] as i32 + delta * 0x100)
+                    .clamp(0, 0xFFFF) as u16;
+            }
+        }
+        ret
+    }
+    /// Implements `make_outline` and `make_shadow`. `reach` is how far the
+    /// outline extends to the bottom and right. (It always extends one pixel
+    /// to the top and left.)
+    fn make_hollow(&self, reach: u16) -> Font {
+        if self.atlas.is_some() {
+            return self.map_atlas(|font| font.make_hollow(reach));
+        }
+        let growth = 1 + reach;
+        let mut new_glyph_locations =
+            Vec::with_capacity(self.glyph_locations.len());
+        new_glyph_locations.push(0);
+        let mut x = 0;
+        for w in self.glyph_locations.windows(2) {
+            let (start, stop) = (w[0], w[1]);
+            if start != stop {
+                x += (stop - start) + growth;
+            }
+            new_glyph_locations.push(x);
+        }
+        let mut new_bitmap = Bitmap::new(
+            *new_glyph_locations.last().unwrap() as u32,
+            self.bitmap.height + growth as u32,
+        );
+        for (w, new_left) in self
+            .glyph_locations
+            .windows(2)
+            .zip(new_glyph_locations.iter())
+        {
+            let (start, stop) = (w[0], w[1]);
+            if start == stop {
+                continue;
+            }
+            let src_rect = Some(Rectangle {
+                left: start as i32,
+                right: stop as i32,
+                ..self.bitmap.get_bounds()
+            });
+            // Where the unaltered glyph lands
+            let dst_left = *new_left as i32 + 1;
+            for dy in -1..=reach as i32 {
+                for dx in -1..=reach as i32 {
+                    new_bitmap.blit_bits(
+                        ModeOr(()),
+                        None,
+                        &self.bitmap,
+                        src_rect,
+                        dst_left + dx,
+                        1 + dy,
+                    );
+                }
+            }
+            new_bitmap.blit_bits(
+                ModeInverseAnd(()),
+                None,
+                &self.bitmap,
+                src_rect,
+                dst_left,
+                1,
+            );
+        }
+        Font {
+            glyph_range: self.glyph_range.clone(),
+            bitmap: new_bitmap,
+            ascent: self.ascent + 1,
+            descent: self.descent + reach as i16,
+            leading: self.leading,
+            glyph_locations: new_glyph_locations,
+            glyph_offsetwidths: self
+                .glyph_offsetwidths
+                .iter()
+                .map(|(offset, advance)| {
+                    if (*offset, *advance) == (-1, 255) {
+                        (*offset, *advance)
+                    } else {
+                        (
+                            offset.saturating_sub(1),
+                            advance.saturating_add(reach as u8),
+                        )
+                    }
+                })
+                .collect(),
+            glyph_widths: self.glyph_widths.as_ref().map(|widths| {
+                widths
+                    .iter()
+                    .map(|x| x.saturating_add(reach * 0x100))
+                    .collect()
+            }),
+            image_heights: self.image_heights.as_ref().map(|heights| {
+                heights
+                    .iter()
+                    .map(|(first_row, num_rows)| {
+                        if *num_rows == 0 {
+                            (*first_row, 0)
+                        } else {
+                            (*first_row, num_rows.saturating_add(growth as u8))
+                        }
+                    })
+                    .collect()
+            }),
+            kerning: self.kerning.clone(),
+            space_width: OnceLock::new(),
+            atlas: None,
+        }
+    }
+    /// Cuts the given glyph (or the missing glyph) out of the strike, with
+    /// blank rows above and below it trimmed away.
+    fn get_loose_glyph(&self, glyph_id: u16) -> LooseGlyph {
+        let (mut rect, offset, advance, _) = self.get_glyph(glyph_id);
+        // The top of the glyph's cell is always at the ascent.
+        let cell_top = rect.top;
+        let mut bitmap = Bitmap::new(rect.get_width(), 1);
+        let mut row_is_blank = |y: i32| {
+            bitmap.blit_bits(
+                ModeCopy(()),
+                None,
+                &self.bitmap,
+                Some(Rectangle {
+                    top: y,
+                    bottom: y + 1,
+                    ..rect
+                }),
+                0,
+                0,
+            );
+            bitmap.to_bytes().iter().all(|x| *x == 0)
+        };
+        while rect.top < rect.bottom && row_is_blank(rect.top) {
+            rect.top += 1;
+        }
+        while rect.top < rect.bottom && row_is_blank(rect.bottom - 1) {
+            rect.bottom -= 1;
+        }
+        let mut bitmap = Bitmap::new(rect.get_width(), rect.get_height());
+        bitmap.blit_bits(ModeCopy(()), None, &self.bitmap, Some(rect), 0, 0);
+        LooseGlyph {
+            bitmap,
+            top: self.get_ascent() - (rect.top - cell_top),
+            offset,
+            advance,
+        }
+    }
+    /// Packs individual glyph bitmaps into a new strike. Glyph IDs between the
+    /// lowest and highest in `glyphs` that aren't present will be missing.
+    ///
+    /// The strike will be made tall enough to contain every glyph, even if
+    /// some of them stick out above `ascent` or below `descent`. If this
+    /// happens, the leading is reduced so that the distance between lines
+    /// stays the same.
+    ///
+    /// If the glyphs won't fit in a strike (because they're too wide in
+    /// total, or some offset or advance is out of range), they're packed into
+    /// an atlas instead.
+    fn from_loose_glyphs(
+        glyphs: &BTreeMap<u16, LooseGlyph>,
+        missing_glyph: &LooseGlyph,
+        ascent: i32,
+        descent: i32,
+        leading: i32,
+    ) -> anyhow::Result<Font> {
+        let first_glyph = glyphs.keys().next().copied().unwrap_or(0);
+        let last_glyph = glyphs.keys().next_back().copied().unwrap_or(0);
+        if last_glyph == MISSING_GLYPH {
+            return Err(anyhow!(
+                "font didn't leave room for the fallback glyph"
+            ));
+        }
+        let (strike_ascent, strike_descent, strike_leading) =
+            fit_metrics(glyphs, missing_glyph, ascent, descent, leading);
+        let mut glyph_locations = vec![0u32];
+        let mut glyph_offsetwidths = vec![];
+        let mut placements = vec![];
+        let slots = (first_glyph..=last_glyph)
+            .map(|id| glyphs.get(&id))
+            .chain(std::iter::once(Some(missing_glyph)));
+        for glyph in slots {
+            let x = *glyph_locations.last().unwrap();
+            match glyph {
+                Some(glyph) => {
+                    let (Ok(offset), Ok(advance)) =
+                        (glyph.offset.try_into(), glyph.advance.try_into())
+                    else {
+                        return Font::pack_atlas(
+                            glyphs,
+                            missing_glyph,
+                            ascent,
+                            descent,
+                            leading,
+                        );
+                    };
+                    glyph_locations.push(x + glyph.bitmap.get_width());
+                    glyph_offsetwidths.push((offset, advance));
+                    placements.push((glyph, x));
+                }
+                None => {
+                    glyph_locations.push(x);
+                    glyph_offsetwidths.push((-1, 255));
+                }
+            }
+        }
+        let strike_width = *glyph_locations.last().unwrap();
+        if strike_width > u16::MAX as u32 {
+            return Font::pack_atlas(
+                glyphs,
+                missing_glyph,
+                ascent,
+                descent,
+                leading,
+            );
+        }
+        let mut bitmap = Bitmap::new(
+            strike_width,
+            (strike_ascent + strike_descent).max(0) as u32,
+        );
+        for (glyph, x) in placements {
+            bitmap.blit_bits(
+                ModeCopy(()),
+                None,
+                &glyph.bitmap,
+                None,
+                x as i32,
+                strike_ascent - glyph.top,
+            );
+        }
+        Ok(Font {
+            glyph_range: first_glyph..=last_glyph,
+            bitmap,
+            ascent: strike_ascent.try_into()?,
+            descent: strike_descent.try_into()?,
+            leading: strike_leading.try_into()?,
+            glyph_locations: glyph_locations
+                .into_iter()
+                .map(|x| x as u16)
+                .collect(),
+            glyph_offsetwidths,
+            glyph_widths: None,
+            image_heights: None,
+            kerning: HashMap::new(),
+            space_width: OnceLock::new(),
+            atlas: None,
+        })
+    }
+    /// Measure a single glyph from the first font that contains it, or the
+    /// "missing character" glyph if none do.
+    pub fn measure_glyph(fonts: &[&Font], glyph: u16) -> GlyphMeasurement {
+        let (font, rect, offset, advance, present) =
+            lookup_glyph(fonts, glyph);
+        GlyphMeasurement {
+            drawn_rectangle: get_glyph_rectangle(font, rect, offset),
+            advance,
+            present,
+        }
+    }
+    /// Measure a whole stream of `TextElement`s, exactly as `draw_text` would
+    /// draw them, without drawing anything. The pen starts at (0, 0), so all
+    /// of the returned coordinates are relative to the starting pen position.
+    ///
+    /// See `Bitmap::draw_text` for the meaning of `get_font`.
+    pub fn measure_text<'a>(
+        get_font: impl Fn(usize) -> Option<&'a Font>,
+        elements: impl IntoIterator<Item = TextElement>,
+    ) -> TextMeasurements {
+        walk_text(get_font, 0, 0, elements, |fonts, x, y, glyph| {
+            let measurement = Font::measure_glyph(fonts, glyph);
+            GlyphMeasurement {
+                drawn_rectangle: measurement.drawn_rectangle.offset_by(x, y),
+                ..measurement
+            }
+        })
+    }
+    /// Returns the "advance" measurement for the space glyph. This is the only
+    /// part of this code that assumes a particular glyph has a particular
+    /// purpose. It assumes the space glyph is glyph 0x20 (ASCII space). You
+    /// could obtain this value yourself by calling `measure_glyph`, but this
+    /// value is often needed if it's needed at all, and this method caches the
+    /// value after the first call.
+    pub fn get_space_width(&self) -> u32 {
+        if let Some(space_width) = self.space_width.get() {
+            *space_width
+        } else {
+            let ret = Font::measure_glyph(&[self], 0x20).advance;
+            // It's harmless if we attempt to set it more than once.
+            let _ = self.space_width.set(ret);
+            ret
+        }
+    }
+}
+
+impl Bitmap {
+    /// Render the given glyph from the first font that contains it, or the
+    /// missing glyph from the first font, at the given pen position. Returns
+    /// the measurements of the glyph. You should advance the pen X by
+    /// `measurements.advance` at the very least.
+    pub fn draw_glyph<'c, Mode: TransferMode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        pen_x: i32,
+        pen_y: i32,
+        fonts: &[&Font],
+        glyph: u16,
+    ) -> GlyphMeasurement {
+        let (font, rect, offset, advance, present) =
+            lookup_glyph(fonts, glyph);
+        let (draw_x, draw_y) = (pen_x + offset, pen_y - font.get_ascent());
+        self.blit_bits(
+            &mode,
+            clip,
+            font.get_bitmap(),
+            Some(rect),
+            draw_x,
+            draw_y,
+        );
+        GlyphMeasurement {
+            drawn_rectangle: get_glyph_rectangle(font, rect, offset)
+                .offset_by(pen_x, pen_y),
+            advance,
+            present,
+        }
+    }
+    /// Render a whole stream of `TextElement`s, starting at the given pen
+    /// position. `get_font` is called with a font index and should return the
+    /// font with that index, or `None` if there is no such font. Fonts are
+    /// numbered consecutively starting at zero, and font zero is the current
+    /// font at the start of the text.
+    ///
+    /// When a glyph is not present in the current font, the other fonts are
+    /// searched in index order. If none of them have it, the current font's
+    /// missing glyph is drawn.
+    ///
+    /// Two glyphs in a row that come from the same font are kerned according
+    /// to that font's kerning pairs. Any other element in between them
+    /// prevents this. Pairs are looked up in the order the glyphs appear in
+    /// the stream, whichever way the pen is moving.
+    ///
+    /// Panics if there is no font zero, or if the stream switches to a font
+    /// index that doesn't exist.
+    pub fn draw_text<'a, 'c, Mode: TransferMode>(
+        &mut self,
+        mode: Mode,
+        clip: impl Into<Clip<'c>>,
+        pen_x: i32,
+        pen_y: i32,
+        get_font: impl Fn(usize) -> Option<&'a Font>,
+        elements: impl IntoIterator<Item = TextElement>,
+    ) -> TextMeasurements {
+        let clip: Clip = clip.into();
+        walk_text(get_font, pen_x, pen_y, elements, |fonts, x, y, glyph| {
+            self.draw_glyph(&mode, clip, x, y, fonts, glyph)
+        })
+    }
+}
+
+/// Walks a stream of `TextElement`s, keeping track of the pen and the current
+/// fallback chain, and calling `handle_glyph` for each glyph. `handle_glyph`
+/// is given the fallback chain, the pen position, and the glyph, and returns
+/// the glyph's measurements with `drawn_rectangle` relative to the bitmap
+/// (not the pen).
+fn walk_text<'a>(
+    get_font: impl Fn(usize) -> Option<&'a Font>,
+    pen_x: i32,
+    pen_y: i32,
+    elements: impl IntoIterator<Item = TextElement>,
+    mut handle_glyph: impl FnMut(&[&'a Font], i32, i32, u16) -> GlyphMeasurement,
+) -> TextMeasurements {
+    let all_fonts = collect_fonts(get_font);
+    let mut fonts = all_fonts.clone();
+    let (mut x, mut y) = (pen_x, pen_y);
+    let mut drawn_rectangle = Rectangle::default();
+    let mut glyph_pens = Vec::new();
+    let mut glyph_advances = Vec::new();
+    // 1 if the pen moves to the right, -1 if it moves to the left.
+    let mut sign = 1;
+    // The previous glyph, if it was immediately before this element.
+    let mut previous_glyph = None;
+    for element in elements {
+        if let TextElement::DrawGlyph(glyph) = element {
+            if let Some(previous_glyph) = previous_glyph {
+                x += sign * get_pair_kerning(&fonts, previous_glyph, glyph);
+            }
+            previous_glyph = Some(glyph);
+        } else {
+            previous_glyph = None;
+        }
+        match element {
+            TextElement::DrawGlyph(glyph) => {
+                glyph_pens.push((x, y));
+                if sign < 0 {
+                    // The glyph is drawn to the left of the pen, so we have
+                    // to know how far to back up before drawing it.
+                    let (_, _, _, advance, _) = lookup_glyph(&fonts, glyph);
+                    x -= advance as i32;
+                }
+                let measurement = handle_glyph(&fonts, x, y, glyph);
+                drawn_rectangle =
+                    drawn_rectangle.union(measurement.drawn_rectangle);
+                glyph_advances.push(sign * measurement.advance as i32);
+                if sign > 0 {
+                    x += measurement.advance as i32;
+                }
+            }
+            TextElement::SetDirection(direction) => {
+                sign = match direction {
+                    Direction::LeftToRight => 1,
+                    Direction::RightToLeft => -1,
+                };
+            }
+            TextElement::SwitchFont(index) => {
+                fonts = fallback_chain(&all_fonts, index);
+            }
+            TextElement::MovePen(dx, dy) => {
+                x += dx;
+                y += dy;
+            }
+            TextElement::Space => {
+                x += sign * fonts[0].get_space_width() as i32;
+            }
+            TextElement::NewLine => {
+                x = pen_x;
+                y += fonts[0].get_ascent()
+                    + fonts[0].get_descent()
+                    + fonts[0].get_leading();
+            }
+        }
+    }
+    TextMeasurements {
+        drawn_rectangle,
+        pen_start: (pen_x, pen_y),
+        pen_end: (x, y),
+        glyph_pens,
+        glyph_advances,
+    }
+}
+
+/// Returns the kerning between two glyphs, if they both come from the same
+/// font in the fallback chain.
+fn get_pair_kerning(fonts: &[&Font], left: u16, right: u16) -> i32 {
+    let (left_font, ..) = lookup_glyph(fonts, left);
+    let (right_font, ..) = lookup_glyph(fonts, right);
+    if std::ptr::eq(left_font, right_font) {
+        left_font.get_kerning(left, right)
+    } else {
+        0
+    }
+}
+
+/// Calls `get_font` with ascending indices until it returns `None`, and
+/// returns all the fonts it gave us.
+fn collect_fonts<'a>(
+    get_font: impl Fn(usize) -> Option<&'a Font>,
+) -> Vec<&'a Font> {
+    let ret: Vec<&Font> = (0..).map_while(get_font).collect();
+    if ret.is_empty() {
+        panic!("At least one font must be provided!");
+    }
+    ret
+}
+
+/// Returns a list of fonts with the given font first, followed by all the
+/// other fonts in index order.
+fn fallback_chain<'a>(
+    all_fonts: &[&'a Font],
+    current: usize,
+) -> Vec<&'a Font> {
+    let first = all_fonts.get(current).unwrap_or_else(|| {
+        panic!("Text switched to font {current}, which doesn't exist!")
+    });
+    std::iter::once(*first)
+        .chain(
+            all_fonts
+                .iter()
+                .enumerate()
+                .filter(|(n, _)| *n != current)
+                .map(|(_, font)| *font),
+        )
+        .collect()
+}
+
+/// A single glyph that is going to be packed into a new strike, by
+/// `Font::from_loose_glyphs`.
+#[derive(Clone)]
+struct LooseGlyph {
+    /// The glyph's pixels.
+    bitmap: Bitmap,
+    /// How far the top row of `bitmap` is above the baseline.
+    top: i32,
+    /// The amount to add to the pen X coordinate to get the left edge of
+    /// `bitmap`.
+    offset: i32,
+    /// The amount to advance the pen X coordinate by.
+    advance: u32,
+}
+
+impl LooseGlyph {
+    /// The glyph's image, relative to the pen, with Y increasing downwards.
+    fn get_bounds(&self) -> Rectangle {
+        Rectangle {
+            left: self.offset,
+            top: -self.top,
+            right: self.offset + self.bitmap.get_width() as i32,
+            bottom: self.bitmap.get_height() as i32 - self.top,
+        }
+    }
+}
+
+/// One element of a stream of text to be rendered by `draw_text`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextElement {
+    /// Draw the given glyph from the current font (or a fallback font) and
+    /// advance the pen accordingly.
+    DrawGlyph(u16),
+    /// Make the font with the given index the current font. Subsequent
+    /// glyphs will be looked up in this font first, and then in the other
+    /// fonts in index order.
+    SwitchFont(usize),
+    /// Move the pen by the given X and Y amounts without drawing anything.
+    MovePen(i32, i32),
+    /// Advance the pen by the width of the current font's space glyph
+    /// without drawing anything.
+    Space,
+    /// Move the pen back to the X coordinate it started at, and down by the
+    /// current font's line height (ascent + descent + leading).
+    NewLine,
+    /// Change which way the pen moves when drawing glyphs and spaces. When
+    /// the pen moves right to left, each glyph is drawn with its advance
+    /// ending at the pen, instead of starting there, so that the pen ends up
+    /// on its left. `MovePen` is not affected.
+    ///
+    /// The pen starts out moving left to right. This does not reorder
+    /// anything; see `encode_text_bidi` for that.
+    SetDirection(Direction),
+}
+
+/// Which way the pen moves as text is drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Glyphs are drawn to the right of the pen, and the pen moves right.
+    #[default]
+    LeftToRight,
+    /// Glyphs are drawn to the left of the pen, and the pen moves left.
+    RightToLeft,
+}
+
+/// The measurements of a whole run of text.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextMeasurements {
+    /// The smallest rectangle that contains every glyph that was drawn.
+    pub drawn_rectangle: Rectangle,
+    /// Where the pen was before the first element.
+    pub pen_start: (i32, i32),
+    /// Where the pen was after the last element.
+    pub pen_end: (i32, i32),
+    /// Where the pen was before each glyph, in the order they were drawn.
+    /// Together with `pen_end`, these are the possible caret positions. When
+    /// the pen is moving right to left, this is the glyph's right edge.
+    pub glyph_pens: Vec<(i32, i32)>,
+    /// How far the pen moved for each glyph, not counting kerning, in the
+    /// order they were drawn. This is negative when the pen was moving right
+    /// to left.
+    pub glyph_advances: Vec<i32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlyphMeasurement {
+    /// The rectangle of touched bits, where (0, 0) is the pen location.
+    pub drawn_rectangle: Rectangle,
+    /// The amount to add to the pen X coordinate after drawing.
+    pub advance: u32,
+    /// True if the glyph was found, false if this is a fallback glyph.
+    pub present: bool,
+}
+
+/// Grows the ascent and descent so that every glyph fits between them, and
+/// shrinks the leading to match. Returns the new ascent, descent, and
+/// leading.
+fn fit_metrics(
+    glyphs: &BTreeMap<u16, LooseGlyph>,
+    missing_glyph: &LooseGlyph,
+    ascent: i32,
+    descent: i32,
+    leading: i32,
+) -> (i32, i32, i32) {
+    let mut new_ascent = ascent;
+    let mut new_descent = descent;
+    for glyph in glyphs.values().chain(std::iter::once(missing_glyph)) {
+        if glyph.bitmap.get_height() != 0 {
+            new_ascent = new_ascent.max(glyph.top);
+            new_descent =
+                new_descent.max(glyph.bitmap.get_height() as i32 - glyph.top);
+        }
+    }
+    let new_leading =
+        leading - (new_ascent - ascent) - (new_descent - descent);
+    (new_ascent, new_descent, new_leading)
+}
+
+fn lookup_glyph<'a>(
+    fonts: &[&'a Font],
+    glyph: u16,
+) -> (&'a Font, Rectangle, i32, u32, bool) {
+    let first_font =
+        fonts.get(0).expect("At least one font must be provided!");
+    let (mut font, (mut rect, mut offset, mut advance, mut present)) =
+        (first_font, first_font.get_glyph(glyph));
+    if !present {
+        let (missing_rect, missing_offset, missing_advance) =
+            (rect, offset, advance);
+        for candidate in fonts[1..].iter() {
+            (font, (rect, offset, advance, present)) =
+                (candidate, candidate.get_glyph(glyph));
+            if present {
+                break;
+            }
+        }
+        if !present {
+            // use the missing glyph from the first font if
+            // none had it
+            (font, rect, offset, advance) =
+                (first_font, missing_rect, missing_offset, missing_advance);
+        }
+    }
+    (font, rect, offset, advance, present)
+}
+
+/// Returns the rectangle that a glyph covers when it's drawn, relative to the
+/// pen, given the font it's from, the rectangle of the font's bitmap that it
+/// occupies, and its offset.
+fn get_glyph_rectangle(
+    font: &Font,
+    rect: Rectangle,
+    offset: i32,
+) -> Rectangle {
+    Rectangle {
+        left: offset,
+        right: offset + rect.get_width() as i32,
+        top: -font.get_ascent(),
+        bottom: -font.get_ascent() + rect.get_height() as i32,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    /// Makes a font covering ASCII 0x20 through 0x7E. Every glyph is a solid
+    /// 4x9 block (except space, which is empty) that advances by 5 pixels.
+    /// Ascent is 7, descent is 2, and leading is 1.
+    pub(super) fn make_test_font() -> Font {
+        let first_glyph = 0x20;
+        let last_glyph = 0x7E;
+        let mut glyph_locations = vec![0, 0];
+        let mut glyph_offsetwidths = vec![(0, 5)];
+        for _ in first_glyph + 1..=last_glyph {
+            glyph_locations.push(glyph_locations.last().unwrap() + 4);
+            glyph_offsetwidths.push((0, 5));
+        }
+        // the missing glyph
+        glyph_locations.push(glyph_locations.last().unwrap() + 4);
+        glyph_offsetwidths.push((0, 5));
+        let mut bitmap =
+            Bitmap::new(*glyph_locations.last().unwrap() as u32, 9);
+        bitmap.fill_rect(
+            ModeCopy(()),
+            None,
+            Rectangle {
+                left: 0,
+                top: 0,
+                right: bitmap.get_width() as i32,
+                bottom: 9,
+            },
+        );
+        Font {
+            glyph_range: first_glyph..=last_glyph,
+            bitmap,
+            ascent: 7,
+            descent: 2,
+            leading: 1,
+            glyph_locations,
+            glyph_offsetwidths,
+            glyph_widths: None,
+            image_heights: None,
+            kerning: HashMap::new(),
+            space_width: OnceLock::new(),
+            atlas: None,
+        }
+    }
+    #[test]
+    fn measure_matches_draw() {
+        let font = make_test_font();
+        let elements = [
+            TextElement::DrawGlyph(b'A' as u16),
+            TextElement::Space,
+            TextElement::DrawGlyph(b'B' as u16),
+            TextElement::NewLine,
+            TextElement::DrawGlyph(b'C' as u16),
+        ];
+        let measured =
+            Font::measure_text(|n| [&font].get(n).copied(), elements);
+        assert_eq!(measured.glyph_pens, &[(0, 0), (10, 0), (0, 10)]);
+        assert_eq!(measured.pen_end, (5, 10));
+        assert_eq!(
+            measured.drawn_rectangle,
+            Rectangle {
+                left: 0,
+                top: -7,
+                right: 14,
+                bottom: 12,
+            }
+        );
+        let mut bitmap = Bitmap::new(32, 32);
+        let drawn = bitmap.draw_text(
+            ModeOr(()),
+            None,
+            3,
+            8,
+            |n| [&font].get(n).copied(),
+            elements,
+        );
+        assert_eq!(drawn.pen_start, (3, 8));
+        assert_eq!(drawn.pen_end, (8, 18));
+        assert_eq!(
+            drawn.drawn_rectangle,
+            Rectangle {
+                left: 3,
+                top: 1,
+                right: 17,
+                bottom: 20,
+            }
+        );
+        // a strike that's taller than the ascent plus the descent, so the
+        // glyphs hang below the descent
+        let mut font = make_test_font();
+        font.descent = 1;
+        let measured =
+            Font::measure_text(|n| [&font].get(n).copied(), elements);
+        let mut bitmap = Bitmap::new(32, 32);
+        let drawn = bitmap.draw_text(
+            ModeOr(()),
+            None,
+            3,
+            8,
+            |n| [&font].get(n).copied(),
+            elements,
+        );
+        assert_eq!(
+            drawn.drawn_rectangle,
+            measured.drawn_rectangle.offset_by(3, 8)
+        );
+        // the second line's pen is at 17, and the glyphs end two rows below
+        assert_eq!(drawn.drawn_rectangle.bottom, 17 + 2);
+        assert_ne!(bitmap.to_bytes()[18 * 4] & 0x10, 0);
+    }
+    #[test]
+    fn kerning() {
+        let mut font = make_test_font();
+        font.set_kerning([((b'A' as u16, b'V' as u16), -2)]);
+        let measured = Font::measure_text(
+            |n| [&font].get(n).copied(),
+            encode_text(MacRoman, "AVA VA"),
+        );
+        assert_eq!(
+            measured.glyph_pens,
+            &[(0, 0), (3, 0), (8, 0), (13, 0), (18, 0), (23, 0)]
+        );
+    }
+    #[test]
+    fn missing_from_every_font() {
+        let first = make_test_font();
+        // a fallback font whose strike is blank
+        let mut second = make_test_font();
+        second.bitmap = Bitmap::new(second.bitmap.get_width(), 9);
+        // the first font's missing glyph, from the first font's strike
+        let mut bitmap = Bitmap::new(8, 9);
+        let measurement = bitmap.draw_glyph(
+            ModeOr(()),
+            None,
+            0,
+            7,
+            &[&first, &second],
+            0x10,
+        );
+        assert!(!measurement.present);
+        assert_eq!(bitmap.to_bytes(), &[0xF0; 9]);
+    }
+    #[test]
+    fn right_to_left() {
+        let mut font = make_test_font();
+        font.set_kerning([((b'A' as u16, b'V' as u16), -2)]);
+        let elements = [
+            TextElement::SetDirection(Direction::RightToLeft),
+            TextElement::DrawGlyph(b'A' as u16),
+            TextElement::Space,
+            TextElement::DrawGlyph(b'B' as u16),
+            TextElement::NewLine,
+            TextElement::DrawGlyph(b'A' as u16),
+            TextElement::DrawGlyph(b'V' as u16),
+        ];
+        let measured =
+            Font::measure_text(|n| [&font].get(n).copied(), elements);
+        assert_eq!(
+            measured.glyph_pens,
+            &[(0, 0), (-10, 0), (0, 10), (-3, 10)]
+        );
+        assert_eq!(measured.pen_end, (-8, 10));
+        assert_eq!(
+            measured.drawn_rectangle,
+            Rectangle {
+                left: -15,
+                top: -7,
+                right: -1,
+                bottom: 12,
+            }
+        );
+        let mut bitmap = Bitmap::new(32, 32);
+        let drawn = bitmap.draw_text(
+            ModeOr(()),
+            None,
+            20,
+            8,
+            |n| [&font].get(n).copied(),
+            elements,
+        );
+        assert_eq!((drawn.pen_start, drawn.pen_end), ((20, 8), (12, 18)));
+        assert!(bitmap.get_pixel(15, 4) && bitmap.get_pixel(18, 4));
+        assert!(!bitmap.get_pixel(19, 4) && !bitmap.get_pixel(14, 4));
+        assert!(bitmap.get_pixel(5, 4) && !bitmap.get_pixel(9, 4));
+    }
+    #[test]
+    fn outline_and_shadow() {
+        let font = make_test_font();
+        let outline = font.make_outline();
+        assert_eq!((outline.get_ascent(), outline.get_descent()), (8, 3));
+        assert_eq!(outline.get_glyph(b'A' as u16).1, -1);
+        assert_eq!(outline.get_glyph(b'A' as u16).2, 6);
+        let mut bitmap = Bitmap::new(8, 12);
+        bitmap.draw_glyph(ModeOr(()), None, 1, 8, &[&outline], b'A' as u16);
+        let mut expected = vec![0xFC];
+        expected.extend([0x84; 9]);
+        expected.extend([0xFC, 0]);
+        assert_eq!(bitmap.to_bytes(), expected);
+        let shadow = font.make_shadow();
+        assert_eq!((shadow.get_ascent(), shadow.get_descent()), (8, 4));
+        assert_eq!(shadow.get_glyph(b'A' as u16).2, 7);
+        let mut bitmap = Bitmap::new(8, 12);
+        bitmap.draw_glyph(ModeOr(()), None, 1, 8, &[&shadow], b'A' as u16);
+        let mut expected = vec![0xFE];
+        expected.extend([0x86; 9]);
+        expected.extend([0xFE, 0xFE]);
+        assert_eq!(bitmap.to_bytes(), expected);
+        // space stays empty
+        assert_eq!(shadow.get_glyph(b' ' as u16).0.get_width(), 0);
+    }
+    /// Checks that two fonts have the same vertical metrics, and the same
+    /// glyphs (including the missing glyph) with the same metrics and images.
+    pub(super) fn assert_same_glyphs(a: &Font, b: &Font) {
+        assert_eq!(
+            (a.get_ascent(), a.get_descent()),
+            (b.get_ascent(), b.get_descent())
+        );
+        let ids = a.glyph_range.clone().chain(b.glyph_range.clone());
+        for id in ids.chain([MISSING_GLYPH]) {
+            let (a_rect, a_offset, a_advance, a_present) = a.get_glyph(id);
+            let (b_rect, b_offset, b_advance, b_present) = b.get_glyph(id);
+            assert_eq!(
+                (a_offset, a_advance, a_present),
+                (b_offset, b_advance, b_present),
+                "glyph {id}"
+            );
+            assert_eq!(a_rect.get_width(), b_rect.get_width(), "glyph {id}");
+            let mut a_bitmap = Bitmap::new(64, 64);
+            let mut b_bitmap = Bitmap::new(64, 64);
+            a_bitmap.draw_glyph(ModeCopy(()), None, 16, 32, &[a], id);
+            b_bitmap.draw_glyph(ModeCopy(()), None, 16, 32, &[b], id);
+            assert_eq!(a_bitmap.to_bytes(), b_bitmap.to_bytes(), "glyph {id}");
+        }
+    }
+    #[test]
+    fn mac_font_round_trip() {
+        let mut font = make_test_font().make_bold().make_italic();
+        font.glyph_offsetwidths[b'j' as usize - 0x20].0 = -2;
+        font.glyph_offsetwidths[b'x' as usize - 0x20] = (-1, 255);
+        let mut nfnt = Vec::new();
+        font.write_mac_font(&mut nfnt).unwrap();
+        let read = Font::read_mac_font(&nfnt[..]).unwrap();
+        assert_same_glyphs(&font, &read);
+        assert_eq!(read.get_leading(), font.get_leading());
+        assert_eq!(read.glyph_widths, font.glyph_widths);
+        assert_eq!(read.image_heights, font.image_heights);
+        // kernMax is the leftmost offset, and the stored offsets are relative
+        // to it
+        assert_eq!(i16::from_be_bytes([nfnt[8], nfnt[9]]), -2);
+    }
+}
@@ -0,0 +1,250 @@
+use super::bdf::make_box_glyph;
+use super::*;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE512: u8 = 0x01;
+const PSF1_MODEHASTAB: u8 = 0x02;
+const PSF1_MODEHASSEQ: u8 = 0x04;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+
+impl Font {
+    /// Attempts to parse the given input as a PC Screen Font (PSF1 or PSF2),
+    /// such as the Linux console uses.
+    ///
+    /// Glyph IDs are the glyphs' positions in the file. If the font has a
+    /// Unicode table, an `Encoding` that maps characters to those glyphs is
+    /// also returned. (Multi-character sequences in the table are ignored.)
+    ///
+    /// - Every glyph is offset 0 and advances by the width of the cell.
+    /// - PSF fonts don't say where the baseline is, so it's placed under
+    ///   whichever row is most often the bottom row of the capital letters
+    ///   and digits. The ascent is the part of the cell above that, and the
+    ///   descent is the part below.
+    /// - The leading is zero.
+    /// - If the Unicode table maps U+FFFD REPLACEMENT CHARACTER to a glyph,
+    ///   that glyph is also used as the missing glyph. Otherwise, a hollow box
+    ///   is used.
+    ///
+    /// See: <https://www.win.tue.nl/~aeb/linux/kbd/font-formats-1.html>
+    pub fn read_psf(
+        mut i: impl Read,
+    ) -> anyhow::Result<(Font, Option<Encoding>)> {
+        let mut buf = Vec::new();
+        i.read_to_end(&mut buf).context("Error reading PSF font.")?;
+        let (cells, width, height, encoding) = if buf.starts_with(&PSF1_MAGIC)
+        {
+            parse_psf1(&buf)?
+        } else if buf.starts_with(&PSF2_MAGIC) {
+            parse_psf2(&buf)?
+        } else {
+            return Err(anyhow!("Input is not a PSF font"));
+        };
+        if cells.len() > MISSING_GLYPH as usize {
+            return Err(anyhow!("PSF font has too many glyphs"));
+        }
+        // Without a Unicode table, assume the font is ASCII-compatible.
+        let find_glyph = |c: char| match &encoding {
+            Some(encoding) => encoding.encode_char(c),
+            None => Some(c as u16),
+        };
+        let mut bottom_row_counts = vec![0; height as usize + 1];
+        for c in ('A'..='Z').chain('0'..='9') {
+            let Some(cell) =
+                find_glyph(c).and_then(|glyph| cells.get(glyph as usize))
+            else {
+                continue;
+            };
+            let row_bytes = cell.len() / height as usize;
+            if let Some(row) = cell
+                .chunks_exact(row_bytes)
+                .rposition(|row| row.iter().any(|x| *x != 0))
+            {
+                bottom_row_counts[row + 1] += 1;
+            }
+        }
+        let (baseline, _) = bottom_row_counts
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, count)| **count)
+            .filter(|(_, count)| **count != 0)
+            .unwrap_or((height as usize - height as usize / 4, &0));
+        let ascent = baseline as i32;
+        let descent = height as i32 - ascent;
+        let glyphs: BTreeMap<u16, LooseGlyph> = cells
+            .iter()
+            .enumerate()
+            .map(|(n, cell)| {
+                (
+                    n as u16,
+                    LooseGlyph {
+                        bitmap: Bitmap::from_bytes(width, height, cell),
+                        top: ascent,
+                        offset: 0,
+                        advance: width,
+                    },
+                )
+            })
+            .collect();
+        let missing_glyph = match encoding
+            .as_ref()
+            .and_then(|encoding| encoding.encode_char('\u{FFFD}'))
+            .and_then(|glyph| glyphs.get(&glyph))
+        {
+            Some(glyph) => LooseGlyph {
+                bitmap: glyph.bitmap.clone(),
+                ..*glyph
+            },
+            None => {
+                let mut glyph = make_box_glyph(
+                    width.saturating_sub(1).max(3),
+                    ascent.max(3) as u32,
+                );
+                glyph.advance = width.max(glyph.advance);
+                glyph
+            }
+        };
+        let font = Font::from_loose_glyphs(
+            &glyphs,
+            &missing_glyph,
+            ascent,
+            descent,
+            0,
+        )?;
+        Ok((font, encoding))
+    }
+}
+
+/// Glyph cells (as packed bytes), width, height, and Unicode mapping.
+type PsfContents<'a> = (Vec<&'a [u8]>, u32, u32, Option<Encoding>);
+
+fn parse_psf1(buf: &[u8]) -> anyhow::Result<PsfContents<'_>> {
+    let mode = *buf.get(2).ok_or_else(|| anyhow!("PSF1 header truncated"))?;
+    let charsize =
+        *buf.get(3).ok_or_else(|| anyhow!("PSF1 header truncated"))? as usize;
+    if charsize == 0 {
+        return Err(anyhow!("PSF1 font has zero height"));
+    }
+    let num_glyphs = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+    let glyph_data = buf
+        .get(4..4 + num_glyphs * charsize)
+        .ok_or_else(|| anyhow!("PSF1 glyph data truncated"))?;
+    let cells = glyph_data.chunks_exact(charsize).collect();
+    let encoding = if mode & (PSF1_MODEHASTAB | PSF1_MODEHASSEQ) != 0 {
+        let mut table = buf[4 + num_glyphs * charsize..]
+            .chunks_exact(2)
+            .map(|x| u16::from_le_bytes([x[0], x[1]]));
+        let mut encoding = Encoding::new();
+        for glyph in 0..num_glyphs as u16 {
+            let mut in_sequence = false;
+            loop {
+                match table.next() {
+                    None => {
+                        return Err(anyhow!("PSF1 Unicode table truncated"))
+                    }
+                    Some(0xFFFF) => break,
+                    Some(0xFFFE) => in_sequence = true,
+                    Some(_) if in_sequence => (),
+                    Some(code) => {
+                        if let Some(c) = char::from_u32(code as u32) {
+                            insert_if_absent(&mut encoding, c, glyph);
+                        }
+                    }
+                }
+            }
+        }
+        Some(encoding)
+    } else {
+        None
+    };
+    Ok((cells, 8, charsize as u32, encoding))
+}
+
+fn parse_psf2(buf: &[u8]) -> anyhow::Result<PsfContents<'_>> {
+    let header: Vec<u32> = buf
+        .get(..32)
+        .ok_or_else(|| anyhow!("PSF2 header truncated"))?
+        .chunks_exact(4)
+        .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect();
+    let header_size = header[2] as usize;
+    let flags = header[3];
+    let num_glyphs = header[4] as usize;
+    let charsize = header[5] as usize;
+    let height = header[6];
+    let width = header[7];
+    if width == 0 || height == 0 {
+        return Err(anyhow!("PSF2 font has zero width or height"));
+    }
+    if charsize != width.div_ceil(8) as usize * height as usize {
+        return Err(anyhow!("PSF2 glyph size doesn't match its dimensions"));
+    }
+    let glyph_data_end = num_glyphs
+        .checked_mul(charsize)
+        .and_then(|x| x.checked_add(header_size))
+        .ok_or_else(|| anyhow!("PSF2 glyph data is impossibly large"))?;
+    let glyph_data = buf
+        .get(header_size..glyph_data_end)
+        .ok_or_else(|| anyhow!("PSF2 glyph data truncated"))?;
+    let cells = glyph_data.chunks_exact(charsize).collect();
+    let encoding = if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+        let mut table = buf[glyph_data_end..].split(|x| *x == 0xFF);
+        let mut encoding = Encoding::new();
+        for glyph in 0..num_glyphs {
+            let entry = table
+                .next()
+                .ok_or_else(|| anyhow!("PSF2 Unicode table truncated"))?;
+            let singles = entry.split(|x| *x == 0xFE).next().unwrap_or(&[]);
+            for c in singles.utf8_chunks().flat_map(|x| x.valid().chars()) {
+                insert_if_absent(&mut encoding, c, glyph as u16);
+            }
+        }
+        Some(encoding)
+    } else {
+        None
+    };
+    Ok((cells, width, height, encoding))
+}
+
+/// Map a character to a glyph, unless it's already been mapped to an earlier
+/// one.
+fn insert_if_absent(encoding: &mut Encoding, c: char, glyph: u16) {
+    if encoding.encode_char(c).is_none() {
+        encoding.insert(c, glyph);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn read_psf2() {
+        let mut psf = PSF2_MAGIC.to_vec();
+        // version, header size, flags, length, charsize, height, width
+        for x in [0u32, 32, PSF2_HAS_UNICODE_TABLE, 2, 4, 4, 6] {
+            psf.extend_from_slice(&x.to_le_bytes());
+        }
+        // an "A" whose bottom row is row 2, and a solid block
+        psf.extend_from_slice(&[0x30, 0x48, 0x78, 0x00]);
+        psf.extend_from_slice(&[0xFC, 0xFC, 0xFC, 0xFC]);
+        psf.extend_from_slice(b"Aa\xFF");
+        psf.extend_from_slice("\u{FFFD}\u{2588}".as_bytes());
+        // a sequence, which should be ignored
+        psf.push(0xFE);
+        psf.extend_from_slice("e\u{301}".as_bytes());
+        psf.push(0xFF);
+        let (font, encoding) = Font::read_psf(&psf[..]).unwrap();
+        let encoding = encoding.unwrap();
+        assert_eq!(encoding.encode_char('A'), Some(0));
+        assert_eq!(encoding.encode_char('a'), Some(0));
+        assert_eq!(encoding.encode_char('\u{2588}'), Some(1));
+        assert_eq!(encoding.encode_char('e'), None);
+        assert_eq!((font.get_ascent(), font.get_descent()), (3, 1));
+        // the missing glyph is the U+FFFD glyph
+        let (rect, offset, advance, present) = font.get_glyph(2);
+        assert_eq!(
+            (rect.get_width(), offset, advance, present),
+            (6, 0, 6, false)
+        );
+    }
+}
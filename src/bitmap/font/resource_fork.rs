@@ -0,0 +1,551 @@
+use std::io::Read;
+
+use anyhow::{anyhow, Context};
+
+use super::*;
+
+/// A single resource from a Macintosh resource fork.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resource {
+    /// The four-character type code, e.g. `*b"NFNT"`.
+    pub kind: [u8; 4],
+    /// The resource ID.
+    pub id: i16,
+    /// The resource's name, if it has one.
+    pub name: Option<String>,
+    /// The resource attribute bits (purgeable, locked, etc.)
+    pub attributes: u8,
+    /// The contents of the resource.
+    pub data: Vec<u8>,
+}
+
+/// All of the resources from a Macintosh resource fork.
+#[derive(Clone, Debug, Default)]
+pub struct ResourceFork {
+    resources: Vec<Resource>,
+}
+
+/// Where a font in a resource fork came from, and what it's for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacFontInfo {
+    /// The name of the font family, if known.
+    pub family_name: Option<String>,
+    /// The font family ID.
+    pub family_id: u16,
+    /// The point size, if known.
+    pub size: Option<u16>,
+    /// The QuickDraw style bits this strike was drawn for. (Bit 0 is bold,
+    /// bit 1 is italic, bit 2 is underline, and so on.) Zero for plain.
+    pub style: u16,
+    /// The type of resource containing the font, `*b"NFNT"` or `*b"FONT"`.
+    pub resource_kind: [u8; 4],
+    /// The ID of the resource containing the font.
+    pub resource_id: i16,
+    /// The ID of the `FOND` that listed this font, if any.
+    pub fond_id: Option<i16>,
+}
+
+impl ResourceFork {
+    /// Reads a resource fork from any of the following:
+    ///
+    /// - A bare resource fork (such as one read from `..namedfork/rsrc` on
+    ///   macOS)
+    /// - A `.dfont` file (which is a resource fork in a data fork)
+    /// - A MacBinary (I, II, or III) file
+    /// - An AppleSingle or AppleDouble file
+    ///
+    /// The whole input is read into memory.
+    pub fn read(mut i: impl Read) -> anyhow::Result<ResourceFork> {
+        let mut buf = Vec::new();
+        i.read_to_end(&mut buf)
+            .context("Error reading resource fork.")?;
+        ResourceFork::parse(&buf)
+    }
+    /// Like `read`, but works on a slice that is already in memory.
+    pub fn parse(buf: &[u8]) -> anyhow::Result<ResourceFork> {
+        let fork = if let Some(fork) = find_apple_single_fork(buf)? {
+            fork
+        } else if let Some(fork) = find_macbinary_fork(buf) {
+            fork
+        } else {
+            buf
+        };
+        Ok(ResourceFork {
+            resources: parse_fork(fork)?,
+        })
+    }
+    /// Returns every resource, in the order they appear in the resource map.
+    pub fn get_resources(&self) -> &[Resource] {
+        &self.resources
+    }
+    /// Returns the resource with the given type and ID, if there is one.
+    pub fn get_resource(&self, kind: [u8; 4], id: i16) -> Option<&Resource> {
+        self.resources.iter().find(|x| x.kind == kind && x.id == id)
+    }
+    /// Returns every resource of the given type.
+    pub fn get_resources_of_kind(
+        &self,
+        kind: [u8; 4],
+    ) -> impl Iterator<Item = &Resource> {
+        self.resources.iter().filter(move |x| x.kind == kind)
+    }
+    /// Finds every font in this resource fork.
+    ///
+    /// Fonts listed in a `FOND`'s font association table get their family
+    /// name from the `FOND` and their size and style from the table. `FONT`
+    /// resources that no `FOND` mentions get their size and family ID from
+    /// their resource ID, and their family name from the name-only `FONT` for
+    /// their family (if there is one). `NFNT` resources that no `FOND`
+    /// mentions are listed with no family name and no size.
+    pub fn list_fonts(&self) -> anyhow::Result<Vec<MacFontInfo>> {
+        let mut ret = Vec::new();
+        for fond in self.get_resources_of_kind(*b"FOND") {
+            let family_id = read_u16(&fond.data, 2)
+                .context("FOND resource is truncated.")?;
+            for (size, style, id) in parse_fond_associations(&fond.data)? {
+                let kind = if self.get_resource(*b"NFNT", id).is_some() {
+                    *b"NFNT"
+                } else if self.get_resource(*b"FONT", id).is_some() {
+                    *b"FONT"
+                } else {
+                    continue;
+                };
+                ret.push(MacFontInfo {
+                    family_name: fond.name.clone(),
+                    family_id,
+                    size: Some(size),
+                    style,
+                    resource_kind: kind,
+                    resource_id: id,
+                    fond_id: Some(fond.id),
+                });
+            }
+        }
+        for font in self.resources.iter() {
+            if ret.iter().any(|x| {
+                x.resource_kind == font.kind && x.resource_id == font.id
+            }) {
+                continue;
+            }
+            if font.kind == *b"FONT" {
+                let family_id = (font.id as u16) >> 7;
+                let size = (font.id as u16) & 127;
+                if size == 0 {
+                    // This one only exists to name the family.
+                    continue;
+                }
+                let family_name = self
+                    .get_resource(*b"FONT", (family_id << 7) as i16)
+                    .and_then(|x| x.name.clone())
+                    .or_else(|| font.name.clone());
+                ret.push(MacFontInfo {
+                    family_name,
+                    family_id,
+                    size: Some(size),
+                    style: 0,
+                    resource_kind: font.kind,
+                    resource_id: font.id,
+                    fond_id: None,
+                });
+            } else if font.kind == *b"NFNT" {
+                ret.push(MacFontInfo {
+                    family_name: font.name.clone(),
+                    family_id: 0,
+                    size: None,
+                    style: 0,
+                    resource_kind: font.kind,
+                    resource_id: font.id,
+                    fond_id: None,
+                });
+            }
+        }
+        Ok(ret)
+    }
+    /// Loads the font described by the given `MacFontInfo` (as returned by
+    /// `list_fonts`). If it came from a `FOND` with a kerning table, the
+    /// kerning pairs for its style (or for plain, if there are none for its
+    /// style) are scaled to its point size and applied.
+    pub fn read_font(&self, info: &MacFontInfo) -> anyhow::Result<Font> {
+        let resource = self
+            .get_resource(info.resource_kind, info.resource_id)
+            .ok_or_else(|| anyhow!("Font resource not found."))?;
+        let mut font = Font::read_mac_font(&resource.data[..])?;
+        if let (Some(fond_id), Some(size)) = (info.fond_id, info.size) {
+            if let Some(fond) = self.get_resource(*b"FOND", fond_id) {
+                font.set_kerning(parse_fond_kerning(
+                    &fond.data, size, info.style,
+                )?);
+            }
+        }
+        Ok(font)
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> anyhow::Result<u16> {
+    buf.get(offset..offset + 2)
+        .map(|x| u16::from_be_bytes([x[0], x[1]]))
+        .ok_or_else(|| anyhow!("Unexpected end of data"))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> anyhow::Result<u32> {
+    buf.get(offset..offset + 4)
+        .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+        .ok_or_else(|| anyhow!("Unexpected end of data"))
+}
+
+fn get_range(buf: &[u8], offset: usize, length: usize) -> Option<&[u8]> {
+    buf.get(offset..offset.checked_add(length)?)
+}
+
+/// If this is an AppleSingle or AppleDouble file, returns its resource fork
+/// (or an empty slice if it doesn't have one).
+fn find_apple_single_fork(buf: &[u8]) -> anyhow::Result<Option<&[u8]>> {
+    match read_u32(buf, 0) {
+        Ok(0x00051600) | Ok(0x00051607) => (),
+        _ => return Ok(None),
+    }
+    let num_entries = read_u16(buf, 24)
+        .context("AppleSingle/AppleDouble header is truncated.")?;
+    for n in 0..num_entries as usize {
+        let entry = 26 + n * 12;
+        let id = read_u32(buf, entry)
+            .context("AppleSingle/AppleDouble header is truncated.")?;
+        if id != 2 {
+            continue;
+        }
+        let offset = read_u32(buf, entry + 4)?;
+        let length = read_u32(buf, entry + 8)?;
+        return get_range(buf, offset as usize, length as usize)
+            .map(Some)
+            .ok_or_else(|| {
+                anyhow!("AppleSingle/AppleDouble resource fork is truncated.")
+            });
+    }
+    Ok(Some(&[]))
+}
+
+/// If this looks like a MacBinary file, returns its resource fork.
+fn find_macbinary_fork(buf: &[u8]) -> Option<&[u8]> {
+    let header = buf.get(..128)?;
+    if header[0] != 0
+        || !(1..=63).contains(&header[1])
+        || header[74] != 0
+        || header[82] != 0
+    {
+        return None;
+    }
+    let data_length = read_u32(header, 83).ok()? as usize;
+    let resource_length = read_u32(header, 87).ok()? as usize;
+    let secondary_header_length = read_u16(header, 120).ok()? as usize;
+    let resource_offset = 128
+        + secondary_header_length.next_multiple_of(128)
+        + data_length.next_multiple_of(128);
+    get_range(buf, resource_offset, resource_length)
+}
+
+fn parse_fork(fork: &[u8]) -> anyhow::Result<Vec<Resource>> {
+    if fork.is_empty() {
+        return Ok(vec![]);
+    }
+    let data_offset =
+        read_u32(fork, 0).context("Resource fork header is truncated.")?;
+    let map_offset = read_u32(fork, 4)?;
+    let data_length = read_u32(fork, 8)?;
+    let map_length = read_u32(fork, 12)?;
+    let data = get_range(fork, data_offset as usize, data_length as usize)
+        .ok_or_else(|| {
+            anyhow!("Resource data extends past the end of the fork.")
+        })?;
+    let map = get_range(fork, map_offset as usize, map_length as usize)
+        .ok_or_else(|| {
+            anyhow!("Resource map extends past the end of the fork.")
+        })?;
+    let type_list_offset =
+        read_u16(map, 24).context("Resource map is truncated.")? as usize;
+    let name_list_offset = read_u16(map, 26)? as usize;
+    let type_list = map
+        .get(type_list_offset..)
+        .ok_or_else(|| anyhow!("Resource type list is out of bounds."))?;
+    // The count is stored minus one, so an empty list is stored as 0xFFFF.
+    let num_types = read_u16(type_list, 0)?.wrapping_add(1);
+    let mut ret = Vec::new();
+    for n in 0..num_types as usize {
+        let entry = 2 + n * 8;
+        let kind = type_list
+            .get(entry..entry + 4)
+            .ok_or_else(|| anyhow!("Resource type list is truncated."))?;
+        let kind = [kind[0], kind[1], kind[2], kind[3]];
+        let num_refs = read_u16(type_list, entry + 4)?.wrapping_add(1);
+        let ref_list_offset = read_u16(type_list, entry + 6)? as usize;
+        for m in 0..num_refs as usize {
+            let reference = ref_list_offset + m * 12;
+            let id = read_u16(type_list, reference)
+                .context("Resource reference list is truncated.")?
+                as i16;
+            let name_offset = read_u16(type_list, reference + 2)?;
+            let attributes_and_offset = read_u32(type_list, reference + 4)?;
+            let attributes = (attributes_and_offset >> 24) as u8;
+            let offset = (attributes_and_offset & 0xFFFFFF) as usize;
+            let length = read_u32(data, offset)
+                .context("Resource data is out of bounds.")?;
+            let data = get_range(data, offset + 4, length as usize)
+                .ok_or_else(|| anyhow!("Resource data is truncated."))?
+                .to_vec();
+            let name = if name_offset == 0xFFFF {
+                None
+            } else {
+                let name_start = name_list_offset + name_offset as usize;
+                let name_length = *map.get(name_start).ok_or_else(|| {
+                    anyhow!("Resource name is out of bounds.")
+                })?;
+                let name =
+                    get_range(map, name_start + 1, name_length as usize)
+                        .ok_or_else(|| {
+                            anyhow!("Resource name is truncated.")
+                        })?;
+                Some(MacRoman.decode(name))
+            };
+            ret.push(Resource {
+                kind,
+                id,
+                name,
+                attributes,
+                data,
+            });
+        }
+    }
+    Ok(ret)
+}
+
+/// Returns the size, style, and resource ID of each entry in a FOND's font
+/// association table.
+fn parse_fond_associations(
+    fond: &[u8],
+) -> anyhow::Result<Vec<(u16, u16, i16)>> {
+    const ASSOCIATION_TABLE: usize = 52;
+    let count = read_u16(fond, ASSOCIATION_TABLE)
+        .context("FOND resource is truncated.")?
+        .wrapping_add(1);
+    (0..count as usize)
+        .map(|n| {
+            let entry = ASSOCIATION_TABLE + 2 + n * 6;
+            Ok((
+                read_u16(fond, entry)
+                    .context("FOND association table is truncated.")?,
+                read_u16(fond, entry + 2)?,
+                read_u16(fond, entry + 4)? as i16,
+            ))
+        })
+        .collect()
+}
+
+/// Returns the kerning pairs for the given style from a FOND's kerning table,
+/// in pixels at the given point size.
+fn parse_fond_kerning(
+    fond: &[u8],
+    size: u16,
+    style: u16,
+) -> anyhow::Result<Vec<((u16, u16), i32)>> {
+    let table =
+        read_u32(fond, 20).context("FOND resource is truncated.")? as usize;
+    if table == 0 {
+        return Ok(vec![]);
+    }
+    let count = read_u16(fond, table)
+        .context("FOND kerning table is out of bounds.")?
+        .wrapping_add(1);
+    let mut entry = table + 2;
+    let (mut exact, mut plain) = (None, None);
+    for _ in 0..count {
+        let kern_style = read_u16(fond, entry)
+            .context("FOND kerning table is truncated.")?;
+        let num_pairs = read_u16(fond, entry + 2)? as usize;
+        if kern_style == style {
+            exact = Some((entry + 4, num_pairs));
+        } else if kern_style == 0 {
+            plain = Some((entry + 4, num_pairs));
+        }
+        entry += 4 + num_pairs * 4;
+    }
+    let Some((pairs, num_pairs)) = exact.or(plain) else {
+        return Ok(vec![]);
+    };
+    (0..num_pairs)
+        .map(|n| {
+            let pair = get_range(fond, pairs + n * 4, 4)
+                .ok_or_else(|| anyhow!("FOND kerning table is truncated."))?;
+            // 4.12 fixed point, in ems
+            let amount = i16::from_be_bytes([pair[2], pair[3]]) as i32;
+            let pixels = (amount * size as i32 + 2048).div_euclid(4096);
+            Ok(((pair[0] as u16, pair[1] as u16), pixels))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    /// Type, ID, name, and data of a resource to put in a test fork.
+    type TestResource<'a> = ([u8; 4], i16, Option<&'a str>, &'a [u8]);
+    /// Builds a resource fork containing the given resources, which must
+    /// already be sorted by type.
+    fn build_fork(resources: &[TestResource]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut kinds: Vec<[u8; 4]> = resources.iter().map(|x| x.0).collect();
+        kinds.dedup();
+        let mut type_list = Vec::new();
+        type_list.extend_from_slice(&(kinds.len() as u16 - 1).to_be_bytes());
+        let mut ref_list = Vec::new();
+        let mut names = Vec::new();
+        for kind in kinds.iter() {
+            let these: Vec<_> =
+                resources.iter().filter(|x| x.0 == *kind).collect();
+            type_list.extend_from_slice(kind);
+            type_list
+                .extend_from_slice(&(these.len() as u16 - 1).to_be_bytes());
+            let ref_offset = 2 + kinds.len() * 8 + ref_list.len();
+            type_list.extend_from_slice(&(ref_offset as u16).to_be_bytes());
+            for (_, id, name, body) in these {
+                ref_list.extend_from_slice(&id.to_be_bytes());
+                match name {
+                    Some(name) => {
+                        ref_list.extend_from_slice(
+                            &(names.len() as u16).to_be_bytes(),
+                        );
+                        names.push(name.len() as u8);
+                        names.extend_from_slice(name.as_bytes());
+                    }
+                    None => ref_list.extend_from_slice(&[0xFF, 0xFF]),
+                }
+                ref_list.extend_from_slice(&(data.len() as u32).to_be_bytes());
+                ref_list.extend_from_slice(&[0; 4]);
+                data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+                data.extend_from_slice(body);
+            }
+        }
+        let mut map = vec![0; 24];
+        map.extend_from_slice(&28u16.to_be_bytes());
+        map.extend_from_slice(
+            &(28 + type_list.len() as u16 + ref_list.len() as u16)
+                .to_be_bytes(),
+        );
+        map.extend_from_slice(&type_list);
+        map.extend_from_slice(&ref_list);
+        map.extend_from_slice(&names);
+        let mut fork = Vec::new();
+        fork.extend_from_slice(&256u32.to_be_bytes());
+        fork.extend_from_slice(&(256 + data.len() as u32).to_be_bytes());
+        fork.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        fork.extend_from_slice(&(map.len() as u32).to_be_bytes());
+        fork.resize(256, 0);
+        fork.extend_from_slice(&data);
+        fork.extend_from_slice(&map);
+        fork
+    }
+    #[test]
+    fn list_fonts() {
+        let mut fond = vec![0; 52];
+        fond[2..4].copy_from_slice(&21u16.to_be_bytes());
+        fond.extend_from_slice(&1u16.to_be_bytes());
+        fond.extend_from_slice(&[0, 12, 0, 0, 0x0A, 0x8C]);
+        fond.extend_from_slice(&[0, 12, 0, 1, 0x0A, 0x8D]);
+        let fork = build_fork(&[
+            (*b"FOND", 21, Some("Helvetica"), &fond),
+            (*b"FONT", 3 << 7, Some("Geneva"), &[]),
+            (*b"FONT", (3 << 7) | 9, None, &[1, 2, 3]),
+            (*b"NFNT", 2700, None, &[4, 5, 6]),
+            (*b"NFNT", 2701, None, &[7, 8, 9]),
+        ]);
+        let fork = ResourceFork::parse(&fork).unwrap();
+        assert_eq!(fork.get_resources().len(), 5);
+        assert_eq!(
+            fork.get_resource(*b"NFNT", 2701).unwrap().data,
+            &[7, 8, 9]
+        );
+        let fonts = fork.list_fonts().unwrap();
+        assert_eq!(
+            fonts,
+            &[
+                MacFontInfo {
+                    family_name: Some("Helvetica".to_string()),
+                    family_id: 21,
+                    size: Some(12),
+                    style: 0,
+                    resource_kind: *b"NFNT",
+                    resource_id: 2700,
+                    fond_id: Some(21),
+                },
+                MacFontInfo {
+                    family_name: Some("Helvetica".to_string()),
+                    family_id: 21,
+                    size: Some(12),
+                    style: 1,
+                    resource_kind: *b"NFNT",
+                    resource_id: 2701,
+                    fond_id: Some(21),
+                },
+                MacFontInfo {
+                    family_name: Some("Geneva".to_string()),
+                    family_id: 3,
+                    size: Some(9),
+                    style: 0,
+                    resource_kind: *b"FONT",
+                    resource_id: (3 << 7) | 9,
+                    fond_id: None,
+                },
+            ]
+        );
+    }
+    #[test]
+    fn fond_kerning() {
+        let mut fond = vec![0; 52];
+        fond[20..24].copy_from_slice(&54u32.to_be_bytes());
+        // no font associations
+        fond.extend_from_slice(&[0xFF, 0xFF]);
+        // two kerning tables: plain, and bold
+        fond.extend_from_slice(&[0, 1]);
+        fond.extend_from_slice(&[0, 0, 0, 2]);
+        // "AV" by -0.125 em, "To" by -0.0625 em
+        fond.extend_from_slice(&[b'A', b'V', 0xFE, 0x00]);
+        fond.extend_from_slice(&[b'T', b'o', 0xFF, 0x00]);
+        fond.extend_from_slice(&[0, 1, 0, 1]);
+        fond.extend_from_slice(&[b'A', b'V', 0xFF, 0x00]);
+        assert_eq!(
+            parse_fond_kerning(&fond, 16, 0).unwrap(),
+            &[
+                ((b'A' as u16, b'V' as u16), -2),
+                ((b'T' as u16, b'o' as u16), -1)
+            ]
+        );
+        assert_eq!(
+            parse_fond_kerning(&fond, 16, 1).unwrap(),
+            &[((b'A' as u16, b'V' as u16), -1)]
+        );
+        // italic has no table of its own, so it gets the plain table
+        assert_eq!(parse_fond_kerning(&fond, 16, 2).unwrap().len(), 2);
+    }
+    #[test]
+    fn macbinary() {
+        let fork = build_fork(&[(*b"NFNT", 128, Some("Test"), &[1, 2, 3])]);
+        let mut macbinary = vec![0; 128];
+        macbinary[1] = 5;
+        macbinary[2..7].copy_from_slice(b"fonts");
+        macbinary[83..87].copy_from_slice(&3u32.to_be_bytes());
+        macbinary[87..91].copy_from_slice(&(fork.len() as u32).to_be_bytes());
+        // a three-byte data fork, padded to 128 bytes
+        macbinary.extend_from_slice(&[0xAA; 3]);
+        macbinary.resize(256, 0);
+        macbinary.extend_from_slice(&fork);
+        let fork = ResourceFork::parse(&macbinary).unwrap();
+        assert_eq!(
+            fork.get_resources(),
+            &[Resource {
+                kind: *b"NFNT",
+                id: 128,
+                name: Some("Test".to_string()),
+                attributes: 0,
+                data: vec![1, 2, 3],
+            }]
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use super::*;
+
+impl Font {
+    /// Makes a version of this font scaled by `num / den`, like the Font
+    /// Manager did when asked for a size with no strike of its own.
+    ///
+    /// - Enlarging by a whole number made up of twos and threes is done with
+    ///   `scaled_pixel_art`, so that diagonals come out smooth.
+    /// - Otherwise, glyph images are resampled with nearest-neighbor scaling.
+    ///   Pixels are sampled relative to the pen and baseline, so every glyph
+    ///   is cut from the same grid.
+    /// - Ascent, descent, leading, offsets, advances, fractional advances, and
+    ///   kerning are scaled and rounded to the nearest pixel.
+    /// - The image-height table, if any, is not carried over.
+    ///
+    /// Returns an error if `num` or `den` is zero, or if the scaled font is
+    /// too big to fit in a strike.
+    pub fn scaled(&self, num: u32, den: u32) -> anyhow::Result<Font> {
+        if num == 0 || den == 0 {
+            return Err(anyhow!("Cannot scale a font by {num}/{den}"));
+        }
+        if num > den && num.is_multiple_of(den) {
+            if let Some(passes) = get_pixel_art_passes(num / den) {
+                return self.rescale_pixel_art(passes);
+            }
+        }
+        self.rescale(num, den, |glyph| scale_nearest(glyph, num, den))
+    }
+    /// Makes a version of this font scaled up by the given factor, using the
+    /// Scale2x and Scale3x pixel-art scalers. These round off diagonals
+    /// instead of turning them into staircases.
+    ///
+    /// Factors that are made up of twos and threes (2, 3, 4, 6, 8, 9...) are
+    /// done with repeated passes. Any other factor (other than 1) is an
+    /// error; use `scaled` for those.
+    pub fn scaled_pixel_art(&self, factor: u32) -> anyhow::Result<Font> {
+        let passes = get_pixel_art_passes(factor).ok_or_else(|| {
+            anyhow!(
+                "Pixel-art scaling only supports factors made up of twos and \
+                 threes, not {factor}"
+            )
+        })?;
+        self.rescale_pixel_art(passes)
+    }
+    /// Implements `scaled_pixel_art`, given the passes that
+    /// `get_pixel_art_passes` returned.
+    fn rescale_pixel_art(&self, passes: Vec<u32>) -> anyhow::Result<Font> {
+        let mut ret = self.clone();
+        for pass in passes {
+            ret = ret.rescale(pass, 1, |glyph| match pass {
+                2 => scale_2x(glyph),
+                _ => scale_3x(glyph),
+            })?;
+        }
+        Ok(ret)
+    }
+    /// Implements `scaled` and `scaled_pixel_art`. Every glyph is cut out of
+    /// the strike and passed through `scale_glyph`, and everything else is
+    /// scaled by `num / den`.
+    fn rescale(
+        &self,
+        num: u32,
+        den: u32,
+        scale_glyph: impl Fn(&LooseGlyph) -> LooseGlyph,
+    ) -> anyhow::Result<Font> {
+        let scale = |x: i32| scale_rounded(x, num, den);
+        let glyphs: BTreeMap<u16, LooseGlyph> = self
+            .glyph_range
+            .clone()
+            .filter(|id| self.get_glyph(*id).3)
+            .map(|id| (id, scale_glyph(&self.get_loose_glyph(id))))
+            .collect();
+        let missing_glyph = scale_glyph(&self.get_loose_glyph(MISSING_GLYPH));
+        let mut ret = Font::from_loose_glyphs(
+            &glyphs,
+            &missing_glyph,
+            scale(self.get_ascent()),
+            scale(self.get_descent()),
+            scale(self.get_leading()),
+        )?;
+        if self.glyph_widths.is_some() {
+            let ids = ret.glyph_range.clone().chain([MISSING_GLYPH]);
+            ret.glyph_widths = Some(
+                ids.map(|id| {
+                    let width =
+                        self.get_fractional_advance(id).unwrap() as i32;
+                    scale(width).clamp(0, 0xFFFF) as u16
+                })
+                .collect(),
+            );
+        }
+        ret.kerning = self
+            .kerning
+            .iter()
+            .map(|(pair, amount)| (*pair, scale(*amount)))
+            .filter(|(_, amount)| *amount != 0)
+            .collect();
+        Ok(ret)
+    }
+}
+
+/// Breaks the given factor down into Scale2x and Scale3x passes, or returns
+/// `None` if it isn't made up of twos and threes.
+fn get_pixel_art_passes(factor: u32) -> Option<Vec<u32>> {
+    let mut passes = vec![];
+    let mut remaining = factor;
+    for pass in [2, 3] {
+        while remaining != 0 && remaining.is_multiple_of(pass) {
+            passes.push(pass);
+            remaining /= pass;
+        }
+    }
+    (remaining == 1).then_some(passes)
+}
+
+/// Multiplies by `num / den`, rounding to the nearest integer.
+fn scale_rounded(x: i32, num: u32, den: u32) -> i32 {
+    let (num, den) = (num as i64, den as i64);
+    (x as i64 * num * 2 + den).div_euclid(den * 2) as i32
+}
+
+/// Nearest-neighbor scaling by `num / den`.
+fn scale_nearest(glyph: &LooseGlyph, num: u32, den: u32) -> LooseGlyph {
+    let bounds = glyph.get_bounds();
+    let left = scale_rounded(bounds.left, num, den);
+    let right = scale_rounded(bounds.right, num, den);
+    let top = scale_rounded(bounds.top, num, den);
+    let bottom = scale_rounded(bounds.bottom, num, den);
+    // Maps the center of a destination pixel back to a source pixel.
+    let unscale = |x: i32| {
+        let (num, den) = (num as i64, den as i64);
+        (((x as i64) * 2 + 1) * den).div_euclid(num * 2) as i32
+    };
+    let mut bitmap = Bitmap::new(
+        (right - left).max(0) as u32,
+        (bottom - top).max(0) as u32,
+    );
+    for y in top..bottom {
+        let src_y = unscale(y) - bounds.top;
+        for x in left..right {
+            let src_x = unscale(x) - bounds.left;
+            if glyph.bitmap.get_pixel(src_x, src_y) {
+                bitmap.set_pixel(x - left, y - top, true);
+            }
+        }
+    }
+    LooseGlyph {
+        bitmap,
+        top: -top,
+        offset: left,
+        advance: scale_rounded(glyph.advance as i32, num, den) as u32,
+    }
+}
+
+/// Scale2x (also known as EPX): each pixel becomes a 2x2 block, whose corners
+/// take on the color of the neighbors that meet there, if they agree.
+fn scale_2x(glyph: &LooseGlyph) -> LooseGlyph {
+    let src = &glyph.bitmap;
+    let mut bitmap = Bitmap::new(src.get_width() * 2, src.get_height() * 2);
+    for y in 0..src.get_height() as i32 {
+        for x in 0..src.get_width() as i32 {
+            let p = src.get_pixel(x, y);
+            let a = src.get_pixel(x, y - 1);
+            let b = src.get_pixel(x + 1, y);
+            let c = src.get_pixel(x - 1, y);
+            let d = src.get_pixel(x, y + 1);
+            let out = if a != d && c != b {
+                [
+                    if c == a { a } else { p },
+                    if a == b { b } else { p },
+                    if d == c { c } else { p },
+                    if b == d { d } else { p },
+                ]
+            } else {
+                [p; 4]
+            };
+            for (n, value) in out.into_iter().enumerate() {
+                let (dx, dy) = (n as i32 % 2, n as i32 / 2);
+                bitmap.set_pixel(x * 2 + dx, y * 2 + dy, value);
+            }
+        }
+    }
+    LooseGlyph {
+        bitmap,
+        top: glyph.top * 2,
+        offset: glyph.offset * 2,
+        advance: glyph.advance * 2,
+    }
+}
+
+/// Scale3x (also known as AdvMAME3x): like Scale2x, but each pixel becomes a
+/// 3x3 block.
+fn scale_3x(glyph: &LooseGlyph) -> LooseGlyph {
+    let src = &glyph.bitmap;
+    let mut bitmap = Bitmap::new(src.get_width() * 3, src.get_height() * 3);
+    for y in 0..src.get_height() as i32 {
+        for x in 0..src.get_width() as i32 {
+            // A B C
+            // D E F
+            // G H I
+            let a = src.get_pixel(x - 1, y - 1);
+            let b = src.get_pixel(x, y - 1);
+            let c = src.get_pixel(x + 1, y - 1);
+            let d = src.get_pixel(x - 1, y);
+            let e = src.get_pixel(x, y);
+            let f = src.get_pixel(x + 1, y);
+            let g = src.get_pixel(x - 1, y + 1);
+            let h = src.get_pixel(x, y + 1);
+            let i = src.get_pixel(x + 1, y + 1);
+            let out = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) {
+                        b
+                    } else {
+                        e
+                    },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) {
+                        d
+                    } else {
+                        e
+                    },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) {
+                        f
+                    } else {
+                        e
+                    },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) {
+                        h
+                    } else {
+                        e
+                    },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+            for (n, value) in out.into_iter().enumerate() {
+                let (dx, dy) = (n as i32 % 3, n as i32 / 3);
+                bitmap.set_pixel(x * 3 + dx, y * 3 + dy, value);
+            }
+        }
+    }
+    LooseGlyph {
+        bitmap,
+        top: glyph.top * 3,
+        offset: glyph.offset * 3,
+        advance: glyph.advance * 3,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn nearest() {
+        let font = super::super::test::make_test_font();
+        let big = font.scaled(2, 1).unwrap();
+        assert_eq!((big.get_ascent(), big.get_descent()), (14, 4));
+        assert_eq!(big.get_leading(), 2);
+        let (rect, offset, advance, present) = big.get_glyph(b'A' as u16);
+        assert_eq!(
+            (rect.get_width(), offset, advance, present),
+            (8, 0, 10, true)
+        );
+        let small = font.scaled(1, 2).unwrap();
+        let (rect, _, advance, _) = small.get_glyph(b'A' as u16);
+        assert_eq!((rect.get_width(), advance), (2, 3));
+        assert!(font.scaled(1, 0).is_err());
+    }
+    #[test]
+    fn pixel_art() {
+        // a diagonal line
+        let glyph = LooseGlyph {
+            bitmap: Bitmap::from_bytes(2, 2, &[0x80, 0x40]),
+            top: 2,
+            offset: 0,
+            advance: 3,
+        };
+        let scaled = scale_2x(&glyph);
+        assert_eq!(scaled.bitmap.to_bytes(), &[0xC0, 0xE0, 0x70, 0x30]);
+        assert_eq!((scaled.top, scaled.advance), (4, 6));
+        let scaled = scale_3x(&glyph);
+        assert_eq!(
+            scaled.bitmap.to_bytes(),
+            &[0xE0, 0xE0, 0xF0, 0x3C, 0x1C, 0x1C]
+        );
+        let font = super::super::test::make_test_font();
+        assert_eq!(font.scaled_pixel_art(6).unwrap().get_ascent(), 42);
+        assert!(font.scaled_pixel_art(5).is_err());
+        // whole-number enlargements by `scaled` go through the pixel-art
+        // scalers too
+        let glyphs = BTreeMap::from([(b'A' as u16, glyph.clone())]);
+        let font = Font::from_loose_glyphs(&glyphs, &glyph, 2, 0, 0).unwrap();
+        let scaled_glyph = |num, den| {
+            let font = font.scaled(num, den).unwrap();
+            font.get_loose_glyph(b'A' as u16).bitmap.to_bytes()
+        };
+        assert_eq!(scaled_glyph(2, 1), scale_2x(&glyph).bitmap.to_bytes());
+        assert_eq!(scaled_glyph(6, 2), scale_3x(&glyph).bitmap.to_bytes());
+        // but other factors are still a staircase
+        assert_eq!(scaled_glyph(5, 1)[..4], [0xF8, 0x00, 0xF8, 0x00]);
+    }
+}
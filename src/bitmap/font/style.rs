@@ -0,0 +1,124 @@
+use std::ops::{BitOr, BitOrAssign};
+
+use super::*;
+
+/// A set of QuickDraw text styles. The bits are the same as in a QuickDraw
+/// `Style` (and `MacFontInfo::style`), so `StyleFlags(info.style)` does what
+/// you'd expect.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct StyleFlags(pub u16);
+
+impl StyleFlags {
+    /// No styles at all.
+    pub const PLAIN: StyleFlags = StyleFlags(0);
+    /// See `Font::make_bold`.
+    pub const BOLD: StyleFlags = StyleFlags(0x01);
+    /// See `Font::make_italic`.
+    pub const ITALIC: StyleFlags = StyleFlags(0x02);
+    /// See `Font::make_underline`.
+    pub const UNDERLINE: StyleFlags = StyleFlags(0x04);
+    /// See `Font::make_outline`.
+    pub const OUTLINE: StyleFlags = StyleFlags(0x08);
+    /// See `Font::make_shadow`.
+    pub const SHADOW: StyleFlags = StyleFlags(0x10);
+    /// See `Font::make_condensed`. (By one pixel.)
+    pub const CONDENSE: StyleFlags = StyleFlags(0x20);
+    /// See `Font::make_extended`. (By one pixel.)
+    pub const EXTEND: StyleFlags = StyleFlags(0x40);
+    /// Returns true if every style in `other` is also in `self`.
+    pub fn contains(self, other: StyleFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+    /// Returns true if there are no styles.
+    pub fn is_plain(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for StyleFlags {
+    type Output = StyleFlags;
+    fn bitor(self, rhs: StyleFlags) -> StyleFlags {
+        StyleFlags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for StyleFlags {
+    fn bitor_assign(&mut self, rhs: StyleFlags) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl Font {
+    /// Makes a version of this font with the given styles applied, assuming
+    /// that this font is plain. The styles are applied in the order that
+    /// QuickDraw applied them: bold, italic, underline, outline, shadow,
+    /// condense, extend.
+    ///
+    /// Shadow includes an outline, so if both are given, only the shadow is
+    /// applied. Condense and extend cancel each other out. Unknown bits are
+    /// ignored.
+    pub fn derive_style(&self, style: StyleFlags) -> Font {
+        let mut ret = None;
+        let mut apply = |f: &dyn Fn(&Font) -> Font| {
+            ret = Some(f(ret.as_ref().unwrap_or(self)));
+        };
+        if style.contains(StyleFlags::BOLD) {
+            apply(&Font::make_bold);
+        }
+        if style.contains(StyleFlags::ITALIC) {
+            apply(&Font::make_italic);
+        }
+        if style.contains(StyleFlags::UNDERLINE) {
+            apply(&Font::make_underline);
+        }
+        if style.contains(StyleFlags::SHADOW) {
+            apply(&Font::make_shadow);
+        } else if style.contains(StyleFlags::OUTLINE) {
+            apply(&Font::make_outline);
+        }
+        match (
+            style.contains(StyleFlags::CONDENSE),
+            style.contains(StyleFlags::EXTEND),
+        ) {
+            (true, false) => apply(&|font| font.make_condensed(1)),
+            (false, true) => apply(&|font| font.make_extended(1)),
+            _ => (),
+        }
+        ret.unwrap_or_else(|| self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn derive_style() {
+        let font = super::super::test::make_test_font();
+        let advance =
+            |font: &Font, style| font.derive_style(style).get_glyph(0x41).2;
+        assert_eq!(advance(&font, StyleFlags::PLAIN), 5);
+        assert_eq!(advance(&font, StyleFlags::BOLD), 6);
+        assert_eq!(advance(&font, StyleFlags::BOLD | StyleFlags::SHADOW), 8);
+        assert_eq!(
+            advance(&font, StyleFlags::SHADOW | StyleFlags::OUTLINE),
+            7
+        );
+        assert_eq!(advance(&font, StyleFlags::CONDENSE), 4);
+        assert_eq!(
+            advance(&font, StyleFlags::CONDENSE | StyleFlags::EXTEND),
+            5
+        );
+        // bold before italic, underline after both
+        let mut expected = Bitmap::new(32, 16);
+        let mut actual = Bitmap::new(32, 16);
+        let derived = font.derive_style(
+            StyleFlags::UNDERLINE | StyleFlags::ITALIC | StyleFlags::BOLD,
+        );
+        let manual = font.make_bold().make_italic().make_underline();
+        expected.draw_glyph(ModeOr(()), None, 4, 8, &[&manual], 0x41);
+        actual.draw_glyph(ModeOr(()), None, 4, 8, &[&derived], 0x41);
+        assert_eq!(expected.to_bytes(), actual.to_bytes());
+    }
+}
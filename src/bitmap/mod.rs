@@ -4,6 +4,8 @@ mod draw;
 
 pub use draw::*;
 
+mod transform;
+
 #[cfg(feature = "font")]
 mod font;
 #[cfg(feature = "font")]
@@ -121,4 +123,35 @@ impl Bitmap {
             bottom: self.height as i32,
         }
     }
+    /// Returns true if the given pixel is set. Pixels outside the bitmap are
+    /// never set.
+    pub fn get_pixel(&self, x: i32, y: i32) -> bool {
+        match self.get_pixel_index(x, y) {
+            Some((i, bit)) => self.words[i] & bit != 0,
+            None => false,
+        }
+    }
+    /// Sets or clears the given pixel. Pixels outside the bitmap are ignored.
+    pub fn set_pixel(&mut self, x: i32, y: i32, value: bool) {
+        if let Some((i, bit)) = self.get_pixel_index(x, y) {
+            if value {
+                self.words[i] |= bit;
+            } else {
+                self.words[i] &= !bit;
+            }
+        }
+    }
+    /// Returns the index of the word containing the given pixel, and the bit
+    /// within that word, if the pixel is inside the bitmap.
+    fn get_pixel_index(&self, x: i32, y: i32) -> Option<(usize, BitmapWord)> {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height
+        {
+            return None;
+        }
+        let (x, y) = (x as u32, y as u32);
+        let i = y * self.words_per_row + x / BITMAP_WORD_BITS as u32;
+        let bit =
+            (1 << (BITMAP_WORD_BITS - 1)) >> (x % BITMAP_WORD_BITS as u32);
+        Some((i as usize, bit))
+    }
 }
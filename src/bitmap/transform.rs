@@ -0,0 +1,414 @@
+use super::*;
+
+impl Bitmap {
+    /// Returns a copy of this bitmap, rotated 90 degrees clockwise.
+    pub fn rotate_90(&self) -> Bitmap {
+        self.transpose().flip_horizontal()
+    }
+    /// Returns a copy of this bitmap, rotated 180 degrees.
+    pub fn rotate_180(&self) -> Bitmap {
+        self.flip_horizontal().flip_vertical()
+    }
+    /// Returns a copy of this bitmap, rotated 270 degrees clockwise (90
+    /// degrees counterclockwise).
+    pub fn rotate_270(&self) -> Bitmap {
+        self.transpose().flip_vertical()
+    }
+    /// Returns a mirror image of this bitmap, with left and right swapped.
+    pub fn flip_horizontal(&self) -> Bitmap {
+        let mut ret = Bitmap::new(self.width, self.height);
+        let words_per_row = self.words_per_row as usize;
+        if words_per_row == 0 {
+            return ret;
+        }
+        // Reversing the words of a row leaves the padding on the left, so
+        // everything has to be shifted left by that much.
+        let padding = (words_per_row * BITMAP_WORD_BITS) as u32 - self.width;
+        for (src, dst) in self
+            .words
+            .chunks_exact(words_per_row)
+            .zip(ret.words.chunks_exact_mut(words_per_row))
+        {
+            for (src, dst) in src.iter().rev().zip(dst.iter_mut()) {
+                *dst = src.reverse_bits();
+            }
+            if padding != 0 {
+                for i in 0..words_per_row {
+                    let next = dst.get(i + 1).copied().unwrap_or(0);
+                    dst[i] = dst[i] << padding
+                        | next >> (BITMAP_WORD_BITS as u32 - padding);
+                }
+            }
+        }
+        ret
+    }
+    /// Returns a mirror image of this bitmap, with top and bottom swapped.
+    pub fn flip_vertical(&self) -> Bitmap {
+        let mut ret = Bitmap::new(self.width, self.height);
+        let words_per_row = self.words_per_row as usize;
+        if words_per_row == 0 {
+            return ret;
+        }
+        for (src, dst) in self
+            .words
+            .chunks_exact(words_per_row)
+            .rev()
+            .zip(ret.words.chunks_exact_mut(words_per_row))
+        {
+            dst.copy_from_slice(src);
+        }
+        ret
+    }
+    /// Returns a copy of this bitmap, rotated clockwise by the given number of
+    /// degrees. The result is just big enough to hold the whole rotated
+    /// image, and the corners that aren't part of it are set to
+    /// `background`.
+    ///
+    /// The rotation is done with three shears (first horizontal, then
+    /// vertical, then horizontal again), after turning by whatever multiple
+    /// of 90 degrees is closest. Every pixel ends up in exactly one place, so
+    /// the image stays crisp and never gains or loses any set pixels, though
+    /// edges become jagged.
+    pub fn rotate(&self, angle: f64, background: bool) -> Bitmap {
+        let quarter_turns = (angle / 90.0).round();
+        let turned = match (quarter_turns as i64).rem_euclid(4) {
+            0 => self.clone(),
+            1 => self.rotate_90(),
+            2 => self.rotate_180(),
+            _ => self.rotate_270(),
+        };
+        let angle = (angle - quarter_turns * 90.0).to_radians();
+        if angle == 0.0 {
+            return turned;
+        }
+        let sin = angle.sin();
+        let tan = (angle / 2.0).tan();
+        let sheared = turned
+            .shear_x(-tan, background)
+            .shear_y(sin, background)
+            .shear_x(-tan, background);
+        // The shears leave a lot of extra space around the image. Keep just
+        // the part that the image ended up in.
+        let bounds = get_sheared_bounds(turned.width, turned.height, tan, sin);
+        let mut ret = Bitmap::new(bounds.get_width(), bounds.get_height());
+        if background {
+            ret.fill_rect(ModeCopy(()), None, ret.get_bounds());
+        }
+        let (left, top) = (bounds.left, bounds.top);
+        ret.blit_bits(
+            ModeCopy(()),
+            None,
+            &sheared,
+            Some(ret.get_bounds().offset_by(left, top)),
+            0,
+            0,
+        );
+        ret
+    }
+    /// Returns a copy of this bitmap, slanted sideways: each row is shifted
+    /// `factor` pixels further right than the row above it, rounded to the
+    /// nearest pixel. (A negative factor slants the other way, as for a
+    /// fake italic.) The result is widened to fit, and the new pixels are
+    /// set to `background`.
+    ///
+    /// Rows that are shifted by the same amount are moved together, with
+    /// one call to `blit_bits`.
+    pub fn shear_x(&self, factor: f64, background: bool) -> Bitmap {
+        let shifts = get_shear_shifts(factor, self.height);
+        let (min, max) = get_shift_range(&shifts);
+        let mut ret =
+            Bitmap::new(self.width + (max - min) as u32, self.height);
+        if background {
+            ret.fill_rect(ModeCopy(()), None, ret.get_bounds());
+        }
+        for (top, bottom, shift) in group_shifts(&shifts) {
+            let rect = Rectangle {
+                left: 0,
+                top,
+                right: self.width as i32,
+                bottom,
+            };
+            ret.blit_bits(
+                ModeCopy(()),
+                None,
+                self,
+                Some(rect),
+                shift - min,
+                top,
+            );
+        }
+        ret
+    }
+    /// Returns a copy of this bitmap, slanted up or down: each column is
+    /// shifted `factor` pixels further down than the column to its left,
+    /// rounded to the nearest pixel. The result is made taller to fit, and
+    /// the new pixels are set to `background`.
+    ///
+    /// Columns that are shifted by the same amount are moved together, with
+    /// one call to `blit_bits`.
+    pub fn shear_y(&self, factor: f64, background: bool) -> Bitmap {
+        let shifts = get_shear_shifts(factor, self.width);
+        let (min, max) = get_shift_range(&shifts);
+        let mut ret =
+            Bitmap::new(self.width, self.height + (max - min) as u32);
+        if background {
+            ret.fill_rect(ModeCopy(()), None, ret.get_bounds());
+        }
+        for (left, right, shift) in group_shifts(&shifts) {
+            let rect = Rectangle {
+                left,
+                top: 0,
+                right,
+                bottom: self.height as i32,
+            };
+            ret.blit_bits(
+                ModeCopy(()),
+                None,
+                self,
+                Some(rect),
+                left,
+                shift - min,
+            );
+        }
+        ret
+    }
+    /// Returns a copy of this bitmap with its rows and columns swapped, one
+    /// 32x32 block at a time.
+    fn transpose(&self) -> Bitmap {
+        let mut ret = Bitmap::new(self.height, self.width);
+        let src_words_per_row = self.words_per_row as usize;
+        let dst_words_per_row = ret.words_per_row as usize;
+        let (width, height) = (self.width as usize, self.height as usize);
+        for block_y in 0..dst_words_per_row {
+            for block_x in 0..src_words_per_row {
+                let mut block = [0; BITMAP_WORD_BITS];
+                for (n, word) in block.iter_mut().enumerate() {
+                    let y = block_y * BITMAP_WORD_BITS + n;
+                    if y >= height {
+                        break;
+                    }
+                    *word = self.words[y * src_words_per_row + block_x];
+                }
+                transpose_block(&mut block);
+                for (n, word) in block.iter().enumerate() {
+                    let x = block_x * BITMAP_WORD_BITS + n;
+                    if x >= width {
+                        break;
+                    }
+                    ret.words[x * dst_words_per_row + block_y] = *word;
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// Returns how far each of `count` rows (or columns) should be shifted to
+/// shear by `factor`. The shear is centered on the middle of the bitmap.
+fn get_shear_shifts(factor: f64, count: u32) -> Vec<i32> {
+    let middle = count as f64 / 2.0;
+    (0..count)
+        .map(|n| (factor * (n as f64 + 0.5 - middle) + 0.5).floor() as i32)
+        .collect()
+}
+
+/// Returns the rectangle that a `width` by `height` image ends up in when
+/// `rotate` shears it by `-tan`, then `sin`, then `-tan` again, relative to
+/// the top-left corner of the final sheared bitmap. This follows the same
+/// rounded shifts as the shears themselves, so every pixel is inside it.
+fn get_sheared_bounds(
+    width: u32,
+    height: u32,
+    tan: f64,
+    sin: f64,
+) -> Rectangle {
+    let first = get_shear_shifts(-tan, height);
+    let (first_min, first_max) = get_shift_range(&first);
+    let second = get_shear_shifts(sin, width + (first_max - first_min) as u32);
+    let (second_min, second_max) = get_shift_range(&second);
+    let third =
+        get_shear_shifts(-tan, height + (second_max - second_min) as u32);
+    let third_min = get_shift_range(&third).0;
+    let mut bounds = Rectangle {
+        left: i32::MAX,
+        top: i32::MAX,
+        right: i32::MIN,
+        bottom: i32::MIN,
+    };
+    for (y, first_shift) in first.iter().enumerate() {
+        let left = first_shift - first_min;
+        for x in left..left + width as i32 {
+            let y = y as i32 + second[x as usize] - second_min;
+            let x = x + third[y as usize] - third_min;
+            bounds.left = bounds.left.min(x);
+            bounds.top = bounds.top.min(y);
+            bounds.right = bounds.right.max(x + 1);
+            bounds.bottom = bounds.bottom.max(y + 1);
+        }
+    }
+    if bounds.is_empty() {
+        Rectangle::default()
+    } else {
+        bounds
+    }
+}
+
+/// Returns the smallest and largest shift.
+fn get_shift_range(shifts: &[i32]) -> (i32, i32) {
+    let min = shifts.iter().copied().min().unwrap_or(0);
+    let max = shifts.iter().copied().max().unwrap_or(0);
+    (min, max)
+}
+
+/// Groups consecutive rows (or columns) that are shifted by the same amount,
+/// returning the start (inclusive), end (exclusive), and shift of each group.
+fn group_shifts(shifts: &[i32]) -> Vec<(i32, i32, i32)> {
+    let mut ret: Vec<(i32, i32, i32)> = vec![];
+    for (n, shift) in shifts.iter().enumerate() {
+        match ret.last_mut() {
+            Some(last) if last.2 == *shift => last.1 = n as i32 + 1,
+            _ => ret.push((n as i32, n as i32 + 1, *shift)),
+        }
+    }
+    ret
+}
+
+/// Transposes a 32x32 matrix of bits in place, where each word is a row and
+/// the most significant bit is the leftmost column. Each pass swaps the
+/// upper right and lower left quarters of every block, working down from
+/// 16x16 blocks to 1x1 blocks. (From "Hacker's Delight", section 7-3.)
+fn transpose_block(block: &mut [BitmapWord; BITMAP_WORD_BITS]) {
+    let mut size = BITMAP_WORD_BITS / 2;
+    let mut mask: BitmapWord = !0 >> size;
+    while size != 0 {
+        let mut k = 0;
+        while k < BITMAP_WORD_BITS {
+            let swapped = (block[k] ^ (block[k + size] >> size)) & mask;
+            block[k] ^= swapped;
+            block[k + size] ^= swapped << size;
+            k = (k + size + 1) & !size;
+        }
+        size /= 2;
+        mask ^= mask << size;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn transforms() {
+        for (width, height) in [(1, 1), (5, 3), (32, 32), (33, 70), (70, 9)] {
+            let mut bitmap = Bitmap::new(width, height);
+            for y in 0..height as i32 {
+                for x in 0..width as i32 {
+                    if (x * 7 + y * 13 + x * y) % 5 < 2 {
+                        bitmap.set_pixel(x, y, true);
+                    }
+                }
+            }
+            let (w, h) = (width as i32, height as i32);
+            let check =
+                |transformed: Bitmap,
+                 size: (u32, u32),
+                 source_of: &dyn Fn(i32, i32) -> (i32, i32)| {
+                    assert_eq!(
+                        (transformed.get_width(), transformed.get_height()),
+                        size
+                    );
+                    for y in 0..size.1 as i32 {
+                        for x in 0..size.0 as i32 {
+                            let (src_x, src_y) = source_of(x, y);
+                            assert_eq!(
+                                transformed.get_pixel(x, y),
+                                bitmap.get_pixel(src_x, src_y),
+                                "{width}x{height} at {x}, {y}"
+                            );
+                        }
+                    }
+                    // nothing stray in the padding
+                    let mut padded = transformed.clone();
+                    padded.width =
+                        padded.words_per_row * BITMAP_WORD_BITS as u32;
+                    for y in 0..size.1 as i32 {
+                        for x in size.0 as i32..padded.width as i32 {
+                            assert!(!padded.get_pixel(x, y));
+                        }
+                    }
+                };
+            check(bitmap.flip_horizontal(), (width, height), &|x, y| {
+                (w - 1 - x, y)
+            });
+            check(bitmap.flip_vertical(), (width, height), &|x, y| {
+                (x, h - 1 - y)
+            });
+            check(bitmap.rotate_180(), (width, height), &|x, y| {
+                (w - 1 - x, h - 1 - y)
+            });
+            check(bitmap.rotate_90(), (height, width), &|x, y| (y, h - 1 - x));
+            check(bitmap.rotate_270(), (height, width), &|x, y| {
+                (w - 1 - y, x)
+            });
+        }
+        assert_eq!(Bitmap::new(0, 5).rotate_90().get_height(), 0);
+    }
+    #[test]
+    fn shear_and_rotate() {
+        let count = |bitmap: &Bitmap| {
+            bitmap.words.iter().map(|x| x.count_ones()).sum::<u32>()
+        };
+        let square = Bitmap::from_bytes(4, 4, &[0xF0; 4]);
+        let sheared = square.shear_x(1.0, false);
+        assert_eq!(sheared.get_width(), 7);
+        assert_eq!(sheared.to_bytes(), &[0xF0, 0x78, 0x3C, 0x1E]);
+        let sheared = square.shear_x(-0.5, false);
+        assert_eq!(sheared.to_bytes(), &[0x3C, 0x78, 0x78, 0xF0]);
+        let sheared = square.shear_x(-0.5, true);
+        assert_eq!(sheared.to_bytes(), &[0xFC; 4]);
+        let bitmap =
+            Bitmap::from_bytes(12, 3, &[0xA5, 0xC0, 0x3C, 0x30, 0xFF, 0x10]);
+        assert_eq!(
+            bitmap.shear_y(0.3, false).to_bytes(),
+            bitmap
+                .transpose()
+                .shear_x(0.3, false)
+                .transpose()
+                .to_bytes()
+        );
+        // right angles
+        assert_eq!(bitmap.rotate(0.0, false).to_bytes(), bitmap.to_bytes());
+        assert_eq!(
+            bitmap.rotate(90.0, false).to_bytes(),
+            bitmap.rotate_90().to_bytes()
+        );
+        assert_eq!(
+            bitmap.rotate(-90.0, false).to_bytes(),
+            bitmap.rotate_270().to_bytes()
+        );
+        // every pixel survives, whatever the size and angle
+        for (width, height) in [(40, 30), (17, 30), (17, 64), (31, 9), (1, 7)]
+        {
+            let mut bitmap = Bitmap::new(width, height);
+            bitmap.fill_rect(ModeCopy(()), None, bitmap.get_bounds());
+            for angle in
+                [3.0, 30.0, -44.0, 100.0, 200.0, -4.8, 2.5, -85.1, 61.7, 0.4]
+            {
+                let rotated = bitmap.rotate(angle, false);
+                assert_eq!(
+                    count(&rotated),
+                    width * height,
+                    "{width}x{height} at {angle}"
+                );
+                let (w, h) = (rotated.get_width(), rotated.get_height());
+                assert!(rotated.get_pixel(w as i32 / 2, h as i32 / 2));
+                let rotated = bitmap.rotate(angle, true);
+                assert_eq!(count(&rotated), w * h);
+            }
+        }
+        let mut square = Bitmap::new(40, 30);
+        square.fill_rect(ModeCopy(()), None, square.get_bounds());
+        let rotated = square.rotate(30.0, false);
+        assert_eq!((rotated.get_width(), rotated.get_height()), (50, 46));
+        assert!(!rotated.get_pixel(0, 0));
+    }
+}
@@ -4,7 +4,7 @@
  * There are some optional features that are disabled by default:
  *
  * - `display`: Display support, using SDL2 and OpenGL 3.1.
- * - `font`: Bitmapped font support. (Currently only Macintosh format)
+ * - `font`: Bitmapped font support. (Macintosh, BDF, and PSF formats)
  * - `netpbm`: netpbm image input and output (pbm, pgm, ppm).
  */
 #[cfg(feature = "display")]
@@ -15,7 +15,10 @@ pub use display::Display;
 
 mod bitmap;
 mod rectangle;
+mod region;
 #[doc(inline)]
 pub use bitmap::*;
 #[doc(inline)]
 pub use rectangle::*;
+#[doc(inline)]
+pub use region::*;
@@ -20,6 +20,16 @@ impl Rectangle {
             bottom: self.bottom + amount as i32,
         }
     }
+    /// Moves the rectangle by the given number of pixels and returns the
+    /// resulting rectangle.
+    pub fn offset_by(&self, dx: i32, dy: i32) -> Rectangle {
+        Rectangle {
+            left: self.left + dx,
+            top: self.top + dy,
+            right: self.right + dx,
+            bottom: self.bottom + dy,
+        }
+    }
     /// Returns the rectangle that exists at the intersection between these two
     /// rectangles. The result will be an empty rectangle if there is no
     /// overlap.
@@ -0,0 +1,366 @@
+use super::*;
+
+/// An arbitrary set of pixels, like QuickDraw's `RgnHandle`. Regions can be
+/// filled, framed, and inverted, and can be used to clip any drawing
+/// operation (see `Clip`).
+///
+/// A region is stored as a list of horizontal bands, from top to bottom.
+/// Every row in a band is covered by the same sorted list of spans (left
+/// inclusive, right exclusive). Every region has exactly one such
+/// representation, so two regions are equal if and only if they cover the
+/// same pixels.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Region {
+    bands: Vec<Band>,
+}
+
+/// A run of rows that are covered by the same spans.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Band {
+    top: i32,
+    bottom: i32,
+    /// Sorted, non-empty, and never touching each other.
+    spans: Vec<(i32, i32)>,
+}
+
+impl Region {
+    /// Creates a new empty region.
+    pub fn new() -> Region {
+        Region::default()
+    }
+    /// Makes a region out of every set pixel in the given bitmap, like
+    /// QuickDraw's `BitMapToRegion`.
+    pub fn from_bitmap(bitmap: &Bitmap) -> Region {
+        let mut ret = Region::new();
+        let width = bitmap.get_width() as i32;
+        for y in 0..bitmap.get_height() as i32 {
+            let start = (y as u32 * bitmap.words_per_row) as usize;
+            let row =
+                &bitmap.words[start..start + bitmap.words_per_row as usize];
+            let mut spans: Vec<(i32, i32)> = vec![];
+            // The left edge of the span we're in, if we're in one.
+            let mut span_start = None;
+            for (i, word) in row.iter().enumerate() {
+                let base = (i * BITMAP_WORD_BITS) as i32;
+                let mut bit = 0;
+                while bit < BITMAP_WORD_BITS as u32 {
+                    // Skip ahead to the next pixel that's different from
+                    // the ones we're in.
+                    let rest = word << bit;
+                    let run = match span_start {
+                        Some(_) => (!rest).leading_zeros(),
+                        None => rest.leading_zeros(),
+                    };
+                    bit += run.min(BITMAP_WORD_BITS as u32 - bit);
+                    if bit == BITMAP_WORD_BITS as u32 {
+                        break;
+                    }
+                    let x = base + bit as i32;
+                    match span_start.take() {
+                        Some(left) => spans.push((left, x)),
+                        None => span_start = Some(x),
+                    }
+                }
+            }
+            if let Some(left) = span_start {
+                spans.push((left, width));
+            }
+            // Rows may have stray bits past the right edge of the bitmap.
+            spans.retain(|(left, _)| *left < width);
+            if let Some(last) = spans.last_mut() {
+                last.1 = last.1.min(width);
+            }
+            ret.push_band(y, y + 1, spans);
+        }
+        ret
+    }
+    /// Makes a bitmap of the given size, with every pixel in this region
+    /// set. Parts of the region outside the bitmap are left out.
+    pub fn to_bitmap(&self, width: u32, height: u32) -> Bitmap {
+        let mut ret = Bitmap::new(width, height);
+        ret.fill_region(ModeOr(()), None, self);
+        ret
+    }
+    /// Returns true if this region doesn't contain any pixels.
+    pub fn is_empty(&self) -> bool {
+        self.bands.is_empty()
+    }
+    /// Returns the smallest rectangle that contains every pixel of this
+    /// region.
+    pub fn get_bounds(&self) -> Rectangle {
+        let (Some(first), Some(last)) =
+            (self.bands.first(), self.bands.last())
+        else {
+            return Rectangle::default();
+        };
+        Rectangle {
+            left: self.bands.iter().map(|x| x.spans[0].0).min().unwrap(),
+            top: first.top,
+            right: self
+                .bands
+                .iter()
+                .map(|x| x.spans.last().unwrap().1)
+                .max()
+                .unwrap(),
+            bottom: last.bottom,
+        }
+    }
+    /// Returns true if the given pixel is in this region.
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.get_spans(y)
+            .iter()
+            .any(|(left, right)| *left <= x && x < *right)
+    }
+    /// Returns the spans (left inclusive, right exclusive) that cover the
+    /// given row, from left to right.
+    pub fn get_spans(&self, y: i32) -> &[(i32, i32)] {
+        let i = self.bands.partition_point(|band| band.bottom <= y);
+        match self.bands.get(i) {
+            Some(band) if band.top <= y => &band.spans,
+            _ => &[],
+        }
+    }
+    /// Returns a set of non-overlapping rectangles that together cover
+    /// exactly this region, from top to bottom and left to right.
+    pub fn get_rectangles(&self) -> impl Iterator<Item = Rectangle> + '_ {
+        self.bands.iter().flat_map(|band| {
+            band.spans.iter().map(|(left, right)| Rectangle {
+                left: *left,
+                top: band.top,
+                right: *right,
+                bottom: band.bottom,
+            })
+        })
+    }
+    /// Returns the region containing every pixel that is in either region.
+    pub fn union(&self, rhs: &Region) -> Region {
+        self.combine(rhs, |a, b| a || b)
+    }
+    /// Returns the region containing every pixel that is in both regions.
+    pub fn intersection(&self, rhs: &Region) -> Region {
+        self.combine(rhs, |a, b| a && b)
+    }
+    /// Returns the region containing every pixel that is in this region but
+    /// not in `rhs`.
+    pub fn difference(&self, rhs: &Region) -> Region {
+        self.combine(rhs, |a, b| a && !b)
+    }
+    /// Returns the region containing every pixel that is in exactly one of
+    /// the two regions.
+    pub fn xor(&self, rhs: &Region) -> Region {
+        self.combine(rhs, |a, b| a != b)
+    }
+    /// Moves the region by the given number of pixels and returns the
+    /// resulting region.
+    pub fn offset_by(&self, dx: i32, dy: i32) -> Region {
+        Region {
+            bands: self
+                .bands
+                .iter()
+                .map(|band| Band {
+                    top: band.top + dy,
+                    bottom: band.bottom + dy,
+                    spans: band
+                        .spans
+                        .iter()
+                        .map(|(left, right)| (left + dx, right + dx))
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+    /// Shrinks the region by moving its left and right edges `dx` pixels
+    /// inward, and its top and bottom edges `dy` pixels inward, and returns
+    /// the resulting region. Parts that are too thin disappear. Negative
+    /// amounts grow the region instead, filling in any gaps that get closed.
+    /// This is QuickDraw's `InsetRgn`.
+    pub fn inset_by(&self, dx: i32, dy: i32) -> Region {
+        let mut horizontal = Region::new();
+        for band in self.bands.iter() {
+            let mut spans: Vec<(i32, i32)> = vec![];
+            for (left, right) in band.spans.iter() {
+                let (left, right) = (left + dx, right - dx);
+                if left >= right {
+                    continue;
+                }
+                match spans.last_mut() {
+                    Some(last) if last.1 >= left => last.1 = last.1.max(right),
+                    _ => spans.push((left, right)),
+                }
+            }
+            horizontal.push_band(band.top, band.bottom, spans);
+        }
+        // A pixel survives shrinking if the pixels `dy` rows above and below
+        // it (and every one in between) are all in the region. Growing is
+        // the other way around.
+        let mut ret = horizontal.clone();
+        for n in 1..=dy.unsigned_abs() as i32 {
+            let above = horizontal.offset_by(0, -n);
+            let below = horizontal.offset_by(0, n);
+            ret = if dy > 0 {
+                ret.intersection(&above).intersection(&below)
+            } else {
+                ret.union(&above).union(&below)
+            };
+        }
+        ret
+    }
+    /// Combines two regions a band at a time. `op` is given whether a pixel
+    /// is in each of the regions, and returns whether it's in the result.
+    fn combine(
+        &self,
+        rhs: &Region,
+        op: impl Fn(bool, bool) -> bool,
+    ) -> Region {
+        let mut edges: Vec<i32> = self
+            .bands
+            .iter()
+            .chain(rhs.bands.iter())
+            .flat_map(|band| [band.top, band.bottom])
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+        let mut ret = Region::new();
+        for pair in edges.windows(2) {
+            let spans = combine_spans(
+                self.get_spans(pair[0]),
+                rhs.get_spans(pair[0]),
+                &op,
+            );
+            ret.push_band(pair[0], pair[1], spans);
+        }
+        ret
+    }
+    /// Adds a band to the bottom of the region, merging it with the band
+    /// above if they have the same spans. Bands must be added from top to
+    /// bottom.
+    fn push_band(&mut self, top: i32, bottom: i32, spans: Vec<(i32, i32)>) {
+        if spans.is_empty() || top >= bottom {
+            return;
+        }
+        match self.bands.last_mut() {
+            Some(last) if last.bottom == top && last.spans == spans => {
+                last.bottom = bottom;
+            }
+            _ => self.bands.push(Band { top, bottom, spans }),
+        }
+    }
+}
+
+impl From<Rectangle> for Region {
+    fn from(rectangle: Rectangle) -> Region {
+        let mut ret = Region::new();
+        if !rectangle.is_empty() {
+            ret.push_band(
+                rectangle.top,
+                rectangle.bottom,
+                vec![(rectangle.left, rectangle.right)],
+            );
+        }
+        ret
+    }
+}
+
+/// Combines two sorted lists of spans, as for `Region::combine`.
+fn combine_spans(
+    a: &[(i32, i32)],
+    b: &[(i32, i32)],
+    op: impl Fn(bool, bool) -> bool,
+) -> Vec<(i32, i32)> {
+    let mut edges: Vec<i32> = a
+        .iter()
+        .chain(b.iter())
+        .flat_map(|(l, r)| [*l, *r])
+        .collect();
+    edges.sort_unstable();
+    edges.dedup();
+    let (mut i, mut j) = (0, 0);
+    let mut ret: Vec<(i32, i32)> = vec![];
+    for pair in edges.windows(2) {
+        let (left, right) = (pair[0], pair[1]);
+        while i < a.len() && a[i].1 <= left {
+            i += 1;
+        }
+        while j < b.len() && b[j].1 <= left {
+            j += 1;
+        }
+        let in_a = i < a.len() && a[i].0 <= left;
+        let in_b = j < b.len() && b[j].0 <= left;
+        if !op(in_a, in_b) {
+            continue;
+        }
+        match ret.last_mut() {
+            Some(last) if last.1 == left => last.1 = right,
+            _ => ret.push((left, right)),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn rect(left: i32, top: i32, right: i32, bottom: i32) -> Region {
+        Rectangle {
+            left,
+            top,
+            right,
+            bottom,
+        }
+        .into()
+    }
+    #[test]
+    fn operations() {
+        let a = rect(0, 0, 4, 4);
+        let b = rect(2, 2, 6, 6);
+        let union = a.union(&b);
+        assert_eq!(union.get_bounds(), rect(0, 0, 6, 6).get_bounds());
+        assert_eq!(union.get_rectangles().count(), 3);
+        assert!(union.contains(5, 5) && !union.contains(5, 0));
+        let intersection = a.intersection(&b);
+        assert_eq!(intersection, rect(2, 2, 4, 4));
+        assert_eq!(union.difference(&intersection).xor(&intersection), union);
+        assert_eq!(a.xor(&b), union.difference(&intersection));
+        assert_eq!(a.difference(&a), Region::new());
+        assert!(rect(0, 0, 0, 4).is_empty());
+        // touching rectangles become one
+        assert_eq!(rect(0, 0, 2, 4).union(&rect(2, 0, 4, 4)), a);
+        assert_eq!(rect(0, 0, 4, 2).union(&rect(0, 2, 4, 4)), a);
+        assert_eq!(a.offset_by(2, 2), b);
+        assert_eq!(b.get_spans(3), &[(2, 6)],);
+        assert!(b.get_spans(6).is_empty());
+    }
+    #[test]
+    fn inset() {
+        let a = rect(0, 0, 8, 8);
+        assert_eq!(a.inset_by(2, 1), rect(2, 1, 6, 7));
+        assert_eq!(a.inset_by(-1, -2), rect(-1, -2, 9, 10));
+        assert!(a.inset_by(4, 0).is_empty());
+        // growing closes the gap between two rectangles
+        let two = rect(0, 0, 2, 2).union(&rect(4, 0, 6, 2));
+        assert_eq!(two.inset_by(-1, 0), rect(-1, 0, 7, 2));
+        // shrinking an L removes its thin arm
+        let l = rect(0, 0, 2, 8).union(&rect(0, 6, 8, 8));
+        assert_eq!(l.inset_by(0, 1), rect(0, 1, 2, 7));
+    }
+    #[test]
+    fn bitmaps() {
+        let bitmap = Bitmap::from_bytes(
+            36,
+            3,
+            &[
+                0xF0, 0x00, 0x00, 0x01, 0xFF, //
+                0x00, 0x00, 0x00, 0x00, 0x00, //
+                0x0F, 0xFF, 0xFF, 0xFF, 0x00, //
+            ],
+        );
+        let region = Region::from_bitmap(&bitmap);
+        assert_eq!(region.get_spans(0), &[(0, 4), (31, 36)]);
+        assert!(region.get_spans(1).is_empty());
+        assert_eq!(region.get_spans(2), &[(4, 32)]);
+        // stray bits past the right edge are left out
+        let mut bytes = bitmap.to_bytes();
+        bytes[4] = 0xF0;
+        assert_eq!(region.to_bitmap(36, 3).to_bytes(), bytes);
+    }
+}